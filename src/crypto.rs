@@ -2,7 +2,7 @@ use crate::primitives::{PublicKey, Signature};
 use crate::types::AlgSuite;
 
 /// Errors that can occur during cryptographic operations.
-#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
 pub enum CryptoError {
     #[error("Signature verification failed: invalid signature")]
     InvalidSignature,
@@ -112,4 +112,41 @@ impl CryptoProvider for ConfigurableCryptoProvider {
     ) -> Result<(), CryptoError> {
         self.verification_outcome.clone() // Return the configured verification outcome
     }
-} 
\ No newline at end of file
+}
+
+/// Deterministic, well-mixed 32-byte digest for tests that need distinct
+/// hashes for distinct inputs (e.g. Merkle proofs). Not cryptographic;
+/// signatures always verify.
+#[cfg(feature = "test-utils")]
+#[derive(Debug, Clone, Default)]
+pub struct DigestCryptoProvider;
+
+#[cfg(feature = "test-utils")]
+impl CryptoProvider for DigestCryptoProvider {
+    fn hash(&self, data: &[u8], alg_suite: AlgSuite) -> Result<[u8; 32], CryptoError> {
+        let mut out = [0u8; 32];
+        for (lane, chunk) in out.chunks_mut(8).enumerate() {
+            // FNV-1a per lane, seeded by lane and suite, finished with a splitmix64 avalanche.
+            let mut h = 0xcbf2_9ce4_8422_2325u64 ^ ((lane as u64) << 8 | alg_suite as u64);
+            for byte in data {
+                h = (h ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+            }
+            h ^= data.len() as u64;
+            h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            h ^= h >> 31;
+            chunk.copy_from_slice(&h.to_le_bytes());
+        }
+        Ok(out)
+    }
+
+    fn verify(
+        &self,
+        _data_to_verify: &[u8],
+        _signature: &Signature,
+        _holder_public_key: &PublicKey,
+        _alg_suite: AlgSuite,
+    ) -> Result<(), CryptoError> {
+        Ok(())
+    }
+}
@@ -5,39 +5,319 @@
 // use crate::crypto_placeholder::CryptoError as PlaceholderCryptoError; // Will be removed
 // Removed unused import: use crate::crypto::CryptoError;
 
+use crate::kernel::state_tree::StateRoot;
+use crate::primitives::{ReplicaID, CID};
+use crate::types::RightsMask;
+
 /// Represents errors that can occur during kernel operations, such as command validation or application.
-#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+///
+/// Every variant has a stable numeric `code()` and serialises with serde, so
+/// clients on the other side of a wire protocol can react to it
+/// programmatically. Payloads carry the values that caused the error.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
 pub enum KernelError {
     /// The specified Capability ID was not found in the current state.
-    #[error("Capability not found in state")]
-    CapabilityNotFound,
+    #[error("Capability {0:?} not found in state")]
+    CapabilityNotFound(CID),
     /// The `alg_suite` in the Command does not match the `alg_suite` of the referenced Capability.
-    #[error("Command AlgSuite mismatch with Capability AlgSuite")]
-    AlgorithmSuiteMismatch,
+    #[error("Command AlgSuite {command} does not match Capability AlgSuite {capability}")]
+    AlgorithmSuiteMismatch { command: u8, capability: u8 },
     /// Cryptographic operation failed.
     #[error("Cryptographic operation failed: {0}")]
     Crypto(#[from] crate::crypto::CryptoError),
     /// The referenced Capability does not grant sufficient rights for the Command's payload.
-    #[error("Capability does not grant sufficient rights")]
-    InsufficientRights,
-    /// The Command's proposed `lclock` is invalid (e.g., too old).
-    #[error("Command lclock is invalid")]
-    InvalidCommandLClock,
+    #[error("Capability rights {granted:#010x} do not cover required rights {required:#010x}")]
+    InsufficientRights { granted: RightsMask, required: RightsMask },
+    /// A rights mask carries reserved kernel bits not allocated by the kernel's rights algebra version.
+    #[error("Rights mask uses unallocated reserved bits {0:#010x}")]
+    UnallocatedRightsBits(u32),
+    /// The rights algebra could not be configured.
+    #[error("Rights algebra error: {0}")]
+    Rights(#[from] crate::rights::RightsError),
+    /// The Command's proposed `lclock` is behind the replica's Lamport clock.
+    #[error("Command lclock {proposed} is behind local lclock {local_lc}")]
+    InvalidCommandLClock { proposed: u64, local_lc: u64 },
+    /// The replica's Lamport clock has reached `u64::MAX` (§7.1.5).
+    #[error("Replica has reached maximum Lamport clock value and cannot process further commands")]
+    LamportClockExhausted,
+    /// The replica handed off to a successor and no longer applies commands.
+    #[error("Replica retired; commands must go to successor {successor:?}")]
+    ReplicaRetired { successor: ReplicaID },
+    /// A handoff record does not name this replica or does not match its snapshot.
+    #[error("Invalid handoff: {0}")]
+    InvalidHandoff(String),
+    /// An incoming event does not extend its replica's hash chain: the replica
+    /// omitted, reordered or forked its own events, or one was not delivered.
+    #[error("Event from replica {replica:?} follows {found:?}, expected {expected:?}")]
+    BrokenEventChain { replica: ReplicaID, expected: CID, found: Option<CID> },
+    /// An incoming event conflicts with an earlier event from the same replica
+    /// at the same Lamport time or vector-clock entry.
+    #[error("Replica {replica:?} equivocated: event {second:?} conflicts with {first:?}")]
+    Equivocation { replica: ReplicaID, first: CID, second: CID },
+    /// Events from the replica are refused after it equivocated.
+    #[error("Replica {0:?} is quarantined")]
+    ReplicaQuarantined(ReplicaID),
+    /// An equivocation proof does not show a conflict.
+    #[error("Invalid equivocation proof: {0}")]
+    InvalidEquivocationProof(String),
+    /// An incoming event claims a clock epoch no verified handoff admitted its replica to.
+    #[error("Replica {replica:?} has not been handed epoch {epoch}")]
+    UnadmittedEpoch { replica: ReplicaID, epoch: u32 },
+    /// An incoming event's id does not match the id recomputed from its contents.
+    #[error("Event {expected:?} does not match its contents (computed {computed:?})")]
+    EventIdMismatch { expected: CID, computed: CID },
     /// The `expiry_lc` of a Capability has been reached or surpassed.
-    #[error("Capability has expired")]
-    CapabilityExpired,
+    #[error("Capability expired at lclock {expiry_lc} (local lclock {local_lc})")]
+    CapabilityExpired { expiry_lc: u64, local_lc: u64 },
+    /// The Capability's `kind` has no registered overlay validator and the kernel policy rejects unknown kinds.
+    #[error("Capability kind {0} is not recognised by this kernel")]
+    UnknownCapabilityKind(u16),
+    /// The overlay validator registered for the Capability's `kind` rejected the Command.
+    #[error("Capability overlay kind {0} rejected command: {1}")]
+    OverlayRejected(u16, String),
+    /// A caveat attached to the Capability does not hold for the Command.
+    #[error("Capability caveat not satisfied: {0}")]
+    CaveatViolated(String),
+    /// A delegated Capability is not a valid attenuation of its parent.
+    #[error("Invalid delegation: {0}")]
+    InvalidDelegation(String),
+    /// A use-limited Capability has already authorized its maximum number of commands.
+    #[error("Capability has no remaining uses")]
+    CapabilityUsesExhausted,
+    /// An overlay validator was registered for a capability kind the kernel reserves.
+    #[error("Capability kind {0} is reserved")]
+    ReservedCapabilityKind(u16),
     /// An invariant was violated during processing (e.g., by the delta from runtime).
     #[error("Kernel invariant violation: {0}")]
-    InvariantViolation(String),
+    InvariantViolation(Invariant),
     /// An error occurred during the execution of the command-specific runtime logic.
     #[error("Runtime error: {0}")]
-    RuntimeError(String),
+    RuntimeError(ErrorCause),
+    /// No event with this id is in the local event log.
+    #[error("Event {0:?} not found in the event log")]
+    EventNotFound(CID),
+    /// A version needed to reconstruct past state has been pruned or was never recorded.
+    #[error("Version of entity {entity:?} at lclock {lclock} is not retained")]
+    HistoryUnavailable { entity: CID, lclock: u64 },
+    /// Replaying the event log contradicted the recorded entity versions.
+    #[error("Replay of event {event:?} failed: {invariant}")]
+    ReplayMismatch { event: CID, invariant: Box<Invariant> },
+    /// Events up to this lclock were compacted into a snapshot and can no longer be replayed.
+    #[error("Events up to lclock {0} have been compacted")]
+    LogCompacted(u64),
+    /// A snapshot's contents do not match its content id or the state it claims to capture.
+    #[error("Snapshot {expected:?} does not match its contents (computed {computed:?})")]
+    SnapshotMismatch { expected: CID, computed: CID },
+    /// An event replayed on top of a snapshot does not continue it.
+    #[error("Event {event:?} cannot follow the snapshot: {reason}")]
+    InvalidSnapshotTail { event: CID, reason: String },
+    /// A Merkle proof or root was requested for a tree size or leaf the log cannot serve.
+    #[error("Merkle proof unavailable: {0}")]
+    ProofUnavailable(String),
+    /// State restored from a snapshot does not hash to the state root the snapshot records.
+    #[error("Snapshot state root {expected:?} does not match the restored state ({computed:?})")]
+    StateRootMismatch { expected: Box<StateRoot>, computed: Box<StateRoot> },
+    /// A command payload could not be encoded, decoded or turned into signed bytes.
+    #[error("Command payload error: {0}")]
+    Command(ErrorCause),
+    /// The storage backend failed to persist or load the event log.
+    #[error("Storage error: {0}")]
+    Storage(ErrorCause),
     /// A general or otherwise unspecified error.
     #[error("Kernel error: {0}")]
     Other(String),
 }
 
+impl KernelError {
+    /// Stable numeric code identifying the error variant, e.g. for rejection receipts.
+    ///
+    /// Codes are grouped by concern: `1xx` authorization, `2xx` clocks and
+    /// replication, `3xx` state invariants and runtime, `4xx` command
+    /// payloads, `5xx` storage, `9xx` other. A variant's code never changes
+    /// and codes are never reused.
+    pub fn code(&self) -> u16 {
+        match self {
+            KernelError::CapabilityNotFound(_) => 100,
+            KernelError::AlgorithmSuiteMismatch { .. } => 101,
+            KernelError::Crypto(_) => 102,
+            KernelError::InsufficientRights { .. } => 103,
+            KernelError::UnallocatedRightsBits(_) => 104,
+            KernelError::Rights(_) => 105,
+            KernelError::CapabilityExpired { .. } => 106,
+            KernelError::UnknownCapabilityKind(_) => 107,
+            KernelError::OverlayRejected(..) => 108,
+            KernelError::CaveatViolated(_) => 109,
+            KernelError::InvalidDelegation(_) => 110,
+            KernelError::CapabilityUsesExhausted => 111,
+            KernelError::ReservedCapabilityKind(_) => 112,
+            KernelError::InvalidCommandLClock { .. } => 200,
+            KernelError::LamportClockExhausted => 201,
+            KernelError::ReplicaRetired { .. } => 202,
+            KernelError::InvalidHandoff(_) => 203,
+            KernelError::BrokenEventChain { .. } => 204,
+            KernelError::Equivocation { .. } => 205,
+            KernelError::ReplicaQuarantined(_) => 206,
+            KernelError::InvalidEquivocationProof(_) => 207,
+            KernelError::UnadmittedEpoch { .. } => 208,
+            KernelError::EventIdMismatch { .. } => 209,
+            KernelError::InvariantViolation(_) => 300,
+            KernelError::RuntimeError(_) => 301,
+            KernelError::EventNotFound(_) => 302,
+            KernelError::HistoryUnavailable { .. } => 303,
+            KernelError::ReplayMismatch { .. } => 304,
+            KernelError::LogCompacted(_) => 305,
+            KernelError::SnapshotMismatch { .. } => 306,
+            KernelError::InvalidSnapshotTail { .. } => 307,
+            KernelError::ProofUnavailable(_) => 308,
+            KernelError::StateRootMismatch { .. } => 309,
+            KernelError::Command(_) => 400,
+            KernelError::Storage(_) => 500,
+            KernelError::Other(_) => 900,
+        }
+    }
+
+    /// Stable name of the error variant, e.g. for metric labels.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KernelError::CapabilityNotFound(_) => "CapabilityNotFound",
+            KernelError::AlgorithmSuiteMismatch { .. } => "AlgorithmSuiteMismatch",
+            KernelError::Crypto(_) => "Crypto",
+            KernelError::InsufficientRights { .. } => "InsufficientRights",
+            KernelError::UnallocatedRightsBits(_) => "UnallocatedRightsBits",
+            KernelError::Rights(_) => "Rights",
+            KernelError::CapabilityExpired { .. } => "CapabilityExpired",
+            KernelError::UnknownCapabilityKind(_) => "UnknownCapabilityKind",
+            KernelError::OverlayRejected(..) => "OverlayRejected",
+            KernelError::CaveatViolated(_) => "CaveatViolated",
+            KernelError::InvalidDelegation(_) => "InvalidDelegation",
+            KernelError::CapabilityUsesExhausted => "CapabilityUsesExhausted",
+            KernelError::ReservedCapabilityKind(_) => "ReservedCapabilityKind",
+            KernelError::InvalidCommandLClock { .. } => "InvalidCommandLClock",
+            KernelError::LamportClockExhausted => "LamportClockExhausted",
+            KernelError::ReplicaRetired { .. } => "ReplicaRetired",
+            KernelError::InvalidHandoff(_) => "InvalidHandoff",
+            KernelError::BrokenEventChain { .. } => "BrokenEventChain",
+            KernelError::Equivocation { .. } => "Equivocation",
+            KernelError::ReplicaQuarantined(_) => "ReplicaQuarantined",
+            KernelError::InvalidEquivocationProof(_) => "InvalidEquivocationProof",
+            KernelError::UnadmittedEpoch { .. } => "UnadmittedEpoch",
+            KernelError::EventIdMismatch { .. } => "EventIdMismatch",
+            KernelError::InvariantViolation(_) => "InvariantViolation",
+            KernelError::RuntimeError(_) => "RuntimeError",
+            KernelError::EventNotFound(_) => "EventNotFound",
+            KernelError::HistoryUnavailable { .. } => "HistoryUnavailable",
+            KernelError::ReplayMismatch { .. } => "ReplayMismatch",
+            KernelError::LogCompacted(_) => "LogCompacted",
+            KernelError::SnapshotMismatch { .. } => "SnapshotMismatch",
+            KernelError::InvalidSnapshotTail { .. } => "InvalidSnapshotTail",
+            KernelError::ProofUnavailable(_) => "ProofUnavailable",
+            KernelError::StateRootMismatch { .. } => "StateRootMismatch",
+            KernelError::Command(_) => "Command",
+            KernelError::Storage(_) => "Storage",
+            KernelError::Other(_) => "Other",
+        }
+    }
+
+    /// Wraps an error raised by a `Runtime`, preserving its cause chain.
+    pub fn runtime(error: &(dyn std::error::Error + 'static)) -> Self {
+        KernelError::RuntimeError(ErrorCause::from_error(error))
+    }
+}
+
+impl From<crate::command_traits::CommandTraitError> for KernelError {
+    fn from(error: crate::command_traits::CommandTraitError) -> Self {
+        KernelError::Command(ErrorCause::from_error(&error))
+    }
+}
+
+impl From<crate::kernel::storage::StorageError> for KernelError {
+    fn from(error: crate::kernel::storage::StorageError) -> Self {
+        KernelError::Storage(ErrorCause::from_error(&error))
+    }
+}
+
+/// The kernel invariant a `StateDelta` violated (`append_delta`, Kernel Spec §4).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+pub enum Invariant {
+    /// A new entity reuses a CID already present in state.
+    #[error("New entity CID {0:?} already exists in state")]
+    EntityAlreadyExists(CID),
+    /// An updated entity is not present in state.
+    #[error("Updated entity with CID {0:?} not found in state")]
+    EntityNotFound(CID),
+    /// An updated entity's version is not exactly one above its current version.
+    #[error("Entity version monotonicity violated for CID {entity:?}: {current} -> {proposed}")]
+    VersionNotMonotonic { entity: CID, current: u64, proposed: u64 },
+    /// The delta creates, updates or deletes an entity that has been tombstoned.
+    #[error("Entity {0:?} has been deleted")]
+    EntityTombstoned(CID),
+    /// An entity references a parent that has not been observed.
+    #[error("Parent {parent:?} of entity {entity:?} has not been observed")]
+    ParentNotFound { entity: CID, parent: CID },
+    /// An entity is older than its parent (spec §2.1 invariant 3: `parent.lclock ≤ child.lclock`).
+    #[error("Entity {entity:?} lclock {entity_lclock} precedes parent {parent:?} lclock {parent_lclock}")]
+    ParentCausalBound { entity: CID, parent: CID, parent_lclock: u64, entity_lclock: u64 },
+    /// The parent chain of an entity loops.
+    #[error("Parent chain of entity {0:?} contains a cycle")]
+    ParentCycle(CID),
+    /// An entity in the delta is not stamped with the event's lclock.
+    #[error("Entity {entity:?} lclock {entity_lclock} must equal event lclock {event_lclock}")]
+    LClockMismatch { entity: CID, entity_lclock: u64, event_lclock: u64 },
+}
+
+/// A serialisable snapshot of a foreign error and its `source()` chain.
+///
+/// Runtime and payload errors are arbitrary `std::error::Error` types that are
+/// neither `Clone` nor serde-aware; the kernel keeps their rendered messages,
+/// outermost first, so the cause chain survives crossing a wire protocol.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ErrorCause {
+    /// `Display` of the error itself.
+    pub message: String,
+    /// `Display` of each successive `source()`, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl ErrorCause {
+    /// A cause with a message and no further chain.
+    pub fn msg(message: impl Into<String>) -> Self {
+        ErrorCause { message: message.into(), causes: Vec::new() }
+    }
+
+    /// Captures `error` and walks its `source()` chain.
+    pub fn from_error(error: &(dyn std::error::Error + 'static)) -> Self {
+        let mut causes = Vec::new();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        ErrorCause { message: error.to_string(), causes }
+    }
+}
+
+impl std::fmt::Display for ErrorCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        for cause in &self.causes {
+            write!(f, ": {}", cause)?;
+        }
+        Ok(())
+    }
+}
+
 /* Removed old CryptoError definition
 /// Error type for cryptographic operations.
 /// This is now defined in `crypto.rs`.
-*/
\ No newline at end of file
+*/
+
+/// Failure of `Kernel::apply_batch`: the position of the first command that was
+/// rejected and why. No command of the batch has been committed.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Batch command {index} failed: {error}")]
+pub struct BatchError {
+    /// Index of the failing command within the batch.
+    pub index: usize,
+    /// The error returned for that command.
+    pub error: KernelError,
+}
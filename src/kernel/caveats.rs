@@ -0,0 +1,151 @@
+//! Capability caveat evaluation and delegation rules.
+//!
+//! Caveats (see `primitives::Caveat`) are macaroon-style conditions attached to a
+//! capability. They only ever narrow what a capability admits: every caveat must
+//! hold for a command to pass `Kernel::validate_command`, and a delegated child
+//! capability inherits all caveats of its parent and may only add more. An
+//! `UnderParent` caveat also bounds every entity the command's delta writes.
+
+use crate::command_traits::EncodedCmd;
+use crate::error::KernelError;
+use crate::kernel::core::{StateDelta, SystemState};
+use crate::primitives::{Capability, Caveat, Command, CID};
+use crate::rights::{self, RightsAlgebra};
+
+/// Evaluates every caveat of `capability` against `command` and the current state.
+///
+/// Evaluation is deterministic and stops at the first caveat that does not hold.
+pub fn check<C: EncodedCmd>(
+    capability: &Capability,
+    command: &Command<C>,
+    state: &SystemState,
+    current_lc: u64,
+) -> Result<(), KernelError> {
+    for caveat in &capability.caveats {
+        if !holds(caveat, capability, command, state, current_lc) {
+            return Err(KernelError::CaveatViolated(format!("{:?}", caveat)));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a single `caveat` of `capability` holds for `command`.
+pub fn holds<C: EncodedCmd>(
+    caveat: &Caveat,
+    capability: &Capability,
+    command: &Command<C>,
+    state: &SystemState,
+    current_lc: u64,
+) -> bool {
+    match caveat {
+        Caveat::BeforeLc(lc) => current_lc < *lc,
+        Caveat::UnderParent(parent) => descends_from(state, None, &capability.target_entity, parent),
+        Caveat::Opcode(opcode) => command.payload.encode().first() == Some(opcode),
+        Caveat::FromReplica(replica) => command.replica == *replica,
+    }
+}
+
+/// Checks that every entity `delta` creates, updates or deletes lies under each
+/// `UnderParent` caveat of `capability`, not just the capability's target.
+///
+/// Entities are placed by the parent links they will have once `delta` is
+/// applied, so new entities may hang off other entities in the same delta.
+pub fn check_delta(capability: &Capability, delta: &StateDelta, state: &SystemState) -> Result<(), KernelError> {
+    for caveat in &capability.caveats {
+        let Caveat::UnderParent(ancestor) = caveat else { continue };
+        let mut written = delta
+            .new_entities
+            .iter()
+            .chain(&delta.updated_entities)
+            .map(|entity| &entity.header.id)
+            .chain(delta.deleted_entities.iter().map(|tombstone| &tombstone.id));
+        if let Some(outside) = written.find(|id| !descends_from(state, Some(delta), id, ancestor)) {
+            return Err(KernelError::CaveatViolated(format!("{:?} does not cover entity {:?}", caveat, outside)));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `entity` is `ancestor` or reaches it by following parent links,
+/// taking those written by `delta` over the ones observed in state.
+///
+/// The walk is bounded by the number of entities, so a malformed (cyclic)
+/// parent chain terminates with `false`.
+fn descends_from(state: &SystemState, delta: Option<&StateDelta>, entity: &CID, ancestor: &CID) -> bool {
+    let written = |id: &CID| {
+        delta
+            .and_then(|d| d.new_entities.iter().chain(&d.updated_entities).find(|e| e.header.id == *id))
+            .map(|e| e.header.parent)
+    };
+    let bound = state.entities.len() + delta.map_or(0, |d| d.new_entities.len());
+    let mut current = *entity;
+    for _ in 0..=bound {
+        if current == *ancestor {
+            return true;
+        }
+        let parent = written(&current).unwrap_or_else(|| state.entities.get(&current).and_then(|e| e.header.parent));
+        match parent {
+            Some(parent) => current = parent,
+            None => return false,
+        }
+    }
+    false
+}
+
+/// Returns the caveats a delegated capability carries: the parent's caveats
+/// plus `extra`, in canonical order.
+pub fn attenuate(parent: &[Caveat], extra: &[Caveat]) -> Vec<Caveat> {
+    let mut combined = parent.to_vec();
+    combined.extend_from_slice(extra);
+    Caveat::canonical(&combined)
+}
+
+/// Whether `child` keeps every caveat of `parent`, i.e. only narrows it.
+pub fn is_narrowing(parent: &[Caveat], child: &[Caveat]) -> bool {
+    parent.iter().all(|caveat| child.contains(caveat))
+}
+
+/// Checks that `child` is a valid delegation of `parent` (`docs/rights.md` §4):
+/// the parent holds `DELEGATE`, the child keeps the parent's kind, its rights
+/// are a subset of the parent's canonicalised rights, its expiry is no later,
+/// it inherits every caveat, and the parent is not use-limited.
+pub fn check_delegation(
+    algebra: &dyn RightsAlgebra,
+    parent: &Capability,
+    child: &Capability,
+) -> Result<(), KernelError> {
+    if !algebra.sufficient(parent.rights, rights::core::DELEGATE) {
+        return Err(KernelError::InvalidDelegation("parent capability lacks DELEGATE".into()));
+    }
+    if child.alg_suite != parent.alg_suite {
+        return Err(KernelError::InvalidDelegation("algorithm suite differs from parent".into()));
+    }
+    // A different kind would escape the parent's overlay validator.
+    if child.kind != parent.kind {
+        return Err(KernelError::InvalidDelegation("kind differs from parent".into()));
+    }
+    if child.target_entity != parent.target_entity {
+        return Err(KernelError::InvalidDelegation("target entity differs from parent".into()));
+    }
+    if !algebra.sufficient(parent.rights, child.rights) {
+        return Err(KernelError::InvalidDelegation("rights exceed those of the parent".into()));
+    }
+    match (parent.expiry_lc, child.expiry_lc) {
+        (Some(_), None) => {
+            return Err(KernelError::InvalidDelegation("child must expire no later than parent".into()));
+        }
+        (Some(p), Some(c)) if c > p => {
+            return Err(KernelError::InvalidDelegation("child must expire no later than parent".into()));
+        }
+        _ => {}
+    }
+    // Each capability counts its own uses, so a child could never draw down
+    // its parent's limit.
+    if parent.max_uses.is_some() {
+        return Err(KernelError::InvalidDelegation("use-limited capabilities cannot be delegated".into()));
+    }
+    if !is_narrowing(&parent.caveats, &child.caveats) {
+        return Err(KernelError::InvalidDelegation("child drops caveats of the parent".into()));
+    }
+    Ok(())
+}
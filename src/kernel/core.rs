@@ -5,42 +5,126 @@
 //! as part of the repository re-organisation (see PROJECT_ROADMAP.md Phase Refactor).
 
 // Primitive types from crate::primitives
-use crate::primitives::{VClock, CID, ReplicaID, Event, Entity, Capability, Command, CidBytes};
+use crate::primitives::{VClock, CID, ReplicaID, Event, Entity, EntityHeader, Capability, Command, CidBytes, PublicKey};
 
 // Shared types from crate::types
 use crate::types::AlgSuite; // RightsMask is not directly used here but good for context
 
 // Traits and specific types from new modules
 use crate::command_traits::EncodedCmd;
-use crate::crypto::{CryptoProvider}; // Removed CryptoError import
+use crate::crypto::{CryptoError, CryptoProvider};
 
-use crate::error::KernelError;
-use std::collections::{HashMap}; // For SystemState and additional_fields in Event
-use crate::rights; // Rights algebra module - uses RightsMask from types
+use crate::error::{BatchError, ErrorCause, Invariant, KernelError};
+use std::collections::HashMap; // For SystemState and additional_fields in Event
+use crate::rights::{RightsAlgebra, RightsRegistry}; // Rights algebra module - uses RightsMask from types
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 // use crate::time::vector as vector_clock; // No longer needed
 use crate::kernel::runtime::{Runtime, DefaultRuntime};
+use crate::kernel::overlay::OverlayRegistry;
+use crate::kernel::caveats;
+use crate::kernel::history::{EntityVersion, RetentionPolicy, VersionStore};
+use crate::kernel::merkle::{MerkleLog, PendingAppend};
+use crate::kernel::state_tree::StateTree;
+use crate::kernel::equivocation::EquivocationLog;
+use crate::kernel::storage::{self, LogRecord, Storage};
+use crate::kernel::metrics::KernelMetrics;
+use crate::kernel::observer::{CapabilityChange, KernelObserver};
+use crate::kernel::explain::{Check, Explanation, Step};
+use crate::kernel::receipts::{RejectionLog, RejectionReceipt};
+use crate::kernel::snapshot::Snapshot;
+
+/// Tags of the optional event fields that follow the reserved bytes in an
+/// event's hash input, in the order they are appended.
+const EVENT_FIELD_DELETED: u8 = 1;
+const EVENT_FIELD_CONSUMED_CAPABILITY: u8 = 2;
+const EVENT_FIELD_EPOCH: u8 = 3;
+const EVENT_FIELD_PREV_EVENT: u8 = 4;
 
 /// Represents the changes to the system state resulting from a command.
 /// This is the `delta` referred to in the kernel specification.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct StateDelta {
     /// New entities created by the command, with bodies in serialized form.
     pub new_entities: Vec<Entity<Vec<u8>>>,
     /// Entities updated by the command, with bodies in serialized form.
     pub updated_entities: Vec<Entity<Vec<u8>>>,
+    /// Tombstones for entities deleted by the command. Like updates, each header
+    /// must carry the entity's next version; its lclock is assigned by the kernel.
+    pub deleted_entities: Vec<EntityHeader>,
+}
+
+/// The outcome of `Kernel::simulate`: what `apply` would commit for a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    /// The event `apply` would append, including its id and Lamport clock.
+    pub event: Event,
+    /// The delta the runtime produced, with entity lclocks already assigned.
+    pub delta: StateDelta,
+}
+
+/// A command committed by `apply`, kept until observers have been notified.
+struct Committed {
+    event: Event,
+    delta: StateDelta,
+    /// Use count of the consumed capability right after this commit.
+    capability_uses: Option<u64>,
+    /// Wall-clock time spent staging and committing.
+    latency: Duration,
+}
+
+/// Kernel state as it was before an `apply_batch`, plus the prior values of
+/// only the entities and capability counters its commands have touched, so a
+/// failed batch is undone in time proportional to what it wrote.
+struct BatchUndo {
+    local_lc: u64,
+    local_vc: VClock,
+    chain_head: Option<CID>,
+    event_log_len: usize,
+    log_size: u64,
+    state_tree: StateTree,
+    entities: HashMap<CID, SavedEntity>,
+    capability_uses: HashMap<CID, Option<u64>>,
+}
+
+/// An entity's slots in Σ and its retained history, as saved by `BatchUndo`.
+struct SavedEntity {
+    live: Option<Arc<Entity<Vec<u8>>>>,
+    tombstone: Option<EntityHeader>,
+    versions: Vec<EntityVersion>,
+}
+
+/// What `append_delta` does with an entity whose `parent` is not in Σ or the delta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnobservedParentPolicy {
+    /// Refuse the delta with `Invariant::ParentNotFound` (conservative default).
+    #[default]
+    Reject,
+    /// Accept it: the parent may exist on a replica whose events have not been
+    /// observed yet (spec §2.1 invariant 3 only binds observed parents).
+    Allow,
 }
 
 /// Represents the authoritative state (Σ) of the Amulet kernel.
 /// This includes the append-only event log and materialised views of entities and capabilities.
-#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct SystemState {
     /// Materialised view of capabilities, mapping CID → Capability.
     pub capabilities: HashMap<CID, Capability>,
     /// Entities are stored with their bodies in serialized Vec<u8> form.
     /// Deserialization happens on-demand within `runtime` or other accessors.
-    pub entities: HashMap<CID, Entity<Vec<u8>>>,
+    /// Each version is behind an `Arc` shared with the `VersionStore`.
+    pub entities: HashMap<CID, Arc<Entity<Vec<u8>>>>,
     /// Append-only log of events.
     pub event_log: Vec<Event>,
+    /// Number of commands each use-limited capability has authorized, mapping CID → uses.
+    /// Advanced only by events carrying `consumed_capability`, so it replicates with the log.
+    #[serde(default)]
+    pub capability_uses: HashMap<CID, u64>,
+    /// Tombstones of deleted entities, mapping CID → final header. A tombstoned
+    /// CID can never be updated or re-created.
+    #[serde(default)]
+    pub tombstones: HashMap<CID, EntityHeader>,
     // Potentially other materialised views or state components.
 }
 
@@ -49,14 +133,52 @@ pub struct SystemState {
 pub struct Kernel<CP: CryptoProvider + Clone, R: Runtime<CP> + Clone + std::fmt::Debug> {
     /// The kernel's current local Lamport clock.
     pub local_lc: u64,
+    /// Clock epoch `local_lc` and `local_vc` belong to (§7.1.5). Starts at 0.
+    pub epoch: u32,
     /// The kernel's current local Vector clock. Now mandatory.
     pub local_vc: VClock,
     /// The authoritative state of the system.
     pub state: SystemState,
     /// The ReplicaID of this kernel instance.
     pub replica_id: ReplicaID,
+    /// Rights algebra (kernel bit version plus domain overlays) used by `rights_sufficient`.
+    pub rights: Arc<dyn RightsAlgebra>,
+    /// Validators for capability `kind` overlays, consulted by `validate_command`.
+    pub overlays: OverlayRegistry,
+    /// Optional log of receipts for commands refused by `apply`. Disabled when `None`.
+    pub rejections: Option<RejectionLog>,
+    /// Every entity version written by `append_delta`, for point-in-time reads.
+    pub history: VersionStore,
+    /// Merkle accumulator over the ids of every event this replica committed.
+    pub log_tree: MerkleLog,
+    /// Sparse Merkle commitments to the entities and capabilities in `state`.
+    pub state_tree: StateTree,
+    /// Whether entities may reference parents this replica has not observed.
+    pub unobserved_parents: UnobservedParentPolicy,
+    /// Snapshot the event log was compacted into, if any. Replay starts from it.
+    pub base_snapshot: Option<Arc<Snapshot>>,
+    /// Successor this replica handed off to, if retired. A retired replica refuses commands.
+    pub successor: Option<ReplicaID>,
+    /// Id of the last event this replica committed; the next one chains to it.
+    pub chain_head: Option<CID>,
+    /// Id of the last event merged from each other replica, checked against
+    /// the `prev_event` of the next one. Tracking starts at the first event merged.
+    /// Since only the next event of each chain is accepted, every merged event
+    /// advances `capability_uses` at most once.
+    pub replica_heads: HashMap<ReplicaID, CID>,
+    /// Clock epoch of each successor admitted by `accept_handoff`. Events from
+    /// any other replica must be in an epoch this replica has reached.
+    pub replica_epochs: HashMap<ReplicaID, u32>,
+    /// Recently merged events, equivocation proofs and quarantined replicas.
+    pub equivocations: EquivocationLog,
+    /// Observers notified after every commit or rejection.
+    observers: Vec<Arc<dyn KernelObserver>>,
+    /// Optional metrics registry shared with the host.
+    metrics: Option<Arc<KernelMetrics>>,
+    /// Optional durable event log, shared by clones of the kernel.
+    pub(crate) storage: Option<Arc<Mutex<dyn Storage>>>,
     pub(crate) runtime: R, // Made pub(crate) for test access
-    crypto_provider: CP, // Store the actual crypto provider instance
+    pub(crate) crypto_provider: CP, // Store the actual crypto provider instance; pub(crate) for test access
 }
 
 impl<CP, R> Kernel<CP, R>
@@ -68,23 +190,172 @@ where
     pub fn new(replica_id: ReplicaID, runtime: R, crypto_provider: CP) -> Self {
         Kernel {
             local_lc: 0,
+            epoch: 0,
             local_vc: VClock::default(),
             state: SystemState::default(),
             replica_id,
+            rights: Arc::new(RightsRegistry::default()),
+            overlays: OverlayRegistry::default(),
+            rejections: None,
+            history: VersionStore::default(),
+            log_tree: MerkleLog::default(),
+            state_tree: StateTree::default(),
+            unobserved_parents: UnobservedParentPolicy::default(),
+            base_snapshot: None,
+            successor: None,
+            chain_head: None,
+            replica_heads: HashMap::new(),
+            replica_epochs: HashMap::new(),
+            equivocations: EquivocationLog::default(),
+            observers: Vec::new(),
+            metrics: None,
+            storage: None,
             runtime,
             crypto_provider, // Store it
         }
     }
 
+    /// Pins the rights algebra version this replica enforces.
+    ///
+    /// Older versions conservatively refuse masks carrying rights they do not know.
+    pub fn with_rights_version(self, version: u16) -> Result<Self, KernelError> {
+        Ok(self.with_rights_algebra(RightsRegistry::with_version(version)?))
+    }
+
+    /// Replaces the rights algebra, e.g. with a `RightsRegistry` carrying domain overlays.
+    pub fn with_rights_algebra<A: RightsAlgebra>(mut self, algebra: A) -> Self {
+        self.rights = Arc::new(algebra);
+        self
+    }
+
+    /// Replaces the kernel's overlay registry.
+    pub fn with_overlays(mut self, overlays: OverlayRegistry) -> Self {
+        self.overlays = overlays;
+        self
+    }
+
     /// Generates a Content ID (CID) for the given data using the kernel's crypto provider.
-    fn generate_cid(&self, data: &[u8], alg_suite_tag: u8) -> Result<CID, KernelError> {
+    pub(crate) fn generate_cid(&self, data: &[u8], alg_suite_tag: u8) -> Result<CID, KernelError> {
         let crypto_alg_suite = AlgSuite::try_from(alg_suite_tag)
-            .map_err(|e| KernelError::Other(format!("Invalid AlgSuite tag: {}", e)))?;
+            .map_err(|_| KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(alg_suite_tag)))?;
         self.crypto_provider.hash(data, crypto_alg_suite) // Use self.crypto_provider
             .map_err(KernelError::Crypto) 
             .map(CidBytes) 
     }
 
+    /// Sets how many versions of each entity `history` retains.
+    pub fn with_version_retention(mut self, retention: RetentionPolicy) -> Self {
+        self.history.set_retention(retention);
+        self
+    }
+
+    /// Hashes the event log accumulator under `alg_suite` (default `CLASSIC`).
+    /// Must be set before the first event is committed.
+    pub fn with_log_alg_suite(mut self, alg_suite: AlgSuite) -> Self {
+        self.log_tree = MerkleLog::new(alg_suite);
+        self
+    }
+
+    /// Hashes the state commitments under `alg_suite` (default `CLASSIC`).
+    /// Σ placed into the kernel beforehand needs `rebuild_state_tree`.
+    pub fn with_state_alg_suite(mut self, alg_suite: AlgSuite) -> Self {
+        self.state_tree = StateTree::new(alg_suite);
+        self
+    }
+
+    /// Sets the policy for entities whose parent has not been observed.
+    pub fn with_unobserved_parent_policy(mut self, policy: UnobservedParentPolicy) -> Self {
+        self.unobserved_parents = policy;
+        self
+    }
+
+    /// Replaces the equivocation log, e.g. to quarantine equivocating replicas.
+    pub fn with_equivocation_log(mut self, log: EquivocationLog) -> Self {
+        self.equivocations = log;
+        self
+    }
+
+    /// Persists every committed and merged event to `storage`. Call `recover`
+    /// afterwards to replay what it already holds.
+    pub fn with_storage<S: Storage + 'static>(mut self, storage: S) -> Self {
+        self.storage = Some(Arc::new(Mutex::new(storage)));
+        self
+    }
+
+    /// Enables rejection receipts, recording into `log`.
+    pub fn with_rejection_log(mut self, log: RejectionLog) -> Self {
+        self.rejections = Some(log);
+        self
+    }
+
+    /// Registers `observer`, to be notified after commits and rejections.
+    pub fn with_observer<O: KernelObserver>(mut self, observer: O) -> Self {
+        self.add_observer(Arc::new(observer));
+        self
+    }
+
+    /// Registers an already shared observer.
+    pub fn add_observer(&mut self, observer: Arc<dyn KernelObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Reports kernel metrics into `metrics`, which the host keeps a handle on.
+    pub fn with_metrics(mut self, metrics: Arc<KernelMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self.report_event_log_length();
+        self
+    }
+
+    /// The metrics registry this kernel reports into, if any.
+    pub fn metrics(&self) -> Option<&Arc<KernelMetrics>> {
+        self.metrics.as_ref()
+    }
+
+    /// Verifies the replica signature on a rejection receipt.
+    pub fn verify_receipt(
+        &self,
+        receipt: &RejectionReceipt,
+        replica_key: &PublicKey,
+        alg_suite: AlgSuite,
+    ) -> Result<(), KernelError> {
+        let signature = receipt
+            .signature
+            .as_ref()
+            .ok_or(KernelError::Crypto(CryptoError::InvalidSignature))?;
+        self.crypto_provider
+            .verify(&receipt.signing_bytes(), signature, replica_key, alg_suite)
+            .map_err(KernelError::Crypto)
+    }
+
+    /// Derives the content id of `capability` from its fields, including its caveats.
+    pub fn capability_id(&self, capability: &Capability) -> Result<CID, KernelError> {
+        self.generate_cid(&capability.id_hash_input(), capability.alg_suite)
+    }
+
+    /// Recomputes the content id of `event` from its fields.
+    pub fn event_id(&self, event: &Event) -> Result<CID, KernelError> {
+        let input = self.get_event_hash_input(
+            &event.caused_by,
+            event.lclock,
+            &event.replica,
+            event.alg_suite,
+            &event.new_entities,
+            &event.updated_entities,
+            &event.deleted_entities,
+            event.consumed_capability.as_ref(),
+            &event.vclock,
+            &event.reserved,
+            event.epoch,
+            event.prev_event.as_ref(),
+        );
+        self.generate_cid(&input, event.alg_suite)
+    }
+
+    /// Checks that `child` is a valid delegation of `parent` under this kernel's rights algebra.
+    pub fn check_delegation(&self, parent: &Capability, child: &Capability) -> Result<(), KernelError> {
+        caveats::check_delegation(self.rights.as_ref(), parent, child)
+    }
+
     /// Appends the identity fields of an event to a byte vector for digest calculation.
     /// These fields include the command that caused the event, the event's Lamport clock,
     /// the ID of the replica that generated the event, and the algorithm suite used.
@@ -131,6 +402,7 @@ where
 
     /// Helper to deterministically serialise event fields for CID generation.
     /// This function now orchestrates calls to more specific append helpers.
+    #[allow(clippy::too_many_arguments)]
     fn get_event_hash_input(
         &self, // Remains to potentially access self.crypto_provider if needed, though not currently used here
         caused_by_command_id: &CID,
@@ -139,8 +411,12 @@ where
         event_alg_suite_tag: u8, // Changed from AlgSuite to u8
         new_entities_cids: &[CID],
         updated_entities_cids: &[CID],
+        deleted_entities_cids: &[CID],
+        consumed_capability: Option<&CID>,
         vector_clock: &VClock,
         reserved_bytes: &[u8], // Changed from additional_fields to reserved_bytes
+        epoch: u32,
+        prev_event: Option<&CID>,
     ) -> Vec<u8> {
         let mut bytes = Vec::new();
 
@@ -169,69 +445,265 @@ where
         // For now, it's the last field.
         bytes.extend_from_slice(&(reserved_bytes.len() as u32).to_le_bytes());
         bytes.extend_from_slice(reserved_bytes);
-        
+
+        // Fields added after the original encoding follow the reserved bytes.
+        // Each is appended only when it differs from its default, behind its
+        // own tag and in tag order, so events that use none of them keep the
+        // ids they had before the field existed.
+
+        // Deleted entities (count + sorted); the tag keeps a deletion
+        // distinguishable from an update of the same CID.
+        if !deleted_entities_cids.is_empty() {
+            bytes.push(EVENT_FIELD_DELETED);
+            bytes.extend_from_slice(&(deleted_entities_cids.len() as u32).to_le_bytes());
+            Self::append_cids_for_digest(&mut bytes, deleted_entities_cids);
+        }
+
+        // The consumed use-limited capability.
+        if let Some(cid) = consumed_capability {
+            bytes.push(EVENT_FIELD_CONSUMED_CAPABILITY);
+            bytes.extend_from_slice(&cid.0);
+        }
+
+        // The clock epoch after a succession.
+        if epoch != 0 {
+            bytes.push(EVENT_FIELD_EPOCH);
+            bytes.extend_from_slice(&epoch.to_le_bytes());
+        }
+
+        // The previous event of the same replica, chaining its events. A
+        // replica's first event has none.
+        if let Some(prev) = prev_event {
+            bytes.push(EVENT_FIELD_PREV_EVENT);
+            bytes.extend_from_slice(&prev.0);
+        }
+
         bytes
     }
 
     /// Append the `delta` into Σ, checking basic invariants.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            lclock_new,
+            new = delta.new_entities.len(),
+            updated = delta.updated_entities.len(),
+            deleted = delta.deleted_entities.len(),
+        ),
+        err(level = "debug")
+    )]
     pub fn append_delta(&mut self, delta: &StateDelta, lclock_new: u64) -> Result<(), KernelError> {
-        // 1. CID uniqueness for new entities.
+        self.check_delta(delta, lclock_new)?;
+        let state_tree = self.stage_state_tree(delta, None)?;
+        self.materialise_delta(delta);
+        self.state_tree = state_tree;
+        Ok(())
+    }
+
+    /// Materialise an already checked `delta` into state.
+    fn materialise_delta(&mut self, delta: &StateDelta) {
+        for ent in &delta.new_entities {
+            let ent = Arc::new(ent.clone());
+            self.state.entities.insert(ent.header.id, Arc::clone(&ent));
+            self.history.record(EntityVersion::Live(ent));
+        }
+        for ent in &delta.updated_entities {
+            self.seed_history(&ent.header.id);
+            let ent = Arc::new(ent.clone());
+            self.state.entities.insert(ent.header.id, Arc::clone(&ent));
+            self.history.record(EntityVersion::Live(ent));
+        }
+        for tombstone in &delta.deleted_entities {
+            self.seed_history(&tombstone.id);
+            self.state.entities.remove(&tombstone.id);
+            self.state.tombstones.insert(tombstone.id, tombstone.clone());
+            self.history.record(EntityVersion::Deleted(tombstone.clone()));
+        }
+    }
+
+    /// Records the current version of an entity placed into Σ without a delta
+    /// (e.g. genesis state) before it is first replaced.
+    fn seed_history(&mut self, id: &CID) {
+        if self.history.history(id).is_empty() {
+            if let Some(current) = self.state.entities.get(id) {
+                self.history.record(EntityVersion::Live(Arc::clone(current)));
+            }
+        }
+    }
+
+    /// The entity `id` exactly as it was at `version`, if that version is retained.
+    ///
+    /// Returns `None` for unknown or pruned versions and for the tombstone version.
+    pub fn get_entity_at_version(&self, id: &CID, version: u64) -> Option<Arc<Entity<Vec<u8>>>> {
+        match self.history.at_version(id, version) {
+            Some(recorded) => recorded.entity().cloned(),
+            None => self
+                .state
+                .entities
+                .get(id)
+                .filter(|current| current.header.version == version)
+                .cloned(),
+        }
+    }
+
+    /// The entity `id` as of Lamport time `lclock`: its latest version written
+    /// at or before `lclock`.
+    ///
+    /// Returns `None` if the entity did not exist yet, had been deleted by then,
+    /// or the relevant version has been pruned.
+    pub fn get_entity_as_of(&self, id: &CID, lclock: u64) -> Option<Arc<Entity<Vec<u8>>>> {
+        match self.history.as_of(id, lclock) {
+            Some(recorded) => recorded.entity().cloned(),
+            None => self
+                .state
+                .entities
+                .get(id)
+                .filter(|current| current.header.lclock <= lclock || self.inherited_from_earlier_epoch(&current.header))
+                .cloned(),
+        }
+    }
+
+    /// Check the invariants `append_delta` enforces, without touching Σ.
+    pub fn check_delta(&self, delta: &StateDelta, lclock_new: u64) -> Result<(), KernelError> {
+        // 1. CID uniqueness for new entities; tombstoned CIDs are never reused.
         for ent in &delta.new_entities {
+            if self.state.tombstones.contains_key(&ent.header.id) {
+                return Err(KernelError::InvariantViolation(Invariant::EntityTombstoned(ent.header.id)));
+            }
             if self.state.entities.contains_key(&ent.header.id) {
-                return Err(KernelError::InvariantViolation(
-                    "New entity CID already exists in state".into(),
-                ));
+                return Err(KernelError::InvariantViolation(Invariant::EntityAlreadyExists(ent.header.id)));
             }
         }
 
-        // 2. Updated entities must exist and version++.
-        for upd in &delta.updated_entities {
-            match self.state.entities.get(&upd.header.id) {
-                Some(prev) if upd.header.version == prev.header.version + 1 => {}
-                Some(_) => {
-                    return Err(KernelError::InvariantViolation(format!(
-                        "Entity version monotonicity violated for CID {:?}",
-                        upd.header.id
-                    )));
+        // 2. Updated and deleted entities must exist, not be tombstoned, and version++.
+        let changed = delta
+            .updated_entities
+            .iter()
+            .map(|upd| &upd.header)
+            .chain(delta.deleted_entities.iter());
+        for header in changed {
+            if self.state.tombstones.contains_key(&header.id) {
+                return Err(KernelError::InvariantViolation(Invariant::EntityTombstoned(header.id)));
+            }
+            match self.state.entities.get(&header.id) {
+                Some(prev) if header.version == prev.header.version + 1 => {}
+                Some(prev) => {
+                    return Err(KernelError::InvariantViolation(Invariant::VersionNotMonotonic {
+                        entity: header.id,
+                        current: prev.header.version,
+                        proposed: header.version,
+                    }));
                 }
                 None => {
-                    return Err(KernelError::InvariantViolation(format!(
-                        "Updated entity with CID {:?} not found in state",
-                        upd.header.id
-                    )));
+                    return Err(KernelError::InvariantViolation(Invariant::EntityNotFound(header.id)));
                 }
             }
         }
 
-        // 3. lclock consistency across entities.
-        for ent in delta.new_entities.iter().chain(delta.updated_entities.iter()) {
-            if ent.header.lclock != lclock_new {
-                return Err(KernelError::InvariantViolation(
-                    "Entity lclock must equal event lclock".into(),
-                ));
+        // 3. lclock consistency across entities and tombstones.
+        let all_headers = delta
+            .new_entities
+            .iter()
+            .chain(delta.updated_entities.iter())
+            .map(|ent| &ent.header)
+            .chain(delta.deleted_entities.iter());
+        for header in all_headers {
+            if header.lclock != lclock_new {
+                return Err(KernelError::InvariantViolation(Invariant::LClockMismatch {
+                    entity: header.id,
+                    entity_lclock: header.lclock,
+                    event_lclock: lclock_new,
+                }));
             }
         }
 
-        // 4. Materialise into state.
-        for ent in &delta.new_entities {
-            self.state.entities.insert(ent.header.id, ent.clone());
-        }
-        for ent in &delta.updated_entities {
-            self.state.entities.insert(ent.header.id, ent.clone());
+        // 4. Parent causal bound (§2.1 invariant 3) and acyclic parent chains.
+        self.check_parents(delta)
+    }
+
+    /// Whether the version `header` of an entity in Σ was written in an earlier
+    /// clock epoch. Everything a replica writes in its current epoch carries an
+    /// lclock of at most `local_lc`, so only a successor can hold later ones.
+    fn inherited_from_earlier_epoch(&self, header: &EntityHeader) -> bool {
+        self.epoch > 0 && header.lclock > self.local_lc
+    }
+
+    /// Checks every parent referenced by a new or updated entity: it must be
+    /// observed (unless `unobserved_parents` allows it), satisfy
+    /// `parent.lclock ≤ child.lclock` unless it was written in an earlier
+    /// epoch, and the chain above it must not loop.
+    ///
+    /// Parents resolve against the delta first, then Σ, then tombstones.
+    fn check_parents(&self, delta: &StateDelta) -> Result<(), KernelError> {
+        let staged: HashMap<CID, &EntityHeader> = delta
+            .new_entities
+            .iter()
+            .chain(delta.updated_entities.iter())
+            .map(|ent| (ent.header.id, &ent.header))
+            .collect();
+        let header_of = |cid: &CID| -> Option<&EntityHeader> {
+            staged
+                .get(cid)
+                .copied()
+                .or_else(|| self.state.entities.get(cid).map(|ent| &ent.header))
+                .or_else(|| self.state.tombstones.get(cid))
+        };
+        // Any chain longer than the number of known headers must revisit one.
+        let max_depth = staged.len() + self.state.entities.len() + self.state.tombstones.len();
+
+        for child in delta.new_entities.iter().chain(delta.updated_entities.iter()).map(|ent| &ent.header) {
+            let Some(parent_id) = child.parent else { continue };
+            match header_of(&parent_id) {
+                Some(parent) if parent.lclock > child.lclock && !self.inherited_from_earlier_epoch(parent) => {
+                    return Err(KernelError::InvariantViolation(Invariant::ParentCausalBound {
+                        entity: child.id,
+                        parent: parent_id,
+                        parent_lclock: parent.lclock,
+                        entity_lclock: child.lclock,
+                    }));
+                }
+                Some(_) => {}
+                None if self.unobserved_parents == UnobservedParentPolicy::Allow => {}
+                None => {
+                    return Err(KernelError::InvariantViolation(Invariant::ParentNotFound {
+                        entity: child.id,
+                        parent: parent_id,
+                    }));
+                }
+            }
+
+            let mut ancestor = Some(parent_id);
+            let mut depth = 0;
+            while let Some(current) = ancestor {
+                if current == child.id || depth > max_depth {
+                    return Err(KernelError::InvariantViolation(Invariant::ParentCycle(child.id)));
+                }
+                ancestor = header_of(&current).and_then(|header| header.parent);
+                depth += 1;
+            }
         }
         Ok(())
     }
 
     /// Create an Event object from the committed `delta`.
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(command_id = ?command.id, replica = ?self.replica_id, lclock_new),
+        err(level = "debug")
+    )]
     fn materialise_event<C: EncodedCmd>(
         &self,
         command: &Command<C>,
         delta: &StateDelta,
+        consumed_capability: Option<CID>,
         lclock_new: u64,
         vc_new: VClock,
     ) -> Result<Event, KernelError> {
         let new_cids: Vec<CID> = delta.new_entities.iter().map(|e| e.header.id).collect();
         let updated_cids: Vec<CID> = delta.updated_entities.iter().map(|e| e.header.id).collect();
+        let deleted_cids: Vec<CID> = delta.deleted_entities.iter().map(|h| h.id).collect();
 
         // For a newly materialised event, additional_fields is None as it's not carrying
         // unknown fields from another source yet.
@@ -245,8 +717,12 @@ where
             command.alg_suite, // This is u8, as required by get_event_hash_input
             &new_cids,
             &updated_cids,
+            &deleted_cids,
+            consumed_capability.as_ref(),
             &vc_new,
             &reserved_for_new_event, // Pass empty reserved bytes
+            self.epoch,
+            self.chain_head.as_ref(),
         );
         let event_id = self.generate_cid(&input, command.alg_suite)?; // command.alg_suite is u8
 
@@ -256,8 +732,12 @@ where
             replica: self.replica_id,
             caused_by: command.id,
             lclock: lclock_new,
+            epoch: self.epoch,
             new_entities: new_cids,
             updated_entities: updated_cids,
+            deleted_entities: deleted_cids,
+            consumed_capability,
+            prev_event: self.chain_head,
             vclock: vc_new,
             reserved: reserved_for_new_event, // Initialize with empty Vec<u8>
         })
@@ -266,7 +746,7 @@ where
     /// Verify the command's signature using the capability holder's pub-key.
     fn verify_signature<C: EncodedCmd>(&self, command: &Command<C>) -> Result<(), KernelError> {
         let crypto_alg_suite = AlgSuite::try_from(command.alg_suite)
-            .map_err(|e| KernelError::Other(format!("Invalid AlgSuite tag in command: {}", e)))?;
+            .map_err(|_| KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(command.alg_suite)))?;
 
         let signed_bytes = command
             .payload
@@ -277,19 +757,14 @@ where
                 &command.capability,
                 command.lclock,
             )
-            .map_err(|e| {
-                KernelError::Other(format!(
-                    "Failed to get signed bytes from command payload: {:?}",
-                    e
-                ))
-            })?;
+            .map_err(|e| KernelError::Command(ErrorCause::from_error(&e)))?;
 
         // Capability lookup to obtain public key.
         let cap = self
             .state
             .capabilities
             .get(&command.capability)
-            .ok_or(KernelError::CapabilityNotFound)?;
+            .ok_or(KernelError::CapabilityNotFound(command.capability))?;
 
         self.crypto_provider.verify(&signed_bytes, &command.signature, &cap.holder, crypto_alg_suite) // Use self.crypto_provider
             .map_err(KernelError::Crypto) 
@@ -300,13 +775,26 @@ where
         capability: &Capability,
         cmd_payload: &T,
     ) -> Result<(), KernelError> {
-        if rights::sufficient(capability.rights, cmd_payload.required_rights()) {
+        let required = cmd_payload.required_rights();
+        for mask in [capability.rights, required] {
+            let unallocated = self.rights.unallocated_bits(mask);
+            if unallocated != 0 {
+                return Err(KernelError::UnallocatedRightsBits(unallocated));
+            }
+        }
+        if self.rights.sufficient(capability.rights, required) {
             Ok(())
         } else {
-            Err(KernelError::InsufficientRights)
+            Err(KernelError::InsufficientRights { granted: capability.rights, required })
         }
     }
 
+    #[tracing::instrument(
+        level = "debug",
+        skip_all,
+        fields(command_id = ?command.id, replica = ?command.replica, cmd_lclock = command.lclock, current_lc),
+        err(level = "debug")
+    )]
     pub fn validate_command<C: EncodedCmd + 'static>(
         &self,
         command: &Command<C>,
@@ -316,14 +804,14 @@ where
             .state
             .capabilities
             .get(&command.capability)
-            .ok_or(KernelError::CapabilityNotFound)?;
+            .ok_or(KernelError::CapabilityNotFound(command.capability))?;
 
         // Convert u8 tags to AlgSuite enums for comparison and use
         let cmd_alg_suite_tag = command.alg_suite;
         let cap_alg_suite_tag = cap.alg_suite;
 
         if cmd_alg_suite_tag != cap_alg_suite_tag { // Compare tags directly is fine here
-            return Err(KernelError::AlgorithmSuiteMismatch);
+            return Err(KernelError::AlgorithmSuiteMismatch { command: cmd_alg_suite_tag, capability: cap_alg_suite_tag });
         }
         // No need to convert to AlgSuite enum *just* for equality comparison of tags.
         // Conversion to AlgSuite enum is needed when calling crypto functions or 
@@ -331,42 +819,111 @@ where
 
         if let Some(expiry) = cap.expiry_lc {
             if current_lc >= expiry {
-                return Err(KernelError::CapabilityExpired);
+                return Err(KernelError::CapabilityExpired { expiry_lc: expiry, local_lc: current_lc });
+            }
+        }
+        if let Some(max_uses) = cap.max_uses {
+            if self.state.capability_uses.get(&cap.id).copied().unwrap_or(0) >= max_uses {
+                return Err(KernelError::CapabilityUsesExhausted);
             }
         }
         self.verify_signature(command)?; // verify_signature now handles AlgSuite conversion
         self.rights_sufficient(cap, &command.payload)?;
+        caveats::check(cap, command, &self.state, current_lc)?;
+        self.overlays.check(cap, command, &self.state)?;
         if command.lclock < current_lc { // Spec: relaxed to >=. Code has <. This needs review against spec §2.3.
             // For now, keeping existing logic: KernelError::InvalidCommandLClock for cmd.lclock < current_lc
             // Spec §2.3 Validation: assert cmd.lclock >= local_lc
             // Spec §7.1.2 Validation: Kernel accepts cmd.lclock >= local_lc.
             // The current code `command.lclock < current_lc` is equivalent to `!(command.lclock >= current_lc)`
             // This seems correct as per spec. Error if it's *less than* current.
-            return Err(KernelError::InvalidCommandLClock);
+            return Err(KernelError::InvalidCommandLClock { proposed: command.lclock, local_lc: current_lc });
         }
         Ok(())
     }
 
-    /// apply(cmd) → Event    (Kernel Spec §3)
-    pub fn apply<C: EncodedCmd + Clone + std::fmt::Debug + PartialEq + Eq + Send + Sync + 'static>(
-        &mut self,
-        command: &Command<C>,
-    ) -> Result<Event, KernelError> {
-        // Lamport overflow guard (§7.1.5).
-        if self.local_lc == u64::MAX {
-            return Err(KernelError::Other(
-                "Replica has reached maximum Lamport clock value and cannot process further commands.".into(),
-            ));
-        }
+    /// Explains the authorization decision for `command` against the current
+    /// state and `local_lc`, without applying it.
+    ///
+    /// Every check of `validate_command` is evaluated in the same order, even
+    /// after one has failed, so the trace shows all reasons a command would be
+    /// refused. `Explanation::verdict` equals the result of `validate_command`.
+    pub fn explain<C: EncodedCmd + 'static>(&self, command: &Command<C>) -> Explanation {
+        let current_lc = self.local_lc;
+        let cap = self.state.capabilities.get(&command.capability);
+        let mut steps = vec![Step::new(
+            Check::CapabilityLookup { capability: command.capability, found: cap.is_some() },
+            cap.map(|_| ()).ok_or(KernelError::CapabilityNotFound(command.capability)),
+        )];
+        let Some(cap) = cap else {
+            return Explanation { steps };
+        };
 
-        // 1. validate(cmd) (Kernel Spec §2.3)
-        self.validate_command(command, self.local_lc)?;
+        steps.push(Step::new(
+            Check::AlgSuite { command: command.alg_suite, capability: cap.alg_suite },
+            if command.alg_suite == cap.alg_suite {
+                Ok(())
+            } else {
+                Err(KernelError::AlgorithmSuiteMismatch { command: command.alg_suite, capability: cap.alg_suite })
+            },
+        ));
+        steps.push(Step::new(
+            Check::Expiry { expiry_lc: cap.expiry_lc, local_lc: current_lc },
+            match cap.expiry_lc {
+                Some(expiry_lc) if current_lc >= expiry_lc => {
+                    Err(KernelError::CapabilityExpired { expiry_lc, local_lc: current_lc })
+                }
+                _ => Ok(()),
+            },
+        ));
+        let used = self.state.capability_uses.get(&cap.id).copied().unwrap_or(0);
+        let exhausted = cap.max_uses.is_some_and(|max_uses| used >= max_uses);
+        steps.push(Step::new(
+            Check::UseLimit { max_uses: cap.max_uses, used },
+            if exhausted { Err(KernelError::CapabilityUsesExhausted) } else { Ok(()) },
+        ));
+        steps.push(Step::new(Check::Signature, self.verify_signature(command)));
+        steps.push(Step::new(
+            Check::Rights {
+                granted: cap.rights,
+                canonical: self.rights.canonicalise(cap.rights),
+                required: command.payload.required_rights(),
+            },
+            self.rights_sufficient(cap, &command.payload),
+        ));
+        for caveat in &cap.caveats {
+            let outcome = if caveats::holds(caveat, cap, command, &self.state, current_lc) {
+                Ok(())
+            } else {
+                Err(KernelError::CaveatViolated(format!("{:?}", caveat)))
+            };
+            steps.push(Step::new(Check::Caveat(caveat.clone()), outcome));
+        }
+        steps.push(Step::new(Check::Overlay { kind: cap.kind }, self.overlays.check(cap, command, &self.state)));
+        steps.push(Step::new(
+            Check::LClock { command: command.lclock, local_lc: current_lc },
+            if command.lclock < current_lc {
+                Err(KernelError::InvalidCommandLClock { proposed: command.lclock, local_lc: current_lc })
+            } else {
+                Ok(())
+            },
+        ));
+        Explanation { steps }
+    }
 
-        // 2. lclock_new = max(cmd.lclock, local_lc + 1) (Kernel Spec §3, §7.1.3)
-        let lclock_new = command.lclock.max(self.local_lc + 1);
+    /// Lamport overflow guard (§7.1.5).
+    fn check_lamport_headroom(&self) -> Result<(), KernelError> {
+        if self.local_lc == u64::MAX {
+            return Err(KernelError::LamportClockExhausted);
+        }
+        Ok(())
+    }
 
-        // 3. delta ← runtime(cmd) (Kernel Spec §3, §5)
-        let mut delta = self.runtime.execute(&self.state, command)?;
+    /// delta ← runtime(cmd) (Kernel Spec §3, §5), with every entity stamped with `lclock_new`.
+    fn execute_command<C: EncodedCmd>(&self, command: &Command<C>, lclock_new: u64) -> Result<StateDelta, KernelError> {
+        let mut delta = tracing::debug_span!("runtime_execute", command_id = ?command.id, lclock_new)
+            .in_scope(|| self.runtime.execute(&self.state, command))
+            .inspect_err(|error| tracing::debug!(%error, "runtime failed"))?;
 
         // --- KERNEL RESPONSIBILITY: SET ENTITY LCLOCKS ---
         // The runtime produces a delta based on the command and current state.
@@ -378,24 +935,21 @@ where
         for entity in delta.updated_entities.iter_mut() {
             entity.header.lclock = lclock_new;
         }
+        for tombstone in delta.deleted_entities.iter_mut() {
+            tombstone.lclock = lclock_new;
+        }
         // --- END LCLOCK ASSIGNMENT ---
+        Ok(delta)
+    }
 
-        // 4. Σ.append(delta, lclock_new) (Kernel Spec §3) 
-        //    (includes invariant checks: delta.respects_invariants() is implicitly checked by append_delta)
-        //    The lclock check in append_delta will now pass due to the step above.
-        self.append_delta(&delta, lclock_new)?;
-
-        // 5. local_lc = lclock_new (Kernel Spec §3)
-        self.local_lc = lclock_new;
-
-        // 6. Update vector clock for the new event.
-        //    Start with the kernel's current local_vc.
-        //    Merge the command's vclock if present.
-        //    Then, set the event's vclock for this replica to the new event lclock.
-        //    (Kernel Spec §3: "vc = merge_vector_clock(local_vc, cmd.vclock_if_present)")
-        //    (Kernel Spec §7.4 Increment: "On Event creation by replica R with Lamport time L: event.vclock[R] = L.
-        //                                Other entries are merged from the causal command or previous local state.")
-        
+    /// Vector clock for a new event at `lclock_new`.
+    ///    Start with the kernel's current local_vc.
+    ///    Merge the command's vclock if present.
+    ///    Then, set the event's vclock for this replica to the new event lclock.
+    ///    (Kernel Spec §3: "vc = merge_vector_clock(local_vc, cmd.vclock_if_present)")
+    ///    (Kernel Spec §7.4 Increment: "On Event creation by replica R with Lamport time L: event.vclock[R] = L.
+    ///                                Other entries are merged from the causal command or previous local state.")
+    fn event_vclock<C: EncodedCmd>(&self, command: &Command<C>, lclock_new: u64) -> VClock {
         let mut vc_for_event = self.local_vc.clone(); // Start with kernel's current VC
 
         if let Some(cmd_vc) = &command.vclock {
@@ -404,28 +958,413 @@ where
 
         // Regardless of command's VC, this replica's entry in the event's VC is set to the new event lclock.
         vc_for_event.0.insert(self.replica_id, lclock_new);
-        
-        // The kernel's local_vc is also updated to this newly computed vc_for_event, 
-        // as it represents the most up-to-date causal knowledge *after* this event.
-        self.local_vc = vc_for_event.clone();
+        vc_for_event
+    }
+
+    /// The use-limited capability consumed by `command`, if any.
+    /// A use-limited capability is consumed by the event, so peers replay the same count.
+    fn consumed_capability<C: EncodedCmd>(&self, command: &Command<C>) -> Option<CID> {
+        self.state
+            .capabilities
+            .get(&command.capability)
+            .filter(|cap| cap.max_uses.is_some())
+            .map(|cap| cap.id)
+    }
+
+    /// Dry-run of `apply`: validates `command`, executes it, checks the delta's
+    /// invariants and materialises the would-be event, all against the current
+    /// state. `local_lc`, `local_vc` and Σ are left untouched.
+    ///
+    /// The returned event is exactly what `apply` would produce if called next
+    /// with the same command.
+    pub fn simulate<C: EncodedCmd>(&self, command: &Command<C>) -> Result<Simulation, KernelError> {
+        self.stage(command).map(|(simulation, _)| simulation)
+    }
+
+    /// `simulate`, also returning the `append_delta` span the delta was checked
+    /// in so `apply` can materialise it in the same span.
+    fn stage<C: EncodedCmd>(&self, command: &Command<C>) -> Result<(Simulation, tracing::Span), KernelError> {
+        if let Some(successor) = self.successor {
+            return Err(KernelError::ReplicaRetired { successor });
+        }
+        self.check_lamport_headroom()?;
+        self.validate_command(command, self.local_lc)?;
+        let lclock_new = command.lclock.max(self.local_lc + 1);
+        let delta = self.execute_command(command, lclock_new)?;
+        let append_span = tracing::debug_span!(
+            "append_delta",
+            lclock_new,
+            new = delta.new_entities.len(),
+            updated = delta.updated_entities.len(),
+            deleted = delta.deleted_entities.len(),
+        );
+        append_span
+            .in_scope(|| {
+                if let Some(cap) = self.state.capabilities.get(&command.capability) {
+                    caveats::check_delta(cap, &delta, &self.state)?;
+                }
+                self.check_delta(&delta, lclock_new)
+            })
+            .inspect_err(|error| append_span.in_scope(|| tracing::debug!(%error, "delta rejected")))?;
+        let vc_for_event = self.event_vclock(command, lclock_new);
+        let event = self.materialise_event(command, &delta, self.consumed_capability(command), lclock_new, vc_for_event)?;
+        Ok((Simulation { event, delta }, append_span))
+    }
+
+    /// apply(cmd) → Event    (Kernel Spec §3)
+    ///
+    /// Every effect is staged first; Σ, `local_lc` and `local_vc` are only
+    /// mutated once the event has been successfully materialised, so a failure
+    /// at any step (including event id generation) leaves the kernel unchanged.
+    #[tracing::instrument(
+        level = "info",
+        skip_all,
+        fields(
+            command_id = ?command.id,
+            replica = ?command.replica,
+            cmd_lclock = command.lclock,
+            local_lc = self.local_lc,
+            event_lclock = tracing::field::Empty,
+            outcome = tracing::field::Empty,
+        )
+    )]
+    pub fn apply<C: EncodedCmd + Clone + std::fmt::Debug + PartialEq + Eq + Send + Sync + 'static>(
+        &mut self,
+        command: &Command<C>,
+    ) -> Result<Event, KernelError> {
+        // Stage (no mutation):
+        //   Lamport overflow guard (§7.1.5)
+        //   1. validate(cmd) (Kernel Spec §2.3)
+        //   2. lclock_new = max(cmd.lclock, local_lc + 1) (Kernel Spec §3, §7.1.3)
+        //   3. delta ← runtime(cmd) (Kernel Spec §3, §5)
+        //   4. delta.respects_invariants() (checked by check_delta)
+        //   5. vc = merge_vector_clock(local_vc, cmd.vclock_if_present), vc[self] = lclock_new
+        //   6. materialise_event (Kernel Spec §3)
+        match self.stage_and_commit(command, None) {
+            Ok(committed) => {
+                let span = tracing::Span::current();
+                span.record("event_lclock", committed.event.lclock);
+                span.record("outcome", "accepted");
+                self.notify_committed(&committed);
+                Ok(committed.event)
+            }
+            Err(error) => {
+                tracing::Span::current().record("outcome", "rejected");
+                tracing::info!(code = error.code(), %error, "command rejected");
+                self.notify_rejected(command, &error);
+                Err(error)
+            }
+        }
+    }
+
+    /// `apply` without observer notification. Within a batch, what the command
+    /// overwrites is first saved to `undo` and the caller persists the event;
+    /// otherwise the event is persisted before it is committed.
+    fn stage_and_commit<C: EncodedCmd>(&mut self, command: &Command<C>, undo: Option<&mut BatchUndo>) -> Result<Committed, KernelError> {
+        let started = Instant::now();
+        let (Simulation { event, delta }, append_span) = match self.stage(command) {
+            Ok(staged) => staged,
+            Err(error) => {
+                self.record_rejection(command, &error);
+                return Err(error);
+            }
+        };
+
+        // Failures past this point are the replica's, not the command's, and
+        // leave no rejection receipt.
+        let leaf = self.log_tree.prepare_append(&self.crypto_provider, &event.id)?;
+        let state_tree = self.stage_state_tree(&delta, event.consumed_capability.as_ref())?;
+
+        match undo {
+            Some(undo) => self.save_for_undo(undo, &delta, &event),
+            None => self.persist(&LogRecord::Committed(vec![(event.clone(), delta.clone())]))?,
+        }
+
+        // Commit (infallible): Σ.append(delta, lclock_new), local_lc, local_vc, event log.
+        append_span.in_scope(|| self.commit(&delta, &event, leaf, state_tree));
+
+        let capability_uses = event.consumed_capability.and_then(|cid| self.state.capability_uses.get(&cid).copied());
+        Ok(Committed { event, delta, capability_uses, latency: started.elapsed() })
+    }
+
+    /// Tells observers about a committed event and its effects on Σ.
+    fn notify_committed(&self, committed: &Committed) {
+        let Committed { event, delta, capability_uses, latency } = committed;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_applied(
+                delta.new_entities.len(),
+                delta.updated_entities.len(),
+                delta.deleted_entities.len(),
+                self.state.event_log.len(),
+                self.local_vc.0.len(),
+                *latency,
+            );
+        }
+        for observer in &self.observers {
+            observer.on_command_accepted(event);
+            for entity in &delta.new_entities {
+                observer.on_entity_created(entity, event);
+            }
+            for entity in &delta.updated_entities {
+                observer.on_entity_updated(entity, event);
+            }
+            for tombstone in &delta.deleted_entities {
+                observer.on_entity_deleted(tombstone, event);
+            }
+        }
+        self.notify_capability_use(event, *capability_uses);
+    }
+
+    fn notify_rejected<C: EncodedCmd>(&self, command: &Command<C>, error: &KernelError) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_rejected(error);
+        }
+        for observer in &self.observers {
+            observer.on_command_rejected(&command.id, &command.capability, error);
+        }
+    }
+
+    fn notify_capability_use(&self, event: &Event, uses: Option<u64>) {
+        let (Some(cid), Some(uses)) = (event.consumed_capability, uses) else { return };
+        let change = CapabilityChange::Used { uses };
+        for observer in &self.observers {
+            observer.on_capability_changed(&cid, &change);
+        }
+    }
+
+    /// Updates the event log length gauge after the log changed other than by
+    /// a commit (compaction, boot, recovery).
+    pub(crate) fn report_event_log_length(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_event_log_length(self.state.event_log.len());
+        }
+    }
 
-        // 7. materialise_event (Kernel Spec §3)
-        let event = self.materialise_event(command, &delta, lclock_new, vc_for_event)?;
+    /// Records a receipt for a refused command if the rejection log is enabled.
+    /// Neither Σ nor the clocks are touched.
+    fn record_rejection<C: EncodedCmd>(&mut self, command: &Command<C>, error: &KernelError) {
+        if let Some(log) = self.rejections.as_mut() {
+            if let Err(signing) = log.record(command.id, command.capability, command.alg_suite, error, self.replica_id, self.local_lc) {
+                tracing::warn!(%signing, code = error.code(), "rejection receipt could not be signed");
+            }
+        }
+    }
+
+    /// Commits a staged delta and its event. Must only be called with the
+    /// output of `simulate` against the current state.
+    fn commit(&mut self, delta: &StateDelta, event: &Event, leaf: PendingAppend, state_tree: StateTree) {
+        self.materialise_delta(delta);
+        self.state_tree = state_tree;
+
+        // local_lc = lclock_new (Kernel Spec §3)
+        self.local_lc = event.lclock;
+
+        // The kernel's local_vc is updated to the event's vclock,
+        // as it represents the most up-to-date causal knowledge *after* this event.
+        self.local_vc = event.vclock.clone();
 
         // Log the event locally (persisting to Σ.event_log).
         self.state.event_log.push(event.clone());
+        self.chain_head = Some(event.id);
+        self.log_tree.commit_append(leaf);
+        self.record_capability_use(event);
+    }
+
+    /// Applies `commands` atomically, in order.
+    ///
+    /// Each command is validated and executed against the state left by the
+    /// previous ones, so later commands observe earlier effects (entities, clocks,
+    /// capability uses). Either every command commits and their events are
+    /// returned in order, or the kernel is restored exactly as it was and the
+    /// index and error of the first failing command are returned.
+    pub fn apply_batch<C: EncodedCmd + Clone + std::fmt::Debug + PartialEq + Eq + Send + Sync + 'static>(
+        &mut self,
+        commands: &[Command<C>],
+    ) -> Result<Vec<Event>, BatchError> {
+        let mut undo = BatchUndo {
+            local_lc: self.local_lc,
+            local_vc: self.local_vc.clone(),
+            chain_head: self.chain_head,
+            event_log_len: self.state.event_log.len(),
+            log_size: self.log_tree.size(),
+            state_tree: self.state_tree.clone(),
+            entities: HashMap::new(),
+            capability_uses: HashMap::new(),
+        };
+        let mut committed = Vec::with_capacity(commands.len());
+        let mut failure = None;
+        for (index, command) in commands.iter().enumerate() {
+            match self.stage_and_commit(command, Some(&mut undo)) {
+                Ok(staged) => committed.push(staged),
+                Err(error) => {
+                    failure = Some((index, error));
+                    break;
+                }
+            }
+        }
+        // The whole batch is persisted as one record, so it is recovered entirely or not at all.
+        if failure.is_none() && !commands.is_empty() {
+            let record = LogRecord::Committed(committed.iter().map(|c| (c.event.clone(), c.delta.clone())).collect());
+            if let Err(error) = self.persist(&record) {
+                failure = Some((commands.len() - 1, error));
+            }
+        }
+        if let Some((index, error)) = failure {
+            self.roll_back(undo);
+            self.notify_rejected(&commands[index], &error);
+            return Err(BatchError { index, error });
+        }
+        // Observers only hear about the batch once all of it has committed.
+        for staged in &committed {
+            self.notify_committed(staged);
+        }
+        Ok(committed.into_iter().map(|staged| staged.event).collect())
+    }
+
+    /// Saves the current value of everything committing `delta` and `event`
+    /// will overwrite, unless an earlier command in the batch already did.
+    fn save_for_undo(&self, undo: &mut BatchUndo, delta: &StateDelta, event: &Event) {
+        let written = delta
+            .new_entities
+            .iter()
+            .chain(&delta.updated_entities)
+            .map(|entity| entity.header.id)
+            .chain(delta.deleted_entities.iter().map(|tombstone| tombstone.id));
+        for id in written {
+            undo.entities.entry(id).or_insert_with(|| SavedEntity {
+                live: self.state.entities.get(&id).cloned(),
+                tombstone: self.state.tombstones.get(&id).cloned(),
+                versions: self.history.history(&id).to_vec(),
+            });
+        }
+        if let Some(cid) = event.consumed_capability {
+            undo.capability_uses.entry(cid).or_insert_with(|| self.state.capability_uses.get(&cid).copied());
+        }
+    }
+
+    /// Restores the kernel to the point `undo` was taken.
+    fn roll_back(&mut self, undo: BatchUndo) {
+        self.local_lc = undo.local_lc;
+        self.local_vc = undo.local_vc;
+        self.chain_head = undo.chain_head;
+        self.state.event_log.truncate(undo.event_log_len);
+        self.log_tree.truncate(undo.log_size);
+        self.state_tree = undo.state_tree;
+        for (id, saved) in undo.entities {
+            match saved.live {
+                Some(entity) => self.state.entities.insert(id, entity),
+                None => self.state.entities.remove(&id),
+            };
+            match saved.tombstone {
+                Some(tombstone) => self.state.tombstones.insert(id, tombstone),
+                None => self.state.tombstones.remove(&id),
+            };
+            self.history.restore(id, saved.versions);
+        }
+        for (cid, uses) in undo.capability_uses {
+            match uses {
+                Some(uses) => self.state.capability_uses.insert(cid, uses),
+                None => self.state.capability_uses.remove(&cid),
+            };
+        }
+    }
+
+    /// Appends `record` to the attached storage, if any.
+    fn persist(&self, record: &LogRecord) -> Result<(), KernelError> {
+        match &self.storage {
+            Some(storage) => Ok(storage::lock(storage)?.append(record)?),
+            None => Ok(()),
+        }
+    }
 
-        Ok(event)
+    /// Advances the usage counter of the capability consumed by `event`, if any.
+    pub(crate) fn record_capability_use(&mut self, event: &Event) {
+        if let Some(cid) = event.consumed_capability {
+            let uses = self.state.capability_uses.entry(cid).or_insert(0);
+            *uses = uses.saturating_add(1);
+        }
     }
 
     /// Merge an incoming event's clocks into the local replica.
     pub fn process_incoming_event(&mut self, evt: &Event) -> Result<(), KernelError> {
-        // Lamport merge (§7.1.4)
-        self.local_lc = self.local_lc.max(evt.lclock);
+        self.merge_incoming(evt, false)
+    }
+
+    /// `process_incoming_event` for an event whose delivery authenticated it
+    /// as coming from `evt.replica`, e.g. over a channel bound to the
+    /// replica's key. Only conflicts between such events quarantine a replica.
+    pub fn process_authenticated_event(&mut self, evt: &Event) -> Result<(), KernelError> {
+        self.merge_incoming(evt, true)
+    }
+
+    #[tracing::instrument(
+        name = "process_incoming_event",
+        level = "info",
+        skip_all,
+        fields(event_id = ?evt.id, replica = ?evt.replica, event_lclock = evt.lclock, local_lc = self.local_lc, authenticated)
+    )]
+    fn merge_incoming(&mut self, evt: &Event, authenticated: bool) -> Result<(), KernelError> {
+        // Nothing below may trust `evt.id` until it is shown to name `evt`.
+        let computed = self.event_id(evt)?;
+        if computed != evt.id {
+            return Err(KernelError::EventIdMismatch { expected: evt.id, computed });
+        }
+        // Redelivering a replica's latest event changes nothing.
+        if self.replica_heads.get(&evt.replica) == Some(&evt.id) {
+            return Ok(());
+        }
+        if evt.replica != self.replica_id {
+            // Any replica may have moved into an epoch this one has reached.
+            let admitted = self.replica_epochs.get(&evt.replica).copied().unwrap_or(0).max(self.epoch);
+            if evt.epoch > admitted {
+                return Err(KernelError::UnadmittedEpoch { replica: evt.replica, epoch: evt.epoch });
+            }
+            self.check_equivocation(evt, authenticated)?;
+        }
+
+        // Once a replica's chain is tracked its events must arrive in chain
+        // order. Anything that does not extend the chain is an omission or a fork.
+        if let Some(head) = self.replica_heads.get(&evt.replica).copied() {
+            if evt.prev_event != Some(head) {
+                return Err(KernelError::BrokenEventChain { replica: evt.replica, expected: head, found: evt.prev_event });
+            }
+        }
 
-        // Vector-clock merge (§7.4.2) - now mandatory, using VClock::merge_into
+        // The replica's own events were counted when applied.
+        let consumed = evt
+            .consumed_capability
+            .filter(|_| evt.replica != self.replica_id);
+        let state_tree = self.stage_state_tree(&StateDelta::default(), consumed.as_ref())?;
+        // Written ahead like local commits: an event that cannot be stored is
+        // refused before anything changes, so a redelivery can store it later.
+        self.persist(&LogRecord::Merged(Box::new(evt.clone())))?;
+
+        // Lamport merge (§7.1.4). Times order by `(epoch, lclock)`, so the
+        // replica also moves into the event's epoch if it is later (§7.1.5).
+        self.epoch = self.epoch.max(evt.epoch);
+        self.local_lc = self.local_lc.max(evt.lclock);
+        // Vector-clock merge (§7.4.2) - now mandatory, using VClock::merge_into.
+        // Entries count each replica's own events, so they merge across epochs.
         self.local_vc.merge_into(&evt.vclock);
 
+        // Replicate use-limited capability counters; the chain check admits each event once.
+        if consumed.is_some() {
+            self.record_capability_use(evt);
+        }
+        self.state_tree = state_tree;
+        if evt.replica != self.replica_id {
+            self.replica_heads.insert(evt.replica, evt.id);
+            self.equivocations.witness(evt, authenticated);
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record_merged(self.local_vc.0.len());
+        }
+        for observer in &self.observers {
+            observer.on_event_merged(evt);
+        }
+        let uses = consumed.and_then(|cid| self.state.capability_uses.get(&cid).copied());
+        self.notify_capability_use(evt, uses);
+
         Ok(())
     }
 }
@@ -435,6 +1374,7 @@ where
 impl<CP: CryptoProvider + Clone, R: Runtime<CP> + Clone + std::fmt::Debug>
     Kernel<CP, R> 
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn get_event_hash_input_for_test(
         &self,
         caused_by_command_id: &CID,
@@ -443,8 +1383,12 @@ impl<CP: CryptoProvider + Clone, R: Runtime<CP> + Clone + std::fmt::Debug>
         event_alg_suite_tag: u8, // Corrected: Was event_alg_suite: AlgSuite, now u8 tag
         new_entities_cids: &[CID],
         updated_entities_cids: &[CID],
+        deleted_entities_cids: &[CID],
+        consumed_capability: Option<&CID>,
         vector_clock: &VClock,
         reserved_bytes: &[u8], // Corrected: Was additional_fields, now reserved_bytes
+        epoch: u32,
+        prev_event: Option<&CID>,
     ) -> Vec<u8> {
         // Now calling the private method from within the same impl block scope (conditionally compiled)
         self.get_event_hash_input(
@@ -454,8 +1398,12 @@ impl<CP: CryptoProvider + Clone, R: Runtime<CP> + Clone + std::fmt::Debug>
             event_alg_suite_tag, // Pass the u8 tag
             new_entities_cids, 
             updated_entities_cids, 
+            deleted_entities_cids,
+            consumed_capability,
             vector_clock, 
-            reserved_bytes // Pass reserved_bytes
+            reserved_bytes, // Pass reserved_bytes
+            epoch,
+            prev_event,
         )
     }
 }
@@ -464,6 +1412,6 @@ impl Kernel<crate::crypto::PlaceholderCryptoProvider, DefaultRuntime> {
     /// Convenience constructor used heavily in tests.
     /// Vector clocks are now mandatory, so enable_vector_clocks parameter is removed.
     pub fn new_with_default_crypto(replica_id: ReplicaID) -> Self {
-        Self::new(replica_id, DefaultRuntime::default(), crate::crypto::PlaceholderCryptoProvider::default())
+        Self::new(replica_id, DefaultRuntime, crate::crypto::PlaceholderCryptoProvider)
     }
 } 
\ No newline at end of file
@@ -0,0 +1,202 @@
+//! Equivocation detection.
+//!
+//! A replica produces exactly one event per Lamport time, and its own entry in
+//! the vector clock advances with every event it produces. Two different
+//! events from the same replica in the same clock epoch that share an `lclock`,
+//! or their own vector-clock entry, prove the replica faulty or malicious.
+//!
+//! `process_incoming_event` compares every incoming event against a window of
+//! events recently merged from the same replica. A conflict is refused with
+//! `KernelError::Equivocation` and kept as an `EquivocationProof`: both events
+//! in full, so anyone holding the proof can recompute their ids and check the
+//! conflict (`Kernel::verify_equivocation`).
+//!
+//! Events are not signed by their replica, so a proof only shows that two
+//! well-formed, conflicting events exist: anyone can build an event carrying
+//! another replica's id. Attributing the conflict to the replica rests on how
+//! the events were delivered. Under `EquivocationPolicy::Quarantine` a replica
+//! is therefore quarantined, and every later event from it refused, only when
+//! both conflicting events arrived through `Kernel::process_authenticated_event`.
+//! Other conflicts are recorded without quarantining anyone.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::crypto::CryptoProvider;
+use crate::error::KernelError;
+use crate::kernel::core::Kernel;
+use crate::kernel::runtime::Runtime;
+use crate::primitives::{Event, ReplicaID};
+
+/// Number of recent events per replica kept for comparison by default.
+pub const DEFAULT_EQUIVOCATION_WINDOW: usize = 256;
+
+/// What the kernel does with a replica once it has equivocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum EquivocationPolicy {
+    /// Refuse the conflicting event and record the proof; keep merging the
+    /// replica's other events.
+    #[default]
+    Record,
+    /// Additionally refuse every later event from the replica, once both
+    /// conflicting events were delivered authenticated.
+    Quarantine,
+}
+
+/// Two conflicting events from the same replica.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EquivocationProof {
+    /// The event merged first.
+    pub first: Event,
+    /// The conflicting event that was refused.
+    pub second: Event,
+}
+
+impl EquivocationProof {
+    /// The equivocating replica.
+    pub fn replica(&self) -> ReplicaID {
+        self.first.replica
+    }
+}
+
+/// Whether `a` and `b` are different events from one replica at the same
+/// position in its history.
+fn conflicts(a: &Event, b: &Event) -> bool {
+    let own_entry = |event: &Event| event.vclock.0.get(&event.replica).copied();
+    a.replica == b.replica
+        && a.epoch == b.epoch
+        && a.id != b.id
+        && (a.lclock == b.lclock || (own_entry(a).is_some() && own_entry(a) == own_entry(b)))
+}
+
+/// Recently merged events per replica, recorded proofs and quarantined replicas.
+#[derive(Debug, Clone)]
+pub struct EquivocationLog {
+    policy: EquivocationPolicy,
+    window: usize,
+    /// Recent events per replica, with whether their delivery was authenticated.
+    recent: HashMap<ReplicaID, VecDeque<(Event, bool)>>,
+    proofs: Vec<EquivocationProof>,
+    quarantined: HashSet<ReplicaID>,
+}
+
+impl Default for EquivocationLog {
+    fn default() -> Self {
+        Self::new(EquivocationPolicy::default())
+    }
+}
+
+impl EquivocationLog {
+    /// Creates an empty log applying `policy`, comparing against the last
+    /// `DEFAULT_EQUIVOCATION_WINDOW` events of each replica.
+    pub fn new(policy: EquivocationPolicy) -> Self {
+        EquivocationLog {
+            policy,
+            window: DEFAULT_EQUIVOCATION_WINDOW,
+            recent: HashMap::new(),
+            proofs: Vec::new(),
+            quarantined: HashSet::new(),
+        }
+    }
+
+    /// Compares incoming events against the last `window` events of each
+    /// replica. Conflicts with older events go undetected.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// Policy applied to equivocating replicas.
+    pub fn policy(&self) -> EquivocationPolicy {
+        self.policy
+    }
+
+    /// Every proof recorded, in the order the conflicts were detected.
+    pub fn proofs(&self) -> &[EquivocationProof] {
+        &self.proofs
+    }
+
+    /// Proofs recorded against `replica`.
+    pub fn for_replica<'a>(&'a self, replica: &'a ReplicaID) -> impl Iterator<Item = &'a EquivocationProof> + 'a {
+        self.proofs.iter().filter(move |proof| proof.replica() == *replica)
+    }
+
+    /// Whether events from `replica` are refused.
+    pub fn is_quarantined(&self, replica: &ReplicaID) -> bool {
+        self.quarantined.contains(replica)
+    }
+
+    /// Lifts the quarantine of `replica`. Returns whether it was quarantined.
+    pub fn release(&mut self, replica: &ReplicaID) -> bool {
+        self.quarantined.remove(replica)
+    }
+
+    /// A recently merged event that `event` conflicts with, if any, and
+    /// whether it was delivered authenticated.
+    pub(crate) fn find_conflict(&self, event: &Event) -> Option<&(Event, bool)> {
+        self.recent.get(&event.replica)?.iter().find(|(seen, _)| conflicts(seen, event))
+    }
+
+    /// Keeps the proof and, if both of its events were delivered
+    /// authenticated, applies the policy to the offending replica.
+    pub(crate) fn record(&mut self, proof: EquivocationProof, authenticated: bool) {
+        if authenticated && self.policy == EquivocationPolicy::Quarantine {
+            self.quarantined.insert(proof.replica());
+        }
+        self.proofs.push(proof);
+    }
+
+    /// Remembers a merged event for later comparison.
+    pub(crate) fn witness(&mut self, event: &Event, authenticated: bool) {
+        if self.window == 0 {
+            return;
+        }
+        let recent = self.recent.entry(event.replica).or_default();
+        if let Some((_, seen_authenticated)) = recent.iter_mut().find(|(seen, _)| seen.id == event.id) {
+            *seen_authenticated |= authenticated;
+            return;
+        }
+        if recent.len() == self.window {
+            recent.pop_front();
+        }
+        recent.push_back((event.clone(), authenticated));
+    }
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Checks that `proof` holds two different, well-formed events from one
+    /// replica that claim the same position in its history: each id matches
+    /// its event's contents, and the events share an epoch and either their
+    /// `lclock` or their replica's own vector-clock entry.
+    pub fn verify_equivocation(&self, proof: &EquivocationProof) -> Result<(), KernelError> {
+        let invalid = |reason: &str| Err(KernelError::InvalidEquivocationProof(reason.to_string()));
+        for event in [&proof.first, &proof.second] {
+            if self.event_id(event)? != event.id {
+                return invalid("event id does not match its contents");
+            }
+        }
+        if !conflicts(&proof.first, &proof.second) {
+            return invalid("events do not conflict");
+        }
+        Ok(())
+    }
+
+    /// Refuses events from quarantined replicas and events that conflict with
+    /// one recently merged, recording the proof. A proof is only recorded once
+    /// `verify_equivocation` accepts it, and the replica only quarantined if
+    /// `event` and the earlier event were both delivered authenticated.
+    pub(crate) fn check_equivocation(&mut self, event: &Event, authenticated: bool) -> Result<(), KernelError> {
+        if self.equivocations.is_quarantined(&event.replica) {
+            return Err(KernelError::ReplicaQuarantined(event.replica));
+        }
+        let Some((first, first_authenticated)) = self.equivocations.find_conflict(event).cloned() else { return Ok(()) };
+        let proof = EquivocationProof { first, second: event.clone() };
+        self.verify_equivocation(&proof)?;
+        let error = KernelError::Equivocation { replica: event.replica, first: proof.first.id, second: event.id };
+        self.equivocations.record(proof, authenticated && first_authenticated);
+        Err(error)
+    }
+}
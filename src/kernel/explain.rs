@@ -0,0 +1,80 @@
+//! Authorization decision traces.
+//!
+//! `Kernel::validate_command` stops at the first failing check and returns a
+//! bare `KernelError`. `Kernel::explain` instead evaluates every check it can,
+//! in the same order, and records the inputs each check compared. The verdict
+//! of an `Explanation` is always the error `validate_command` would return.
+
+use crate::error::KernelError;
+use crate::primitives::{Caveat, CID};
+use crate::types::RightsMask;
+
+/// A single check of `validate_command`, with the values it compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// The command's capability was looked up in Σ.
+    CapabilityLookup { capability: CID, found: bool },
+    /// Command and capability alg-suite tags.
+    AlgSuite { command: u8, capability: u8 },
+    /// Capability expiry against the replica's Lamport clock.
+    Expiry { expiry_lc: Option<u64>, local_lc: u64 },
+    /// Uses already recorded for a use-limited capability.
+    UseLimit { max_uses: Option<u64>, used: u64 },
+    /// Command signature against the capability holder's key.
+    Signature,
+    /// Capability rights, their closure under the rights algebra, and the
+    /// rights the payload requires.
+    Rights { granted: RightsMask, canonical: RightsMask, required: RightsMask },
+    /// One caveat attached to the capability.
+    Caveat(Caveat),
+    /// The overlay rules for the capability's `kind`.
+    Overlay { kind: u16 },
+    /// Command Lamport clock against the replica's.
+    LClock { command: u64, local_lc: u64 },
+}
+
+/// A check and its outcome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub check: Check,
+    pub outcome: Result<(), KernelError>,
+}
+
+impl Step {
+    pub(crate) fn new(check: Check, outcome: Result<(), KernelError>) -> Self {
+        Step { check, outcome }
+    }
+}
+
+/// Structured trace of an authorization decision, see `Kernel::explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Checks in the order `validate_command` applies them. If the capability
+    /// is not found, only the lookup step is present.
+    pub steps: Vec<Step>,
+}
+
+impl Explanation {
+    /// The decision: the first failing step's error, or `Ok(())`.
+    pub fn verdict(&self) -> Result<(), KernelError> {
+        match self.first_failure() {
+            Some(step) => step.outcome.clone(),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the command would pass validation.
+    pub fn is_allowed(&self) -> bool {
+        self.first_failure().is_none()
+    }
+
+    /// The step that decided a rejection, if any.
+    pub fn first_failure(&self) -> Option<&Step> {
+        self.steps.iter().find(|step| step.outcome.is_err())
+    }
+
+    /// Every step that failed, not just the deciding one.
+    pub fn failures(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().filter(|step| step.outcome.is_err())
+    }
+}
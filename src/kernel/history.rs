@@ -0,0 +1,195 @@
+//! Entity version history.
+//!
+//! Σ only holds the latest version of each entity; `append_delta` replaces it
+//! in place. The `VersionStore` keeps every version `append_delta` writes so
+//! auditors can read an entity as it was at a given version or Lamport time.
+//!
+//! Versions are immutable and stored behind `Arc`: the live version is the
+//! same allocation Σ holds, and reads and kernel clones share versions instead
+//! of copying entity bodies. A `RetentionPolicy` bounds how many versions are
+//! kept per entity.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::primitives::{Entity, EntityHeader, CID};
+
+/// How many versions of each entity the store keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RetentionPolicy {
+    /// Keep every version (default).
+    #[default]
+    KeepAll,
+    /// Keep only the most recent `n` versions of each entity (at least one).
+    KeepLast(usize),
+}
+
+/// One recorded version of an entity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityVersion {
+    /// The entity as written at this version.
+    Live(Arc<Entity<Vec<u8>>>),
+    /// The tombstone that deleted the entity at this version.
+    Deleted(EntityHeader),
+}
+
+impl EntityVersion {
+    /// Header of this version.
+    pub fn header(&self) -> &EntityHeader {
+        match self {
+            EntityVersion::Live(entity) => &entity.header,
+            EntityVersion::Deleted(header) => header,
+        }
+    }
+
+    /// The entity, unless this version is a tombstone.
+    pub fn entity(&self) -> Option<&Arc<Entity<Vec<u8>>>> {
+        match self {
+            EntityVersion::Live(entity) => Some(entity),
+            EntityVersion::Deleted(_) => None,
+        }
+    }
+}
+
+/// Per-entity version history, ordered by version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionStore {
+    versions: HashMap<CID, Vec<EntityVersion>>,
+    retention: RetentionPolicy,
+}
+
+impl VersionStore {
+    /// Creates an empty store that keeps every version.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty store with the given retention policy.
+    pub fn with_retention(retention: RetentionPolicy) -> Self {
+        VersionStore { versions: HashMap::new(), retention }
+    }
+
+    /// The retention policy applied on every write.
+    pub fn retention(&self) -> RetentionPolicy {
+        self.retention
+    }
+
+    /// Changes the retention policy and prunes existing histories to match.
+    pub fn set_retention(&mut self, retention: RetentionPolicy) {
+        self.retention = retention;
+        let ids: Vec<CID> = self.versions.keys().copied().collect();
+        for id in ids {
+            self.prune(&id);
+        }
+    }
+
+    /// Records a new version. Versions must be recorded in increasing order.
+    pub(crate) fn record(&mut self, version: EntityVersion) {
+        let id = version.header().id;
+        self.versions.entry(id).or_default().push(version);
+        self.prune(&id);
+    }
+
+    fn prune(&mut self, id: &CID) {
+        if let (RetentionPolicy::KeepLast(n), Some(history)) = (self.retention, self.versions.get_mut(id)) {
+            let keep = n.max(1);
+            if history.len() > keep {
+                history.drain(..history.len() - keep);
+            }
+        }
+    }
+
+    /// Every retained version of `id`, oldest first.
+    pub fn history(&self, id: &CID) -> &[EntityVersion] {
+        self.versions.get(id).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The retained record of `id` at exactly `version`.
+    pub fn at_version(&self, id: &CID, version: u64) -> Option<&EntityVersion> {
+        let history = self.versions.get(id)?;
+        history
+            .binary_search_by_key(&version, |v| v.header().version)
+            .ok()
+            .map(|index| &history[index])
+    }
+
+    /// The latest retained record of `id` written at or before `lclock`.
+    pub fn as_of(&self, id: &CID, lclock: u64) -> Option<&EntityVersion> {
+        self.versions.get(id)?.iter().rev().find(|v| v.header().lclock <= lclock)
+    }
+
+    /// The version of `id` written at exactly `lclock`, if retained.
+    pub fn written_at(&self, id: &CID, lclock: u64) -> Option<&EntityVersion> {
+        self.as_of(id, lclock).filter(|v| v.header().lclock == lclock)
+    }
+
+    /// Ids of every entity with at least one retained version.
+    pub fn entity_ids(&self) -> impl Iterator<Item = &CID> {
+        self.versions.iter().filter(|(_, history)| !history.is_empty()).map(|(id, _)| id)
+    }
+
+    /// Replaces the retained versions of `id`, e.g. to undo writes.
+    pub(crate) fn restore(&mut self, id: CID, versions: Vec<EntityVersion>) {
+        if versions.is_empty() {
+            self.versions.remove(&id);
+        } else {
+            self.versions.insert(id, versions);
+        }
+    }
+
+    /// Forgets every recorded version.
+    pub fn clear(&mut self) {
+        self.versions.clear();
+    }
+
+    /// Total number of retained versions across all entities.
+    pub fn len(&self) -> usize {
+        self.versions.values().map(Vec::len).sum()
+    }
+
+    /// Whether no version has been retained.
+    pub fn is_empty(&self) -> bool {
+        self.versions.values().all(Vec::is_empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::CidBytes;
+
+    fn version(v: u64, lclock: u64) -> EntityVersion {
+        EntityVersion::Live(Arc::new(Entity {
+            header: EntityHeader { id: CidBytes([1; 32]), version: v, lclock, parent: None },
+            body: vec![v as u8],
+        }))
+    }
+
+    #[test]
+    fn lookups_by_version_and_lclock() {
+        let mut store = VersionStore::new();
+        for v in 1..=3 {
+            store.record(version(v, v * 10));
+        }
+        let id = CidBytes([1; 32]);
+        assert_eq!(store.at_version(&id, 2).unwrap().header().lclock, 20);
+        assert!(store.at_version(&id, 4).is_none());
+        assert_eq!(store.as_of(&id, 25).unwrap().header().version, 2);
+        assert_eq!(store.as_of(&id, 30).unwrap().header().version, 3);
+        assert!(store.as_of(&id, 9).is_none());
+    }
+
+    #[test]
+    fn retention_keeps_latest_versions() {
+        let mut store = VersionStore::with_retention(RetentionPolicy::KeepLast(2));
+        for v in 1..=4 {
+            store.record(version(v, v));
+        }
+        let id = CidBytes([1; 32]);
+        let kept: Vec<u64> = store.history(&id).iter().map(|v| v.header().version).collect();
+        assert_eq!(kept, vec![3, 4]);
+
+        store.set_retention(RetentionPolicy::KeepLast(0));
+        assert_eq!(store.len(), 1, "The latest version is always kept");
+    }
+}
@@ -0,0 +1,552 @@
+//! Merkle accumulator over the event log (RFC 6962 §2.1).
+//!
+//! Every event a replica commits is appended as a leaf (its id) to an
+//! append-only Merkle tree, so a client holding a signed root can check that
+//! an `Event` is part of the replica's log (inclusion proof) and that a later
+//! log extends an earlier one (consistency proof). Hashing uses the kernel's
+//! `CryptoProvider` under a fixed algorithm suite, with the RFC 6962 domain
+//! separation: leaves hash `0x00 || event id`, interior nodes `0x01 || left || right`.
+//!
+//! The tree caches the hash of every complete, aligned subtree, so appends,
+//! roots and proofs cost O(log n) hashes. A tree restored from a snapshot only
+//! knows the peaks of its prefix; proofs that need pruned nodes fail with
+//! `KernelError::ProofUnavailable`.
+
+use crate::crypto::{CryptoError, CryptoProvider};
+use crate::error::KernelError;
+use crate::kernel::core::Kernel;
+use crate::kernel::runtime::Runtime;
+use crate::primitives::CID;
+use crate::types::AlgSuite;
+
+/// A node or root hash.
+pub type TreeHash = [u8; 32];
+
+/// Complete-subtree hashes of one tree level; `nodes[i]` covers subtree `offset + i`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Level {
+    offset: u64,
+    nodes: Vec<TreeHash>,
+}
+
+/// Append-only Merkle tree over event ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleLog {
+    alg_suite: AlgSuite,
+    size: u64,
+    /// `levels[k]` holds the hashes of complete subtrees of `2^k` leaves.
+    levels: Vec<Level>,
+}
+
+impl Default for MerkleLog {
+    fn default() -> Self {
+        Self::new(AlgSuite::CLASSIC)
+    }
+}
+
+/// Nodes staged by `MerkleLog::prepare_append`: the leaf hash followed by
+/// every complete subtree the leaf closes, bottom-up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PendingAppend(Vec<TreeHash>);
+
+impl MerkleLog {
+    /// Creates an empty tree hashing under `alg_suite`.
+    pub fn new(alg_suite: AlgSuite) -> Self {
+        MerkleLog { alg_suite, size: 0, levels: Vec::new() }
+    }
+
+    /// Restores a tree of `size` leaves from its `peaks`: the roots of the
+    /// complete subtrees `size` decomposes into, largest (leftmost) first.
+    pub fn from_peaks(alg_suite: AlgSuite, size: u64, peaks: &[TreeHash]) -> Result<Self, KernelError> {
+        let heights: Vec<u32> = (0..u64::BITS).rev().filter(|k| size & (1 << k) != 0).collect();
+        if heights.len() != peaks.len() {
+            return Err(KernelError::ProofUnavailable(format!(
+                "{} peaks given for a tree of {size} leaves, expected {}",
+                peaks.len(),
+                heights.len()
+            )));
+        }
+        let height = heights.first().map_or(0, |k| *k as usize + 1);
+        let mut levels: Vec<Level> =
+            (0..height).map(|k| Level { offset: size >> k, nodes: Vec::new() }).collect();
+        for (k, peak) in heights.into_iter().zip(peaks) {
+            let level = &mut levels[k as usize];
+            level.offset -= 1;
+            level.nodes.push(*peak);
+        }
+        Ok(MerkleLog { alg_suite, size, levels })
+    }
+
+    /// Algorithm suite every node is hashed under.
+    pub fn alg_suite(&self) -> AlgSuite {
+        self.alg_suite
+    }
+
+    /// Number of leaves.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Peaks of the first `size` leaves (see `from_peaks`).
+    pub fn peaks(&self, size: u64) -> Result<Vec<TreeHash>, KernelError> {
+        self.check_size(size)?;
+        let mut start = 0;
+        let mut peaks = Vec::new();
+        for k in (0..u64::BITS).rev().filter(|k| size & (1 << k) != 0) {
+            peaks.push(self.node(k, start >> k)?);
+            start += 1 << k;
+        }
+        Ok(peaks)
+    }
+
+    /// Hashes `event_id` and the subtrees it completes without changing the tree.
+    pub(crate) fn prepare_append<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        event_id: &CID,
+    ) -> Result<PendingAppend, KernelError> {
+        let mut staged = vec![leaf_hash(provider, self.alg_suite, event_id)?];
+        let mut index = self.size;
+        let mut k = 0;
+        while index & 1 == 1 {
+            let left = self.node(k, index - 1)?;
+            let right = *staged.last().expect("staged starts with the leaf");
+            staged.push(node_hash(provider, self.alg_suite, &left, &right)?);
+            index >>= 1;
+            k += 1;
+        }
+        Ok(PendingAppend(staged))
+    }
+
+    /// Appends a leaf staged by `prepare_append` against the current tree.
+    pub(crate) fn commit_append(&mut self, pending: PendingAppend) {
+        self.size += 1;
+        for (k, hash) in pending.0.into_iter().enumerate() {
+            if self.levels.len() == k {
+                self.levels.push(Level::default());
+            }
+            self.levels[k].nodes.push(hash);
+        }
+    }
+
+    /// Hashes and appends `event_id`.
+    pub fn append<CP: CryptoProvider>(&mut self, provider: &CP, event_id: &CID) -> Result<(), KernelError> {
+        let pending = self.prepare_append(provider, event_id)?;
+        self.commit_append(pending);
+        Ok(())
+    }
+
+    /// Drops every leaf past the first `size`, undoing later appends.
+    pub(crate) fn truncate(&mut self, size: u64) {
+        if size >= self.size {
+            return;
+        }
+        self.size = size;
+        for (k, level) in self.levels.iter_mut().enumerate() {
+            let keep = (size >> k).saturating_sub(level.offset);
+            level.nodes.truncate(keep as usize);
+        }
+        while self.levels.last().is_some_and(|level| level.offset == 0 && level.nodes.is_empty()) {
+            self.levels.pop();
+        }
+    }
+
+    /// Root of the current tree.
+    pub fn root<CP: CryptoProvider>(&self, provider: &CP) -> Result<TreeHash, KernelError> {
+        self.root_at(provider, self.size)
+    }
+
+    /// Root of the tree formed by the first `size` leaves. The empty tree's
+    /// root is the hash of the empty string.
+    pub fn root_at<CP: CryptoProvider>(&self, provider: &CP, size: u64) -> Result<TreeHash, KernelError> {
+        self.check_size(size)?;
+        if size == 0 {
+            return provider.hash(&[], self.alg_suite).map_err(KernelError::Crypto);
+        }
+        self.subtree(provider, 0, size)
+    }
+
+    /// Audit path of leaf `index` in the tree of the first `size` leaves (RFC 6962 §2.1.1).
+    pub fn inclusion_proof<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        index: u64,
+        size: u64,
+    ) -> Result<InclusionProof, KernelError> {
+        self.check_size(size)?;
+        if index >= size {
+            return Err(KernelError::ProofUnavailable(format!("leaf {index} is outside a tree of {size} leaves")));
+        }
+        let mut path = Vec::new();
+        self.path(provider, index, 0, size, &mut path)?;
+        Ok(InclusionProof { leaf_index: index, tree_size: size, path })
+    }
+
+    /// Proof that the tree of `old_size` leaves is a prefix of the tree of
+    /// `new_size` leaves (RFC 6962 §2.1.2). Requires `0 < old_size <= new_size`.
+    pub fn consistency_proof<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        old_size: u64,
+        new_size: u64,
+    ) -> Result<ConsistencyProof, KernelError> {
+        self.check_size(new_size)?;
+        if old_size == 0 || old_size > new_size {
+            return Err(KernelError::ProofUnavailable(format!("no consistency proof from {old_size} to {new_size} leaves")));
+        }
+        let mut path = Vec::new();
+        self.subproof(provider, old_size, 0, new_size, true, &mut path)?;
+        Ok(ConsistencyProof { old_size, new_size, path })
+    }
+
+    fn check_size(&self, size: u64) -> Result<(), KernelError> {
+        if size > self.size {
+            return Err(KernelError::ProofUnavailable(format!("tree has {} leaves, not {size}", self.size)));
+        }
+        Ok(())
+    }
+
+    /// Cached hash of complete subtree `index` at level `k`.
+    fn node(&self, k: u32, index: u64) -> Result<TreeHash, KernelError> {
+        self.levels
+            .get(k as usize)
+            .and_then(|level| index.checked_sub(level.offset).and_then(|i| level.nodes.get(i as usize)))
+            .copied()
+            .ok_or_else(|| KernelError::ProofUnavailable(format!("subtree {index} at height {k} was pruned")))
+    }
+
+    /// MTH(D[start:start + n]) for `n > 0`.
+    fn subtree<CP: CryptoProvider>(&self, provider: &CP, start: u64, n: u64) -> Result<TreeHash, KernelError> {
+        if n.is_power_of_two() && start.is_multiple_of(n) {
+            let k = n.trailing_zeros();
+            return self.node(k, start >> k);
+        }
+        let k = split(n);
+        let left = self.subtree(provider, start, k)?;
+        let right = self.subtree(provider, start + k, n - k)?;
+        node_hash(provider, self.alg_suite, &left, &right)
+    }
+
+    /// PATH(m, D[start:start + n]).
+    fn path<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        m: u64,
+        start: u64,
+        n: u64,
+        out: &mut Vec<TreeHash>,
+    ) -> Result<(), KernelError> {
+        if n == 1 {
+            return Ok(());
+        }
+        let k = split(n);
+        if m < k {
+            self.path(provider, m, start, k, out)?;
+            out.push(self.subtree(provider, start + k, n - k)?);
+        } else {
+            self.path(provider, m - k, start + k, n - k, out)?;
+            out.push(self.subtree(provider, start, k)?);
+        }
+        Ok(())
+    }
+
+    /// SUBPROOF(m, D[start:start + n], complete).
+    fn subproof<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        m: u64,
+        start: u64,
+        n: u64,
+        complete: bool,
+        out: &mut Vec<TreeHash>,
+    ) -> Result<(), KernelError> {
+        if m == n {
+            if !complete {
+                out.push(self.subtree(provider, start, n)?);
+            }
+            return Ok(());
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(provider, m, start, k, complete, out)?;
+            out.push(self.subtree(provider, start + k, n - k)?);
+        } else {
+            self.subproof(provider, m - k, start + k, n - k, false, out)?;
+            out.push(self.subtree(provider, start, k)?);
+        }
+        Ok(())
+    }
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Root of the event log accumulator over every committed event.
+    pub fn log_root(&self) -> Result<TreeHash, KernelError> {
+        self.log_tree.root(&self.crypto_provider)
+    }
+
+    /// Proof that the local event `event_id` is included in the current log root.
+    ///
+    /// Fails with `EventNotFound` for events not in the (possibly compacted) local log.
+    pub fn event_inclusion_proof(&self, event_id: &CID) -> Result<InclusionProof, KernelError> {
+        let log = &self.state.event_log;
+        let position = log
+            .iter()
+            .position(|event| event.id == *event_id)
+            .ok_or(KernelError::EventNotFound(*event_id))?;
+        // The log holds the most recent leaves; compacted events precede it.
+        let size = self.log_tree.size();
+        let index = size.saturating_sub(log.len() as u64) + position as u64;
+        self.log_tree.inclusion_proof(&self.crypto_provider, index, size)
+    }
+
+    /// Proof that the log of `old_size` events is a prefix of the log of `new_size` events.
+    pub fn log_consistency_proof(&self, old_size: u64, new_size: u64) -> Result<ConsistencyProof, KernelError> {
+        self.log_tree.consistency_proof(&self.crypto_provider, old_size, new_size)
+    }
+}
+
+/// Proof that a leaf is included in a tree of a given size.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    pub path: Vec<TreeHash>,
+}
+
+impl InclusionProof {
+    /// Whether the proof shows `event_id` at `leaf_index` in the tree with `root`
+    /// (RFC 9162 §2.1.3.2).
+    pub fn verify<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        alg_suite: AlgSuite,
+        event_id: &CID,
+        root: &TreeHash,
+    ) -> Result<bool, CryptoError> {
+        if self.leaf_index >= self.tree_size {
+            return Ok(false);
+        }
+        let (mut fn_, mut sn) = (self.leaf_index, self.tree_size - 1);
+        let mut r = leaf_hash_raw(provider, alg_suite, event_id)?;
+        for p in &self.path {
+            if sn == 0 {
+                return Ok(false);
+            }
+            if fn_ & 1 == 1 || fn_ == sn {
+                r = node_hash_raw(provider, alg_suite, p, &r)?;
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            } else {
+                r = node_hash_raw(provider, alg_suite, &r, p)?;
+            }
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+        Ok(sn == 0 && r == *root)
+    }
+}
+
+/// Proof that one tree size is a prefix of another.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ConsistencyProof {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub path: Vec<TreeHash>,
+}
+
+impl ConsistencyProof {
+    /// Whether the proof shows the tree with `old_root` is a prefix of the tree
+    /// with `new_root` (RFC 9162 §2.1.4.2).
+    pub fn verify<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        alg_suite: AlgSuite,
+        old_root: &TreeHash,
+        new_root: &TreeHash,
+    ) -> Result<bool, CryptoError> {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return Ok(false);
+        }
+        if self.old_size == self.new_size {
+            return Ok(self.path.is_empty() && old_root == new_root);
+        }
+        let mut path = self.path.iter();
+        let first = if self.old_size.is_power_of_two() { Some(old_root) } else { path.next() };
+        let Some(first) = first else { return Ok(false) };
+
+        let (mut fn_, mut sn) = (self.old_size - 1, self.new_size - 1);
+        while fn_ & 1 == 1 {
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+        let (mut fr, mut sr) = (*first, *first);
+        for c in path {
+            if sn == 0 {
+                return Ok(false);
+            }
+            if fn_ & 1 == 1 || fn_ == sn {
+                fr = node_hash_raw(provider, alg_suite, c, &fr)?;
+                sr = node_hash_raw(provider, alg_suite, c, &sr)?;
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            } else {
+                sr = node_hash_raw(provider, alg_suite, &sr, c)?;
+            }
+            fn_ >>= 1;
+            sn >>= 1;
+        }
+        Ok(fr == *old_root && sr == *new_root && sn == 0)
+    }
+}
+
+/// Largest power of two strictly less than `n` (`n > 1`).
+fn split(n: u64) -> u64 {
+    1 << (u64::BITS - 1 - (n - 1).leading_zeros())
+}
+
+fn leaf_hash_raw<CP: CryptoProvider>(provider: &CP, alg_suite: AlgSuite, event_id: &CID) -> Result<TreeHash, CryptoError> {
+    let mut bytes = Vec::with_capacity(33);
+    bytes.push(0x00);
+    bytes.extend_from_slice(&event_id.0);
+    provider.hash(&bytes, alg_suite)
+}
+
+fn node_hash_raw<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    left: &TreeHash,
+    right: &TreeHash,
+) -> Result<TreeHash, CryptoError> {
+    let mut bytes = Vec::with_capacity(65);
+    bytes.push(0x01);
+    bytes.extend_from_slice(left);
+    bytes.extend_from_slice(right);
+    provider.hash(&bytes, alg_suite)
+}
+
+fn leaf_hash<CP: CryptoProvider>(provider: &CP, alg_suite: AlgSuite, event_id: &CID) -> Result<TreeHash, KernelError> {
+    leaf_hash_raw(provider, alg_suite, event_id).map_err(KernelError::Crypto)
+}
+
+fn node_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    left: &TreeHash,
+    right: &TreeHash,
+) -> Result<TreeHash, KernelError> {
+    node_hash_raw(provider, alg_suite, left, right).map_err(KernelError::Crypto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::DigestCryptoProvider;
+    use crate::primitives::CidBytes;
+
+    const ALG: AlgSuite = AlgSuite::CLASSIC;
+
+    fn id(i: u64) -> CID {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&i.to_le_bytes());
+        CidBytes(bytes)
+    }
+
+    /// Reference MTH straight from RFC 6962, without caching.
+    fn reference_root(leaves: &[CID]) -> TreeHash {
+        let p = DigestCryptoProvider;
+        match leaves.len() {
+            0 => p.hash(&[], ALG).unwrap(),
+            1 => leaf_hash_raw(&p, ALG, &leaves[0]).unwrap(),
+            n => {
+                let k = split(n as u64) as usize;
+                node_hash_raw(&p, ALG, &reference_root(&leaves[..k]), &reference_root(&leaves[k..])).unwrap()
+            }
+        }
+    }
+
+    fn tree(n: u64) -> MerkleLog {
+        let mut tree = MerkleLog::new(ALG);
+        for i in 0..n {
+            tree.append(&DigestCryptoProvider, &id(i)).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn roots_match_reference() {
+        let p = DigestCryptoProvider;
+        let full = tree(33);
+        let leaves: Vec<CID> = (0..33).map(id).collect();
+        for n in 0..=33 {
+            assert_eq!(full.root_at(&p, n).unwrap(), reference_root(&leaves[..n as usize]), "size {n}");
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_verify() {
+        let p = DigestCryptoProvider;
+        let full = tree(21);
+        for size in 1..=21 {
+            let root = full.root_at(&p, size).unwrap();
+            for index in 0..size {
+                let proof = full.inclusion_proof(&p, index, size).unwrap();
+                assert!(proof.verify(&p, ALG, &id(index), &root).unwrap(), "leaf {index} of {size}");
+                assert!(!proof.verify(&p, ALG, &id(index + 1), &root).unwrap());
+                let mut shifted = proof.clone();
+                shifted.leaf_index = (index + 1) % size;
+                assert!(size == 1 || !shifted.verify(&p, ALG, &id(index), &root).unwrap());
+            }
+        }
+        assert!(full.inclusion_proof(&p, 5, 5).is_err());
+        assert!(full.inclusion_proof(&p, 0, 22).is_err());
+    }
+
+    #[test]
+    fn consistency_proofs_verify() {
+        let p = DigestCryptoProvider;
+        let full = tree(21);
+        for new in 1..=21 {
+            let new_root = full.root_at(&p, new).unwrap();
+            for old in 1..=new {
+                let old_root = full.root_at(&p, old).unwrap();
+                let proof = full.consistency_proof(&p, old, new).unwrap();
+                assert!(proof.verify(&p, ALG, &old_root, &new_root).unwrap(), "{old} -> {new}");
+                if old < new {
+                    let forged = tree(old).root_at(&p, old).map(|mut r| {
+                        r[0] ^= 1;
+                        r
+                    });
+                    assert!(!proof.verify(&p, ALG, &forged.unwrap(), &new_root).unwrap());
+                }
+            }
+        }
+        assert!(full.consistency_proof(&p, 0, 3).is_err());
+        assert!(full.consistency_proof(&p, 4, 3).is_err());
+    }
+
+    #[test]
+    fn restored_and_truncated_trees_continue_identically() {
+        let p = DigestCryptoProvider;
+        let base = tree(13);
+        let mut restored = MerkleLog::from_peaks(ALG, 13, &base.peaks(13).unwrap()).unwrap();
+        let mut truncated = tree(20);
+        truncated.truncate(13);
+        assert_eq!(truncated, base);
+        for i in 13..20 {
+            restored.append(&p, &id(i)).unwrap();
+        }
+        let full = tree(20);
+        assert_eq!(restored.root(&p).unwrap(), full.root(&p).unwrap());
+        assert!(restored.inclusion_proof(&p, 15, 20).unwrap().verify(&p, ALG, &id(15), &full.root(&p).unwrap()).unwrap());
+        assert!(matches!(restored.inclusion_proof(&p, 3, 20), Err(KernelError::ProofUnavailable(_))));
+        assert!(MerkleLog::from_peaks(ALG, 13, &[]).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Kernel metrics.
+//!
+//! A `KernelMetrics` registry is shared (`Arc`) between a kernel and the host
+//! application. The kernel updates it after every commit, rejection and merge;
+//! the host reads a consistent `MetricsSnapshot` at any time and may render it
+//! in the Prometheus text exposition format.
+//!
+//! Metrics are observational only: they are never read back by the kernel and
+//! cannot influence deterministic state.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::KernelError;
+
+/// Default `apply` latency buckets, in seconds.
+pub const DEFAULT_LATENCY_BUCKETS: [f64; 10] =
+    [0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// A fixed-bucket histogram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Upper bounds of the buckets, ascending. An implicit `+Inf` bucket follows.
+    pub bounds: Vec<f64>,
+    /// Number of observations per bucket (not cumulative); one more entry than `bounds`.
+    pub counts: Vec<u64>,
+    /// Sum of all observations.
+    pub sum: f64,
+    /// Number of observations.
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Creates an empty histogram with the given ascending bucket bounds.
+    pub fn new(bounds: &[f64]) -> Self {
+        Histogram { bounds: bounds.to_vec(), counts: vec![0; bounds.len() + 1], sum: 0.0, count: 0 }
+    }
+
+    /// Records one observation.
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Point-in-time copy of every kernel metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Commands applied (committed) by this replica.
+    pub commands_applied: u64,
+    /// Commands refused, by `KernelError::kind`.
+    pub rejections: BTreeMap<&'static str, u64>,
+    /// Entities created by applied commands.
+    pub entities_created: u64,
+    /// Entities updated by applied commands.
+    pub entities_updated: u64,
+    /// Entities deleted (tombstoned) by applied commands.
+    pub entities_deleted: u64,
+    /// Events from other replicas merged by `process_incoming_event`.
+    pub events_merged: u64,
+    /// Length of the local event log after the last commit.
+    pub event_log_length: u64,
+    /// Number of replicas in the local vector clock.
+    pub vclock_width: u64,
+    /// Wall-clock latency of successful `apply` calls, in seconds.
+    pub apply_latency: Histogram,
+}
+
+impl Default for MetricsSnapshot {
+    fn default() -> Self {
+        MetricsSnapshot {
+            commands_applied: 0,
+            rejections: BTreeMap::new(),
+            entities_created: 0,
+            entities_updated: 0,
+            entities_deleted: 0,
+            events_merged: 0,
+            event_log_length: 0,
+            vclock_width: 0,
+            apply_latency: Histogram::new(&DEFAULT_LATENCY_BUCKETS),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// Total number of rejected commands across all error kinds.
+    pub fn rejections_total(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format (v0.0.4).
+    ///
+    /// Every metric name is prefixed with `amulet_kernel_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP amulet_kernel_{name} {help}");
+            let _ = writeln!(out, "# TYPE amulet_kernel_{name} counter");
+            let _ = writeln!(out, "amulet_kernel_{name} {value}");
+        };
+        let gauge = |out: &mut String, name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP amulet_kernel_{name} {help}");
+            let _ = writeln!(out, "# TYPE amulet_kernel_{name} gauge");
+            let _ = writeln!(out, "amulet_kernel_{name} {value}");
+        };
+
+        counter(&mut out, "commands_applied_total", "Commands applied by this replica.", self.commands_applied);
+
+        let _ = writeln!(out, "# HELP amulet_kernel_commands_rejected_total Commands refused, by error kind.");
+        let _ = writeln!(out, "# TYPE amulet_kernel_commands_rejected_total counter");
+        for (kind, count) in &self.rejections {
+            let _ = writeln!(out, "amulet_kernel_commands_rejected_total{{kind=\"{kind}\"}} {count}");
+        }
+
+        counter(&mut out, "entities_created_total", "Entities created by applied commands.", self.entities_created);
+        counter(&mut out, "entities_updated_total", "Entities updated by applied commands.", self.entities_updated);
+        counter(&mut out, "entities_deleted_total", "Entities deleted by applied commands.", self.entities_deleted);
+        counter(&mut out, "events_merged_total", "Events merged from other replicas.", self.events_merged);
+        gauge(&mut out, "event_log_length", "Length of the local event log.", self.event_log_length);
+        gauge(&mut out, "vclock_width", "Replicas in the local vector clock.", self.vclock_width);
+
+        let latency = &self.apply_latency;
+        let _ = writeln!(out, "# HELP amulet_kernel_apply_latency_seconds Latency of successful apply calls.");
+        let _ = writeln!(out, "# TYPE amulet_kernel_apply_latency_seconds histogram");
+        let mut cumulative = 0;
+        for (bound, count) in latency.bounds.iter().zip(&latency.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "amulet_kernel_apply_latency_seconds_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "amulet_kernel_apply_latency_seconds_bucket{{le=\"+Inf\"}} {}", latency.count);
+        let _ = writeln!(out, "amulet_kernel_apply_latency_seconds_sum {}", latency.sum);
+        let _ = writeln!(out, "amulet_kernel_apply_latency_seconds_count {}", latency.count);
+        out
+    }
+}
+
+/// Thread-safe metrics registry updated by the kernel.
+#[derive(Debug, Default)]
+pub struct KernelMetrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl KernelMetrics {
+    /// Creates a registry with the default latency buckets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry with custom `apply` latency buckets, in seconds.
+    pub fn with_latency_buckets(bounds: &[f64]) -> Self {
+        KernelMetrics {
+            inner: Mutex::new(MetricsSnapshot { apply_latency: Histogram::new(bounds), ..Default::default() }),
+        }
+    }
+
+    /// A consistent copy of every metric.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.lock().clone()
+    }
+
+    pub(crate) fn record_applied(
+        &self,
+        entities_created: usize,
+        entities_updated: usize,
+        entities_deleted: usize,
+        event_log_length: usize,
+        vclock_width: usize,
+        latency: Duration,
+    ) {
+        let mut metrics = self.lock();
+        metrics.commands_applied += 1;
+        metrics.entities_created += entities_created as u64;
+        metrics.entities_updated += entities_updated as u64;
+        metrics.entities_deleted += entities_deleted as u64;
+        metrics.event_log_length = event_log_length as u64;
+        metrics.vclock_width = vclock_width as u64;
+        metrics.apply_latency.observe(latency.as_secs_f64());
+    }
+
+    pub(crate) fn record_rejected(&self, error: &KernelError) {
+        *self.lock().rejections.entry(error.kind()).or_insert(0) += 1;
+    }
+
+    pub(crate) fn record_merged(&self, vclock_width: usize) {
+        let mut metrics = self.lock();
+        metrics.events_merged += 1;
+        metrics.vclock_width = vclock_width as u64;
+    }
+
+    pub(crate) fn record_event_log_length(&self, event_log_length: usize) {
+        self.lock().event_log_length = event_log_length as u64;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsSnapshot> {
+        // Metrics stay usable even if a thread panicked while holding the lock.
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_observations() {
+        let mut histogram = Histogram::new(&[1.0, 2.0]);
+        for value in [0.5, 1.0, 1.5, 3.0] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.counts, vec![2, 1, 1]);
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.sum, 6.0);
+    }
+
+    #[test]
+    fn prometheus_rendering() {
+        let metrics = KernelMetrics::with_latency_buckets(&[0.5, 1.0]);
+        metrics.record_applied(2, 1, 0, 1, 1, Duration::from_millis(250));
+        metrics.record_rejected(&KernelError::CapabilityUsesExhausted);
+        metrics.record_rejected(&KernelError::CapabilityUsesExhausted);
+        metrics.record_merged(3);
+
+        let text = metrics.snapshot().to_prometheus();
+        for line in [
+            "# TYPE amulet_kernel_commands_applied_total counter",
+            "amulet_kernel_commands_applied_total 1",
+            "amulet_kernel_commands_rejected_total{kind=\"CapabilityUsesExhausted\"} 2",
+            "amulet_kernel_entities_created_total 2",
+            "amulet_kernel_entities_updated_total 1",
+            "amulet_kernel_events_merged_total 1",
+            "# TYPE amulet_kernel_vclock_width gauge",
+            "amulet_kernel_vclock_width 3",
+            "# TYPE amulet_kernel_apply_latency_seconds histogram",
+            "amulet_kernel_apply_latency_seconds_bucket{le=\"0.5\"} 1",
+            "amulet_kernel_apply_latency_seconds_bucket{le=\"1\"} 1",
+            "amulet_kernel_apply_latency_seconds_bucket{le=\"+Inf\"} 1",
+            "amulet_kernel_apply_latency_seconds_sum 0.25",
+            "amulet_kernel_apply_latency_seconds_count 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line:?} in:\n{text}");
+        }
+    }
+}
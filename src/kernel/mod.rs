@@ -1,10 +1,38 @@
 pub mod core;
 pub mod runtime;
+pub mod overlay;
+pub mod caveats;
+pub mod receipts;
+pub mod explain;
+pub mod observer;
+pub mod metrics;
+pub mod history;
+pub mod equivocation;
+pub mod merkle;
+pub mod replay;
+pub mod snapshot;
+pub mod state_tree;
+pub mod storage;
+pub mod succession;
 
 // TODO: Potentially move error definitions specific to kernel operations here?
 // For now, top-level `error.rs` is used.
 mod tests; // Added to include the new test module
 
 // Re-export the primary types so existing `crate::kernel::*` paths continue to work.
-pub use core::{Kernel, StateDelta, SystemState};
-pub use runtime::{Runtime, DefaultRuntime}; 
\ No newline at end of file
+pub use core::{Kernel, Simulation, StateDelta, SystemState, UnobservedParentPolicy};
+pub use runtime::{Runtime, DefaultRuntime};
+pub use explain::{Check, Explanation, Step};
+pub use history::{EntityVersion, RetentionPolicy, VersionStore};
+pub use equivocation::{EquivocationLog, EquivocationPolicy, EquivocationProof, DEFAULT_EQUIVOCATION_WINDOW};
+pub use merkle::{ConsistencyProof, InclusionProof, MerkleLog, TreeHash};
+pub use metrics::{Histogram, KernelMetrics, MetricsSnapshot};
+pub use observer::{CapabilityChange, KernelObserver};
+pub use receipts::{RejectionLog, RejectionReceipt, ReceiptSigner};
+pub use snapshot::Snapshot;
+pub use state_tree::{
+    capability_value_hash, entity_value_hash, tombstone_value_hash, SparseMerkleTree, StateProof, StateRoot, StateTree, EMPTY_ROOT,
+};
+pub use storage::{FileWal, FsyncPolicy, LogRecord, MemoryStorage, Recovery, Storage, StorageError};
+pub use succession::Handoff;
+pub use overlay::{OverlayRegistry, KindValidator, CommandView, UnknownKindPolicy}; 
\ No newline at end of file
@@ -0,0 +1,43 @@
+//! Kernel observer hooks.
+//!
+//! Observers let integrators react to kernel outcomes without wrapping every
+//! `Kernel::apply` call. They are notified only after a state change has been
+//! committed (or a command definitively refused) and receive shared
+//! references, so they can never influence deterministic state.
+
+use crate::error::KernelError;
+use crate::primitives::{Entity, EntityHeader, Event, CID};
+
+/// How a capability's replicated state changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CapabilityChange {
+    /// A use-limited capability authorized another command; `uses` is the new count.
+    Used { uses: u64 },
+}
+
+/// Callbacks invoked by the kernel after commit. Every method defaults to a no-op.
+///
+/// Callbacks run synchronously on the kernel's thread, in the order the
+/// corresponding effects were committed. They should return quickly.
+pub trait KernelObserver: Send + Sync + std::fmt::Debug + 'static {
+    /// A local command was applied and produced `event`.
+    fn on_command_accepted(&self, _event: &Event) {}
+
+    /// A local command was refused with `error`. Nothing was committed.
+    fn on_command_rejected(&self, _command_id: &CID, _capability: &CID, _error: &KernelError) {}
+
+    /// `entity` was inserted into Σ by `event`.
+    fn on_entity_created(&self, _entity: &Entity<Vec<u8>>, _event: &Event) {}
+
+    /// `entity` replaced its previous version in Σ by `event`.
+    fn on_entity_updated(&self, _entity: &Entity<Vec<u8>>, _event: &Event) {}
+
+    /// The entity described by `tombstone` was deleted from Σ by `event`.
+    fn on_entity_deleted(&self, _tombstone: &EntityHeader, _event: &Event) {}
+
+    /// The replicated state of `capability` changed.
+    fn on_capability_changed(&self, _capability: &CID, _change: &CapabilityChange) {}
+
+    /// An event from another replica was merged by `process_incoming_event`.
+    fn on_event_merged(&self, _event: &Event) {}
+}
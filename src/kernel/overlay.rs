@@ -0,0 +1,157 @@
+//! Capability overlay registry.
+//!
+//! `Capability.kind` is reserved for overlay semantics (SpecPlan §1, §3). An
+//! overlay gives a capability type (e.g. spending limit, read-only auditor,
+//! time-boxed session) extra admission rules that run during
+//! `Kernel::validate_command`, after the core kernel checks have passed.
+//!
+//! Validators are looked up by `kind` in an `OverlayRegistry`. Kind `0`
+//! (`KIND_PLAIN`) denotes a plain capability with no overlay and is never
+//! dispatched. What happens to any other kind without a registered validator
+//! is decided by the registry's `UnknownKindPolicy`.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::command_traits::EncodedCmd;
+use crate::error::KernelError;
+use crate::kernel::core::SystemState;
+use crate::primitives::{Capability, Command, ReplicaID, VClock, CID};
+use crate::types::RightsMask;
+
+/// The `kind` of a plain capability that carries no overlay semantics.
+pub const KIND_PLAIN: u16 = 0;
+
+/// Payload-agnostic view of a `Command` handed to overlay validators.
+///
+/// `Command<C>` is generic over its payload, which would make validators
+/// impossible to store behind a trait object. The kernel therefore lowers the
+/// command into this view, exposing the encoded payload bytes and the rights
+/// the payload requires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandView<'a> {
+    pub id: &'a CID,
+    pub alg_suite: u8,
+    pub replica: &'a ReplicaID,
+    pub capability: &'a CID,
+    pub lclock: u64,
+    pub vclock: Option<&'a VClock>,
+    /// `payload.encode()` of the original command.
+    pub payload: Vec<u8>,
+    /// `payload.required_rights()` of the original command.
+    pub required_rights: RightsMask,
+}
+
+impl<'a> CommandView<'a> {
+    /// Lowers a typed command into a payload-agnostic view.
+    pub fn new<C: EncodedCmd>(command: &'a Command<C>) -> Self {
+        CommandView {
+            id: &command.id,
+            alg_suite: command.alg_suite,
+            replica: &command.replica,
+            capability: &command.capability,
+            lclock: command.lclock,
+            vclock: command.vclock.as_ref(),
+            payload: command.payload.encode(),
+            required_rights: command.payload.required_rights(),
+        }
+    }
+}
+
+/// Extra admission rules attached to a capability `kind`.
+///
+/// Like `Runtime::execute`, `validate` MUST be deterministic and free of side
+/// effects: every replica has to reach the same verdict for the same inputs.
+pub trait KindValidator: Send + Sync + std::fmt::Debug + 'static {
+    /// Returns `Ok(())` to admit the command, or a human-readable reason to reject it.
+    fn validate(
+        &self,
+        capability: &Capability,
+        command: &CommandView<'_>,
+        state: &SystemState,
+    ) -> Result<(), String>;
+}
+
+/// What the kernel does with a non-plain `kind` that has no registered validator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnknownKindPolicy {
+    /// Refuse the command with `KernelError::UnknownCapabilityKind` (conservative default).
+    #[default]
+    Reject,
+    /// Treat the capability as plain and admit the command.
+    Allow,
+}
+
+/// Maps capability `kind` values to their overlay validators.
+#[derive(Debug, Clone, Default)]
+pub struct OverlayRegistry {
+    validators: BTreeMap<u16, Arc<dyn KindValidator>>,
+    unknown_kind_policy: UnknownKindPolicy,
+}
+
+impl OverlayRegistry {
+    /// Creates an empty registry that rejects unknown kinds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty registry with the given policy for unknown kinds.
+    pub fn with_policy(unknown_kind_policy: UnknownKindPolicy) -> Self {
+        OverlayRegistry { validators: BTreeMap::new(), unknown_kind_policy }
+    }
+
+    /// Registers `validator` for `kind`, returning the validator it replaced, if any.
+    ///
+    /// `KIND_PLAIN` cannot be overlaid.
+    pub fn register<V: KindValidator>(
+        &mut self,
+        kind: u16,
+        validator: V,
+    ) -> Result<Option<Arc<dyn KindValidator>>, KernelError> {
+        if kind == KIND_PLAIN {
+            return Err(KernelError::ReservedCapabilityKind(kind));
+        }
+        Ok(self.validators.insert(kind, Arc::new(validator)))
+    }
+
+    /// Removes the validator for `kind`, returning it if one was registered.
+    pub fn unregister(&mut self, kind: u16) -> Option<Arc<dyn KindValidator>> {
+        self.validators.remove(&kind)
+    }
+
+    /// Returns the validator registered for `kind`, if any.
+    pub fn get(&self, kind: u16) -> Option<&Arc<dyn KindValidator>> {
+        self.validators.get(&kind)
+    }
+
+    /// The policy applied to kinds without a registered validator.
+    pub fn unknown_kind_policy(&self) -> UnknownKindPolicy {
+        self.unknown_kind_policy
+    }
+
+    /// Changes the policy applied to kinds without a registered validator.
+    pub fn set_unknown_kind_policy(&mut self, policy: UnknownKindPolicy) {
+        self.unknown_kind_policy = policy;
+    }
+
+    /// Runs the overlay rules for `capability.kind` against `command`.
+    pub fn check<C: EncodedCmd>(
+        &self,
+        capability: &Capability,
+        command: &Command<C>,
+        state: &SystemState,
+    ) -> Result<(), KernelError> {
+        if capability.kind == KIND_PLAIN {
+            return Ok(());
+        }
+        match self.validators.get(&capability.kind) {
+            Some(validator) => validator
+                .validate(capability, &CommandView::new(command), state)
+                .map_err(|reason| KernelError::OverlayRejected(capability.kind, reason)),
+            None => match self.unknown_kind_policy {
+                UnknownKindPolicy::Reject => Err(KernelError::UnknownCapabilityKind(capability.kind)),
+                UnknownKindPolicy::Allow => Ok(()),
+            },
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Rejection receipts.
+//!
+//! When a replica refuses a command, the caller only sees a `KernelError`. An
+//! optional `RejectionLog` lets the kernel additionally record a deterministic
+//! receipt of the refusal, optionally signed by the replica, so auditors can
+//! later prove that the replica declined the action.
+//!
+//! Receipts are local audit records: they are not events, never enter
+//! `SystemState.event_log` and never advance the Lamport clock. They record
+//! refusals of the command itself (validation, runtime and delta checks), not
+//! failures of the replica such as a storage error.
+
+use std::sync::Arc;
+
+use crate::crypto::CryptoError;
+use crate::error::KernelError;
+use crate::primitives::{ReplicaID, Signature, CID};
+
+/// Signs rejection receipts (and snapshots) on behalf of the replica.
+///
+/// Private keys stay outside the kernel (see `types::PrivateKeyPlaceholder`);
+/// the host supplies a signer backed by its key management.
+pub trait ReceiptSigner: Send + Sync + std::fmt::Debug + 'static {
+    /// Signs the receipt's `signing_bytes`.
+    fn sign(&self, data: &[u8]) -> Result<Signature, CryptoError>;
+}
+
+/// A deterministic record that a replica refused a command.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RejectionReceipt {
+    /// Id of the refused command.
+    pub command_id: CID,
+    /// Capability the command presented.
+    pub capability: CID,
+    /// Alg-suite tag of the refused command.
+    pub alg_suite: u8,
+    /// `KernelError::code` of the rejection.
+    pub reason_code: u16,
+    /// Replica that refused the command.
+    pub replica: ReplicaID,
+    /// The replica's Lamport clock at the time of refusal.
+    pub local_lc: u64,
+    /// Replica signature over `signing_bytes`, if a signer is configured.
+    pub signature: Option<Signature>,
+}
+
+impl RejectionReceipt {
+    /// Deterministic byte encoding of every field except the signature.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"amulet/rejection/v1");
+        bytes.extend_from_slice(&self.command_id.0);
+        bytes.extend_from_slice(&self.capability.0);
+        bytes.push(self.alg_suite);
+        bytes.extend_from_slice(&self.reason_code.to_le_bytes());
+        bytes.extend_from_slice(&self.replica.0);
+        bytes.extend_from_slice(&self.local_lc.to_le_bytes());
+        bytes
+    }
+}
+
+/// Append-only log of rejection receipts, with an optional signer.
+#[derive(Debug, Clone, Default)]
+pub struct RejectionLog {
+    receipts: Vec<RejectionReceipt>,
+    signer: Option<Arc<dyn ReceiptSigner>>,
+    signing_failures: u64,
+}
+
+impl RejectionLog {
+    /// Creates an empty log whose receipts are left unsigned.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty log whose receipts are signed by `signer`.
+    pub fn with_signer<S: ReceiptSigner>(signer: S) -> Self {
+        RejectionLog { receipts: Vec::new(), signer: Some(Arc::new(signer)), signing_failures: 0 }
+    }
+
+    /// Records a receipt for `error`, signing it if a signer is configured.
+    ///
+    /// With a signer configured no unsigned receipt is ever recorded: a signing
+    /// failure is counted in `signing_failures` and returned instead.
+    pub(crate) fn record(
+        &mut self,
+        command_id: CID,
+        capability: CID,
+        alg_suite: u8,
+        error: &KernelError,
+        replica: ReplicaID,
+        local_lc: u64,
+    ) -> Result<&RejectionReceipt, CryptoError> {
+        let mut receipt = RejectionReceipt {
+            command_id,
+            capability,
+            alg_suite,
+            reason_code: error.code(),
+            replica,
+            local_lc,
+            signature: None,
+        };
+        if let Some(signer) = &self.signer {
+            match signer.sign(&receipt.signing_bytes()) {
+                Ok(signature) => receipt.signature = Some(signature),
+                Err(error) => {
+                    self.signing_failures += 1;
+                    return Err(error);
+                }
+            }
+        }
+        self.receipts.push(receipt);
+        Ok(self.receipts.last().expect("receipt was just pushed"))
+    }
+
+    /// Number of rejections left without a receipt because signing failed.
+    pub fn signing_failures(&self) -> u64 {
+        self.signing_failures
+    }
+
+    /// All receipts, in the order they were recorded.
+    pub fn receipts(&self) -> &[RejectionReceipt] {
+        &self.receipts
+    }
+
+    /// Receipts recorded for the command `command_id`.
+    pub fn for_command<'a>(&'a self, command_id: &'a CID) -> impl Iterator<Item = &'a RejectionReceipt> + 'a {
+        self.receipts.iter().filter(move |r| r.command_id == *command_id)
+    }
+
+    /// Receipts recorded for commands presenting `capability`.
+    pub fn for_capability<'a>(&'a self, capability: &'a CID) -> impl Iterator<Item = &'a RejectionReceipt> + 'a {
+        self.receipts.iter().filter(move |r| r.capability == *capability)
+    }
+
+    /// Number of recorded receipts.
+    pub fn len(&self) -> usize {
+        self.receipts.len()
+    }
+
+    /// Whether no receipt has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.receipts.is_empty()
+    }
+}
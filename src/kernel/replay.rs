@@ -0,0 +1,186 @@
+//! Point-in-time reconstruction of Σ.
+//!
+//! `SystemState` only holds the latest materialised view. Auditors that need Σ
+//! as it was after a given event rebuild it here by replaying a prefix of the
+//! local event log against the entity versions retained in the `VersionStore`.
+//!
+//! Replay re-checks the `append_delta` invariants for every event, so a log
+//! that disagrees with the recorded versions is reported as
+//! `KernelError::ReplayMismatch` instead of producing a silently wrong view.
+//! Capabilities are not derived from events: the reconstructed state carries
+//! the current capability set, and `capability_uses` counts only the uses
+//! recorded by the replayed local events.
+//!
+//! Once the log has been compacted, replay starts from the base snapshot
+//! instead, and states before it are no longer available.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::crypto::CryptoProvider;
+use crate::error::{Invariant, KernelError};
+use crate::kernel::core::{Kernel, SystemState};
+use crate::kernel::history::EntityVersion;
+use crate::kernel::runtime::Runtime;
+use crate::primitives::{Entity, EntityHeader, Event, CID};
+
+/// Live entities keyed by id, as held in `SystemState::entities`.
+type Entities = HashMap<CID, Arc<Entity<Vec<u8>>>>;
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Σ as it was immediately after the local event `event_id` was committed.
+    ///
+    /// Fails with `EventNotFound` if the event is not in the local log and with
+    /// `HistoryUnavailable` if a version needed for the replay has been pruned.
+    pub fn state_as_of_event(&self, event_id: &CID) -> Result<SystemState, KernelError> {
+        let position = self
+            .state
+            .event_log
+            .iter()
+            .position(|event| event.id == *event_id)
+            .ok_or(KernelError::EventNotFound(*event_id))?;
+        self.replay(position + 1)
+    }
+
+    /// Σ as it was after every local event with a Lamport time at or before `lclock`.
+    ///
+    /// Fails with `LogCompacted` if `lclock` precedes the base snapshot.
+    pub fn state_as_of_lclock(&self, lclock: u64) -> Result<SystemState, KernelError> {
+        if let Some(base) = self.base_snapshot.as_deref().filter(|base| lclock < base.lclock) {
+            return Err(KernelError::LogCompacted(base.lclock));
+        }
+        let count = self.state.event_log.iter().take_while(|event| event.lclock <= lclock).count();
+        self.replay(count)
+    }
+
+    /// Rebuilds Σ from the base snapshot (or the genesis entities) and the
+    /// first `count` events of the log.
+    fn replay(&self, count: usize) -> Result<SystemState, KernelError> {
+        let log = &self.state.event_log;
+        let mut state = match &self.base_snapshot {
+            Some(base) => base.to_state(),
+            None => SystemState {
+                capabilities: self.state.capabilities.clone(),
+                entities: self.genesis_entities()?,
+                ..SystemState::default()
+            },
+        };
+
+        for event in &log[..count] {
+            self.replay_event(&mut state, event)?;
+            if let Some(cid) = event.consumed_capability {
+                *state.capability_uses.entry(cid).or_insert(0) += 1;
+            }
+            state.event_log.push(event.clone());
+        }
+        Ok(state)
+    }
+
+    /// Entities that were placed into Σ directly rather than created by an
+    /// event, at the version they had before any event touched them.
+    fn genesis_entities(&self) -> Result<Entities, KernelError> {
+        let log = &self.state.event_log;
+        let created: HashSet<&CID> = log.iter().flat_map(|event| &event.new_entities).collect();
+        let candidates: HashSet<&CID> = self
+            .state
+            .entities
+            .keys()
+            .chain(self.state.tombstones.keys())
+            .chain(self.history.entity_ids())
+            .filter(|id| !created.contains(id))
+            .collect();
+
+        let mut entities = HashMap::new();
+        for id in candidates {
+            let touches: Vec<&Event> = log
+                .iter()
+                .filter(|event| event.updated_entities.contains(id) || event.deleted_entities.contains(id))
+                .collect();
+            let genesis = match touches.first() {
+                // Untouched entities are still at their genesis version in Σ.
+                None => self.state.entities.get(id).cloned(),
+                // A touched genesis entity was seeded into history before its
+                // first replacement; pruning drops that seed first.
+                Some(first) => match self.history.history(id) {
+                    versions if versions.len() > touches.len() => versions[0].entity().cloned(),
+                    _ => return Err(KernelError::HistoryUnavailable { entity: *id, lclock: first.lclock }),
+                },
+            };
+            if let Some(entity) = genesis {
+                entities.insert(*id, entity);
+            }
+        }
+        Ok(entities)
+    }
+
+    /// Applies the entity versions written by `event` to `state`, re-checking
+    /// the invariants `append_delta` enforced when the event was committed.
+    fn replay_event(&self, state: &mut SystemState, event: &Event) -> Result<(), KernelError> {
+        let mismatch = |invariant| KernelError::ReplayMismatch { event: event.id, invariant: Box::new(invariant) };
+
+        for id in &event.new_entities {
+            if state.tombstones.contains_key(id) {
+                return Err(mismatch(Invariant::EntityTombstoned(*id)));
+            }
+            if state.entities.contains_key(id) {
+                return Err(mismatch(Invariant::EntityAlreadyExists(*id)));
+            }
+            let entity = self.live_version_at(id, event.lclock)?;
+            state.entities.insert(*id, entity);
+        }
+
+        for id in &event.updated_entities {
+            let entity = self.live_version_at(id, event.lclock)?;
+            check_successor(state, &entity.header).map_err(mismatch)?;
+            state.entities.insert(*id, entity);
+        }
+
+        for id in &event.deleted_entities {
+            let tombstone = self.tombstone_at(id, event.lclock)?;
+            check_successor(state, &tombstone).map_err(mismatch)?;
+            state.entities.remove(id);
+            state.tombstones.insert(*id, tombstone);
+        }
+        Ok(())
+    }
+
+    /// The live version of `id` written at exactly `lclock`.
+    fn live_version_at(&self, id: &CID, lclock: u64) -> Result<Arc<Entity<Vec<u8>>>, KernelError> {
+        let recorded = match self.history.written_at(id, lclock) {
+            Some(EntityVersion::Live(entity)) => Some(Arc::clone(entity)),
+            Some(EntityVersion::Deleted(_)) => None,
+            None => self.state.entities.get(id).filter(|current| current.header.lclock == lclock).cloned(),
+        };
+        recorded.ok_or(KernelError::HistoryUnavailable { entity: *id, lclock })
+    }
+
+    /// The tombstone of `id` written at exactly `lclock`.
+    fn tombstone_at(&self, id: &CID, lclock: u64) -> Result<EntityHeader, KernelError> {
+        let recorded = match self.history.written_at(id, lclock) {
+            Some(EntityVersion::Deleted(header)) => Some(header.clone()),
+            Some(EntityVersion::Live(_)) => None,
+            None => self.state.tombstones.get(id).filter(|tombstone| tombstone.lclock == lclock).cloned(),
+        };
+        recorded.ok_or(KernelError::HistoryUnavailable { entity: *id, lclock })
+    }
+}
+
+/// `header` must be the next version of a live entity in `state`.
+fn check_successor(state: &SystemState, header: &EntityHeader) -> Result<(), Invariant> {
+    if state.tombstones.contains_key(&header.id) {
+        return Err(Invariant::EntityTombstoned(header.id));
+    }
+    match state.entities.get(&header.id) {
+        Some(prev) if header.version == prev.header.version + 1 => Ok(()),
+        Some(prev) => Err(Invariant::VersionNotMonotonic {
+            entity: header.id,
+            current: prev.header.version,
+            proposed: header.version,
+        }),
+        None => Err(Invariant::EntityNotFound(header.id)),
+    }
+}
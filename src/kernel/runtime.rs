@@ -37,6 +37,6 @@ impl<CP: CryptoProvider> Runtime<CP> for DefaultRuntime {
         _state: &SystemState,
         _cmd: &Command<C>,
     ) -> Result<StateDelta, KernelError> {
-        Ok(StateDelta { new_entities: Vec::new(), updated_entities: Vec::new() })
+        Ok(StateDelta::default())
     }
 } 
\ No newline at end of file
@@ -0,0 +1,383 @@
+//! Snapshots and event-log compaction (Kernel Spec §7.1.5).
+//!
+//! A `Snapshot` captures Σ (entities, tombstones, capabilities and their use
+//! counters) together with the replica's clocks as of a Lamport time. It is
+//! content-addressed: its `id` is the `CryptoProvider` hash of a canonical
+//! encoding, so any replica can check that a snapshot matches its contents.
+//! Snapshots may additionally be signed by the replica that took them.
+//!
+//! Once a snapshot is taken, `Kernel::compact` drops the events it covers from
+//! `SystemState.event_log`. A replica restarts with `Kernel::boot_from_snapshot`
+//! from the snapshot plus the events committed after it, each accompanied by
+//! the `StateDelta` it applied so the tail can be re-checked against Σ. Boot
+//! also checks the restored Σ against the state root the snapshot records, and
+//! every tail event against its content id.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::crypto::{CryptoError, CryptoProvider};
+use crate::error::KernelError;
+use crate::kernel::core::{Kernel, StateDelta, SystemState};
+use crate::kernel::merkle::{MerkleLog, TreeHash};
+use crate::kernel::receipts::ReceiptSigner;
+use crate::kernel::runtime::Runtime;
+use crate::kernel::state_tree::{StateRoot, StateTree};
+use crate::primitives::{
+    Capability, CidBytes, Entity, EntityHeader, Event, PublicKey, ReplicaID, Signature, VClock, CID,
+};
+use crate::types::AlgSuite;
+
+/// Content-addressed copy of Σ and the replica clocks at a Lamport time.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot {
+    /// Hash of `content_bytes` under `alg_suite`.
+    pub id: CID,
+    /// Replica that took the snapshot.
+    pub replica: ReplicaID,
+    /// Alg-suite tag used to derive `id`.
+    pub alg_suite: u8,
+    /// Lamport time of the snapshot; it covers every local event at or before it.
+    pub lclock: u64,
+    /// Clock epoch `lclock` and `vclock` belong to.
+    #[serde(default)]
+    pub epoch: u32,
+    /// The replica's vector clock as of `lclock`.
+    pub vclock: VClock,
+    /// Id of the last event the snapshot covers, if any.
+    pub last_event: Option<CID>,
+    /// Number of events in the log accumulator as of the snapshot.
+    #[serde(default)]
+    pub log_size: u64,
+    /// Peaks of the log accumulator at `log_size`, so appends continue after boot.
+    #[serde(default)]
+    pub log_peaks: Vec<TreeHash>,
+    /// Root of the state tree over the snapshot's contents, hashed under `alg_suite`.
+    #[serde(default)]
+    pub state_root: Option<StateRoot>,
+    /// Capabilities, mapping CID → Capability.
+    pub capabilities: HashMap<CID, Capability>,
+    /// Uses of use-limited capabilities, mapping CID → uses.
+    pub capability_uses: HashMap<CID, u64>,
+    /// Live entities, mapping CID → Entity.
+    pub entities: HashMap<CID, Arc<Entity<Vec<u8>>>>,
+    /// Tombstones of deleted entities, mapping CID → final header.
+    pub tombstones: HashMap<CID, EntityHeader>,
+    /// Replica signature over `signing_bytes`, if signed.
+    pub signature: Option<Signature>,
+}
+
+impl Snapshot {
+    /// Canonical byte encoding of every field except `id` and `signature`.
+    ///
+    /// Maps are encoded in key order, so equal snapshots always encode alike.
+    pub fn content_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"amulet/snapshot/v1");
+        bytes.extend_from_slice(&self.replica.0);
+        bytes.push(self.alg_suite);
+        bytes.extend_from_slice(&self.lclock.to_le_bytes());
+        bytes.extend_from_slice(&self.epoch.to_le_bytes());
+        put_optional_cid(&mut bytes, self.last_event.as_ref());
+        bytes.extend_from_slice(&self.log_size.to_le_bytes());
+        put_len(&mut bytes, self.log_peaks.len());
+        for peak in &self.log_peaks {
+            bytes.extend_from_slice(peak);
+        }
+        match &self.state_root {
+            Some(root) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&root.entities);
+                bytes.extend_from_slice(&root.capabilities);
+            }
+            None => bytes.push(0),
+        }
+
+        put_len(&mut bytes, self.vclock.0.len());
+        for (replica, lclock) in sorted(&self.vclock.0) {
+            bytes.extend_from_slice(&replica.0);
+            bytes.extend_from_slice(&lclock.to_le_bytes());
+        }
+
+        put_len(&mut bytes, self.capabilities.len());
+        for (id, capability) in sorted(&self.capabilities) {
+            let encoded = capability.id_hash_input();
+            bytes.extend_from_slice(&id.0);
+            put_len(&mut bytes, encoded.len());
+            bytes.extend_from_slice(&encoded);
+            bytes.extend_from_slice(&capability.signature.0);
+        }
+
+        put_len(&mut bytes, self.capability_uses.len());
+        for (id, uses) in sorted(&self.capability_uses) {
+            bytes.extend_from_slice(&id.0);
+            bytes.extend_from_slice(&uses.to_le_bytes());
+        }
+
+        put_len(&mut bytes, self.entities.len());
+        for (_, entity) in sorted(&self.entities) {
+            put_header(&mut bytes, &entity.header);
+            put_len(&mut bytes, entity.body.len());
+            bytes.extend_from_slice(&entity.body);
+        }
+
+        put_len(&mut bytes, self.tombstones.len());
+        for (_, tombstone) in sorted(&self.tombstones) {
+            put_header(&mut bytes, tombstone);
+        }
+        bytes
+    }
+
+    /// Bytes the replica signs: a domain tag followed by the content id.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = b"amulet/snapshot-signature/v1".to_vec();
+        bytes.extend_from_slice(&self.id.0);
+        bytes
+    }
+
+    /// Signs the snapshot with `signer`, replacing any previous signature.
+    pub fn sign(&mut self, signer: &dyn ReceiptSigner) -> Result<(), CryptoError> {
+        self.signature = Some(signer.sign(&self.signing_bytes())?);
+        Ok(())
+    }
+
+    /// Σ as captured by the snapshot, with an empty event log.
+    pub fn to_state(&self) -> SystemState {
+        SystemState {
+            capabilities: self.capabilities.clone(),
+            entities: self.entities.clone(),
+            event_log: Vec::new(),
+            capability_uses: self.capability_uses.clone(),
+            tombstones: self.tombstones.clone(),
+        }
+    }
+}
+
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn sorted_ids<'a>(ids: impl IntoIterator<Item = &'a CID>) -> Vec<CID> {
+    let mut ids: Vec<CID> = ids.into_iter().copied().collect();
+    ids.sort();
+    ids
+}
+
+pub(crate) fn put_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+pub(crate) fn put_optional_cid(bytes: &mut Vec<u8>, cid: Option<&CID>) {
+    match cid {
+        Some(cid) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&cid.0);
+        }
+        None => bytes.push(0),
+    }
+}
+
+pub(crate) fn put_header(bytes: &mut Vec<u8>, header: &EntityHeader) {
+    bytes.extend_from_slice(&header.id.0);
+    bytes.extend_from_slice(&header.version.to_le_bytes());
+    bytes.extend_from_slice(&header.lclock.to_le_bytes());
+    put_optional_cid(bytes, header.parent.as_ref());
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Takes an unsigned snapshot of Σ as of `lclock`, content-addressed under `alg_suite`.
+    ///
+    /// At or after the local clock the snapshot captures the live state,
+    /// including use counters replicated from other replicas. Earlier
+    /// snapshots are rebuilt from the event log (see `state_as_of_lclock`).
+    pub fn snapshot(&self, lclock: u64, alg_suite: AlgSuite) -> Result<Snapshot, KernelError> {
+        let replayed;
+        let (state, lclock, vclock) = if lclock >= self.local_lc {
+            (&self.state, self.local_lc, self.local_vc.clone())
+        } else {
+            // Rebuilt states hold a prefix of the log (see `state_as_of_lclock`).
+            replayed = self.state_as_of_lclock(lclock)?;
+            let vclock = match (replayed.event_log.last(), &self.base_snapshot) {
+                (Some(event), _) => event.vclock.clone(),
+                (None, Some(base)) => base.vclock.clone(),
+                (None, None) => VClock::default(),
+            };
+            (&replayed, lclock, vclock)
+        };
+        let last_event = match (state.event_log.last(), &self.base_snapshot) {
+            (Some(event), _) => Some(event.id),
+            (None, Some(base)) => base.last_event,
+            (None, None) => None,
+        };
+        let later_events = self.state.event_log.len() - state.event_log.len();
+        let log_size = self.log_tree.size().saturating_sub(later_events as u64);
+        self.snapshot_of(state, lclock, self.epoch, vclock, last_event, log_size, alg_suite)
+    }
+
+    /// Builds and content-addresses a snapshot of `state` with the given clocks
+    /// and the first `log_size` leaves of the log accumulator.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn snapshot_of(
+        &self,
+        state: &SystemState,
+        lclock: u64,
+        epoch: u32,
+        vclock: VClock,
+        last_event: Option<CID>,
+        log_size: u64,
+        alg_suite: AlgSuite,
+    ) -> Result<Snapshot, KernelError> {
+        let mut snapshot = Snapshot {
+            id: CidBytes([0; 32]),
+            replica: self.replica_id,
+            alg_suite: alg_suite as u8,
+            lclock,
+            epoch,
+            vclock,
+            last_event,
+            log_size,
+            log_peaks: self.log_tree.peaks(log_size)?,
+            state_root: Some(StateTree::from_state(&self.crypto_provider, alg_suite, state)?.root()),
+            capabilities: state.capabilities.clone(),
+            capability_uses: state.capability_uses.clone(),
+            entities: state.entities.clone(),
+            tombstones: state.tombstones.clone(),
+            signature: None,
+        };
+        snapshot.id = self.snapshot_id(&snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Recomputes the content id of `snapshot`.
+    pub fn snapshot_id(&self, snapshot: &Snapshot) -> Result<CID, KernelError> {
+        self.generate_cid(&snapshot.content_bytes(), snapshot.alg_suite)
+    }
+
+    /// Checks that `snapshot` matches its content id and carries a valid
+    /// signature by `replica_key`.
+    pub fn verify_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        replica_key: &PublicKey,
+        alg_suite: AlgSuite,
+    ) -> Result<(), KernelError> {
+        self.check_snapshot_id(snapshot)?;
+        let signature = snapshot
+            .signature
+            .as_ref()
+            .ok_or(KernelError::Crypto(CryptoError::InvalidSignature))?;
+        self.crypto_provider
+            .verify(&snapshot.signing_bytes(), signature, replica_key, alg_suite)
+            .map_err(KernelError::Crypto)
+    }
+
+    pub(crate) fn check_snapshot_id(&self, snapshot: &Snapshot) -> Result<(), KernelError> {
+        let computed = self.snapshot_id(snapshot)?;
+        if computed != snapshot.id {
+            return Err(KernelError::SnapshotMismatch { expected: snapshot.id, computed });
+        }
+        Ok(())
+    }
+
+    /// Drops every event covered by `snapshot` from the event log and keeps the
+    /// snapshot as the new replay base. Returns the number of events removed.
+    ///
+    /// The snapshot must match this replica's own state at `snapshot.lclock`.
+    pub fn compact(&mut self, snapshot: Snapshot) -> Result<usize, KernelError> {
+        self.check_snapshot_id(&snapshot)?;
+        let alg_suite = AlgSuite::try_from(snapshot.alg_suite)
+            .map_err(|_| KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(snapshot.alg_suite)))?;
+        let local = self.snapshot(snapshot.lclock, alg_suite)?;
+        if local.content_bytes() != snapshot.content_bytes() {
+            return Err(KernelError::SnapshotMismatch { expected: snapshot.id, computed: local.id });
+        }
+
+        let covered = self.state.event_log.iter().take_while(|event| event.lclock <= snapshot.lclock).count();
+        self.state.event_log.drain(..covered);
+        self.base_snapshot = Some(Arc::new(snapshot));
+        self.report_event_log_length();
+        Ok(covered)
+    }
+
+    /// Restores Σ and the clocks from `snapshot`, then re-applies `tail`: the
+    /// events committed after the snapshot, each with the delta it applied.
+    ///
+    /// Every tail delta must write exactly the entities its event lists and pass
+    /// the `append_delta` invariants, so the booted state matches the log.
+    pub fn boot_from_snapshot(mut self, snapshot: Snapshot, tail: &[(Event, StateDelta)]) -> Result<Self, KernelError> {
+        self.check_snapshot_id(&snapshot)?;
+        self.state = snapshot.to_state();
+        self.local_lc = snapshot.lclock;
+        self.epoch = snapshot.epoch;
+        self.local_vc = snapshot.vclock.clone();
+        self.history.clear();
+        self.log_tree = MerkleLog::from_peaks(self.log_tree.alg_suite(), snapshot.log_size, &snapshot.log_peaks)?;
+        self.rebuild_state_tree()?;
+        if let Some(expected) = snapshot.state_root {
+            let computed = if self.state_tree.alg_suite() as u8 == snapshot.alg_suite {
+                self.state_root()
+            } else {
+                let alg_suite = AlgSuite::try_from(snapshot.alg_suite)
+                    .map_err(|_| KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(snapshot.alg_suite)))?;
+                StateTree::from_state(&self.crypto_provider, alg_suite, &self.state)?.root()
+            };
+            if computed != expected {
+                return Err(KernelError::StateRootMismatch { expected: Box::new(expected), computed: Box::new(computed) });
+            }
+        }
+        self.chain_head = snapshot.last_event;
+        self.base_snapshot = Some(Arc::new(snapshot));
+
+        for (event, delta) in tail {
+            self.replay_committed(event, delta)?;
+        }
+        self.report_event_log_length();
+        Ok(self)
+    }
+
+    /// Re-applies a local event committed after the current state, with the
+    /// delta it applied (a snapshot tail or a recovered log record).
+    pub(crate) fn replay_committed(&mut self, event: &Event, delta: &StateDelta) -> Result<(), KernelError> {
+        let invalid = |reason: String| KernelError::InvalidSnapshotTail { event: event.id, reason };
+        if self.event_id(event)? != event.id {
+            return Err(invalid("id does not match the event's contents".to_string()));
+        }
+        if event.epoch != self.epoch {
+            return Err(invalid(format!("epoch {} differs from the snapshot epoch {}", event.epoch, self.epoch)));
+        }
+        if event.lclock <= self.local_lc {
+            return Err(invalid(format!("lclock {} does not advance past {}", event.lclock, self.local_lc)));
+        }
+        if event.prev_event != self.chain_head {
+            return Err(invalid(format!("chains to {:?} instead of {:?}", event.prev_event, self.chain_head)));
+        }
+        let matches = sorted_ids(delta.new_entities.iter().map(|e| &e.header.id)) == sorted_ids(&event.new_entities)
+            && sorted_ids(delta.updated_entities.iter().map(|e| &e.header.id)) == sorted_ids(&event.updated_entities)
+            && sorted_ids(delta.deleted_entities.iter().map(|h| &h.id)) == sorted_ids(&event.deleted_entities);
+        if !matches {
+            return Err(invalid("delta does not write the entities the event lists".to_string()));
+        }
+
+        self.append_delta(delta, event.lclock).map_err(|e| match e {
+            KernelError::InvariantViolation(invariant) => {
+                KernelError::ReplayMismatch { event: event.id, invariant: Box::new(invariant) }
+            }
+            other => other,
+        })?;
+        self.local_lc = event.lclock;
+        self.local_vc.merge_into(&event.vclock);
+        let state_tree = self.stage_state_tree(&StateDelta::default(), event.consumed_capability.as_ref())?;
+        self.record_capability_use(event);
+        self.state_tree = state_tree;
+        self.log_tree.append(&self.crypto_provider, &event.id)?;
+        self.state.event_log.push(event.clone());
+        self.chain_head = Some(event.id);
+        Ok(())
+    }
+}
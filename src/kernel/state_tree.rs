@@ -0,0 +1,512 @@
+//! Sparse Merkle commitments to Σ.
+//!
+//! The kernel keeps two sparse Merkle trees alongside `SystemState`: one keyed
+//! by entity CID, committing to every live entity and tombstone, and one keyed
+//! by capability CID, committing to every capability together with its use
+//! count. Their roots (`StateRoot`) let two replicas check that they agree on Σ
+//! by comparing 64 bytes, and let a light client check a single entity or
+//! capability against a root with a `StateProof`, without the kernel.
+//!
+//! Both trees are updated incrementally as events commit (`append_delta`,
+//! `apply`, merged capability uses); state placed into Σ directly, such as
+//! genesis entities and capabilities, is picked up by
+//! `Kernel::rebuild_state_tree`.
+//!
+//! The trees are binary tries over the 256 key bits, most significant bit
+//! first, in which a subtree holding a single leaf is replaced by that leaf.
+//! The shape therefore depends only on the set of keys, and proofs carry one
+//! sibling per bit of the longest prefix the key shares with another key.
+//! Leaves hash `0x00 || key || value hash`, interior nodes
+//! `0x01 || left || right`, and an empty subtree is all zeroes. Nodes are
+//! shared between versions, so cloning a tree is cheap and updates are staged
+//! on a copy before a commit swaps it in.
+
+use std::sync::Arc;
+
+use crate::crypto::{CryptoError, CryptoProvider};
+use crate::error::KernelError;
+use crate::kernel::core::{Kernel, StateDelta, SystemState};
+use crate::kernel::merkle::TreeHash;
+use crate::kernel::runtime::Runtime;
+use crate::kernel::snapshot::{put_header, put_len};
+use crate::primitives::{Capability, Entity, EntityHeader, CID};
+use crate::types::AlgSuite;
+
+/// Hash of an empty subtree, and so the root of an empty tree.
+pub const EMPTY_ROOT: TreeHash = [0; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Empty,
+    Leaf { key: CID, value: TreeHash, hash: TreeHash },
+    Internal { left: Arc<Node>, right: Arc<Node>, hash: TreeHash },
+}
+
+impl Node {
+    fn hash(&self) -> TreeHash {
+        match self {
+            Node::Empty => EMPTY_ROOT,
+            Node::Leaf { hash, .. } | Node::Internal { hash, .. } => *hash,
+        }
+    }
+}
+
+/// Sparse Merkle tree mapping CIDs to value hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseMerkleTree {
+    alg_suite: AlgSuite,
+    root: Arc<Node>,
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        Self::new(AlgSuite::CLASSIC)
+    }
+}
+
+impl SparseMerkleTree {
+    /// Creates an empty tree hashing under `alg_suite`.
+    pub fn new(alg_suite: AlgSuite) -> Self {
+        SparseMerkleTree { alg_suite, root: Arc::new(Node::Empty) }
+    }
+
+    /// Algorithm suite every node is hashed under.
+    pub fn alg_suite(&self) -> AlgSuite {
+        self.alg_suite
+    }
+
+    /// Root hash; `EMPTY_ROOT` for an empty tree.
+    pub fn root(&self) -> TreeHash {
+        self.root.hash()
+    }
+
+    /// Value hash stored under `key`, if any.
+    pub fn get(&self, key: &CID) -> Option<TreeHash> {
+        match self.find(key).1 {
+            Node::Leaf { key: found, value, .. } if found == key => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Stores `value` under `key`, or removes `key` when `value` is `None`.
+    pub fn update<CP: CryptoProvider>(
+        &mut self,
+        provider: &CP,
+        key: &CID,
+        value: Option<TreeHash>,
+    ) -> Result<(), KernelError> {
+        self.root = self.update_node(provider, &self.root, 0, key, value)?;
+        Ok(())
+    }
+
+    /// Proof of the value stored under `key`, or of its absence.
+    pub fn prove(&self, key: &CID) -> StateProof {
+        let (siblings, node) = self.find(key);
+        let leaf = match node {
+            Node::Leaf { key, value, .. } => Some((*key, *value)),
+            _ => None,
+        };
+        StateProof { siblings, leaf }
+    }
+
+    /// Walks towards `key`, returning the siblings passed (top-down) and the
+    /// empty subtree or leaf the walk ends at.
+    fn find(&self, key: &CID) -> (Vec<TreeHash>, &Node) {
+        let mut siblings = Vec::new();
+        let mut node = self.root.as_ref();
+        while let Node::Internal { left, right, .. } = node {
+            let (next, sibling) = if bit(key, siblings.len()) { (right, left) } else { (left, right) };
+            siblings.push(sibling.hash());
+            node = next.as_ref();
+        }
+        (siblings, node)
+    }
+
+    fn update_node<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        node: &Arc<Node>,
+        depth: usize,
+        key: &CID,
+        value: Option<TreeHash>,
+    ) -> Result<Arc<Node>, KernelError> {
+        match (node.as_ref(), value) {
+            (Node::Empty, None) => Ok(node.clone()),
+            (Node::Empty, Some(value)) => self.leaf(provider, key, value),
+            (Node::Leaf { key: existing, .. }, None) if existing == key => Ok(Arc::new(Node::Empty)),
+            (Node::Leaf { .. }, None) => Ok(node.clone()),
+            (Node::Leaf { key: existing, .. }, Some(value)) if existing == key => self.leaf(provider, key, value),
+            (Node::Leaf { key: existing, .. }, Some(value)) => {
+                let leaf = self.leaf(provider, key, value)?;
+                self.split(provider, (node.clone(), existing), (leaf, key), depth)
+            }
+            (Node::Internal { left, right, .. }, _) => {
+                if bit(key, depth) {
+                    let right = self.update_node(provider, right, depth + 1, key, value)?;
+                    self.join(provider, left.clone(), right)
+                } else {
+                    let left = self.update_node(provider, left, depth + 1, key, value)?;
+                    self.join(provider, left, right.clone())
+                }
+            }
+        }
+    }
+
+    /// The smallest subtree rooted at `depth` that separates two leaves.
+    fn split<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        existing: (Arc<Node>, &CID),
+        new: (Arc<Node>, &CID),
+        depth: usize,
+    ) -> Result<Arc<Node>, KernelError> {
+        let side = bit(new.1, depth);
+        if bit(existing.1, depth) == side {
+            let child = self.split(provider, existing, new, depth + 1)?;
+            let empty = Arc::new(Node::Empty);
+            return if side { self.internal(provider, empty, child) } else { self.internal(provider, child, empty) };
+        }
+        if side {
+            self.internal(provider, existing.0, new.0)
+        } else {
+            self.internal(provider, new.0, existing.0)
+        }
+    }
+
+    /// Joins two subtrees, lifting a lone leaf to keep the shape canonical.
+    fn join<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        left: Arc<Node>,
+        right: Arc<Node>,
+    ) -> Result<Arc<Node>, KernelError> {
+        match (left.as_ref(), right.as_ref()) {
+            (Node::Empty, Node::Empty | Node::Leaf { .. }) => Ok(right),
+            (Node::Leaf { .. }, Node::Empty) => Ok(left),
+            _ => self.internal(provider, left, right),
+        }
+    }
+
+    fn leaf<CP: CryptoProvider>(&self, provider: &CP, key: &CID, value: TreeHash) -> Result<Arc<Node>, KernelError> {
+        let hash = leaf_hash(provider, self.alg_suite, key, &value).map_err(KernelError::Crypto)?;
+        Ok(Arc::new(Node::Leaf { key: *key, value, hash }))
+    }
+
+    fn internal<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        left: Arc<Node>,
+        right: Arc<Node>,
+    ) -> Result<Arc<Node>, KernelError> {
+        let hash = node_hash(provider, self.alg_suite, &left.hash(), &right.hash()).map_err(KernelError::Crypto)?;
+        Ok(Arc::new(Node::Internal { left, right, hash }))
+    }
+}
+
+/// Proof of membership or non-membership of a key in a `SparseMerkleTree`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateProof {
+    /// Sibling hashes along the key's path, from the root down.
+    pub siblings: Vec<TreeHash>,
+    /// The leaf the path ends at, as `(key, value hash)`; `None` for an empty subtree.
+    /// A leaf under a different key proves the queried key absent.
+    pub leaf: Option<(CID, TreeHash)>,
+}
+
+impl StateProof {
+    /// Whether the proof shows `key` holding `value` in the tree with `root`,
+    /// or, for `None`, that `key` is absent from it.
+    pub fn verify<CP: CryptoProvider>(
+        &self,
+        provider: &CP,
+        alg_suite: AlgSuite,
+        root: &TreeHash,
+        key: &CID,
+        value: Option<&TreeHash>,
+    ) -> Result<bool, CryptoError> {
+        let depth = self.siblings.len();
+        if depth > 256 {
+            return Ok(false);
+        }
+        let mut hash = match (&self.leaf, value) {
+            (Some((found, stored)), Some(value)) if found == key && stored == value => {
+                leaf_hash(provider, alg_suite, found, stored)?
+            }
+            (Some((found, stored)), None) if found != key && (0..depth).all(|d| bit(found, d) == bit(key, d)) => {
+                leaf_hash(provider, alg_suite, found, stored)?
+            }
+            (None, None) => EMPTY_ROOT,
+            _ => return Ok(false),
+        };
+        for (d, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if bit(key, d) {
+                node_hash(provider, alg_suite, sibling, &hash)?
+            } else {
+                node_hash(provider, alg_suite, &hash, sibling)?
+            };
+        }
+        Ok(hash == *root)
+    }
+}
+
+/// Roots of the entity and capability trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct StateRoot {
+    pub entities: TreeHash,
+    pub capabilities: TreeHash,
+}
+
+/// Commitments to the entities (live and tombstoned) and capabilities of Σ.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateTree {
+    pub entities: SparseMerkleTree,
+    pub capabilities: SparseMerkleTree,
+}
+
+impl StateTree {
+    /// Creates empty trees hashing under `alg_suite`.
+    pub fn new(alg_suite: AlgSuite) -> Self {
+        StateTree { entities: SparseMerkleTree::new(alg_suite), capabilities: SparseMerkleTree::new(alg_suite) }
+    }
+
+    /// Builds the trees for `state` from scratch.
+    pub fn from_state<CP: CryptoProvider>(
+        provider: &CP,
+        alg_suite: AlgSuite,
+        state: &SystemState,
+    ) -> Result<Self, KernelError> {
+        let mut tree = StateTree::new(alg_suite);
+        for (id, entity) in &state.entities {
+            let value = entity_value_hash(provider, alg_suite, entity).map_err(KernelError::Crypto)?;
+            tree.entities.update(provider, id, Some(value))?;
+        }
+        for (id, tombstone) in &state.tombstones {
+            let value = tombstone_value_hash(provider, alg_suite, tombstone).map_err(KernelError::Crypto)?;
+            tree.entities.update(provider, id, Some(value))?;
+        }
+        for (id, capability) in &state.capabilities {
+            let uses = state.capability_uses.get(id).copied().unwrap_or(0);
+            let value = capability_value_hash(provider, alg_suite, capability, uses).map_err(KernelError::Crypto)?;
+            tree.capabilities.update(provider, id, Some(value))?;
+        }
+        Ok(tree)
+    }
+
+    /// Algorithm suite both trees hash under.
+    pub fn alg_suite(&self) -> AlgSuite {
+        self.entities.alg_suite()
+    }
+
+    /// Roots of both trees.
+    pub fn root(&self) -> StateRoot {
+        StateRoot { entities: self.entities.root(), capabilities: self.capabilities.root() }
+    }
+}
+
+/// Value hash of a live entity in the entity tree.
+pub fn entity_value_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    entity: &Entity<Vec<u8>>,
+) -> Result<TreeHash, CryptoError> {
+    let mut bytes = b"amulet/state/entity/v1".to_vec();
+    put_header(&mut bytes, &entity.header);
+    put_len(&mut bytes, entity.body.len());
+    bytes.extend_from_slice(&entity.body);
+    provider.hash(&bytes, alg_suite)
+}
+
+/// Value hash of a tombstone in the entity tree.
+pub fn tombstone_value_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    tombstone: &EntityHeader,
+) -> Result<TreeHash, CryptoError> {
+    let mut bytes = b"amulet/state/tombstone/v1".to_vec();
+    put_header(&mut bytes, tombstone);
+    provider.hash(&bytes, alg_suite)
+}
+
+/// Value hash of a capability that has been used `uses` times.
+pub fn capability_value_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    capability: &Capability,
+    uses: u64,
+) -> Result<TreeHash, CryptoError> {
+    let encoded = capability.id_hash_input();
+    let mut bytes = b"amulet/state/capability/v1".to_vec();
+    bytes.extend_from_slice(&capability.id.0);
+    put_len(&mut bytes, encoded.len());
+    bytes.extend_from_slice(&encoded);
+    bytes.extend_from_slice(&capability.signature.0);
+    bytes.extend_from_slice(&uses.to_le_bytes());
+    provider.hash(&bytes, alg_suite)
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Roots committing to the current entities and capabilities of Σ.
+    pub fn state_root(&self) -> StateRoot {
+        self.state_tree.root()
+    }
+
+    /// Proof of the live entity or tombstone stored under `id`, or of its absence,
+    /// against `state_root().entities`.
+    pub fn entity_proof(&self, id: &CID) -> StateProof {
+        self.state_tree.entities.prove(id)
+    }
+
+    /// Proof of the capability `id` and its use count, or of its absence,
+    /// against `state_root().capabilities`.
+    pub fn capability_proof(&self, id: &CID) -> StateProof {
+        self.state_tree.capabilities.prove(id)
+    }
+
+    /// Recomputes both trees from Σ. Needed after placing entities or
+    /// capabilities into Σ directly rather than through events.
+    pub fn rebuild_state_tree(&mut self) -> Result<(), KernelError> {
+        self.state_tree = StateTree::from_state(&self.crypto_provider, self.state_tree.alg_suite(), &self.state)?;
+        Ok(())
+    }
+
+    /// The trees after `delta` is materialised and `consumed_capability` used
+    /// once more, computed without touching Σ.
+    pub(crate) fn stage_state_tree(
+        &self,
+        delta: &StateDelta,
+        consumed_capability: Option<&CID>,
+    ) -> Result<StateTree, KernelError> {
+        let provider = &self.crypto_provider;
+        let alg_suite = self.state_tree.alg_suite();
+        let crypto = KernelError::Crypto;
+        let mut tree = self.state_tree.clone();
+        for entity in delta.new_entities.iter().chain(&delta.updated_entities) {
+            let value = entity_value_hash(provider, alg_suite, entity).map_err(crypto)?;
+            tree.entities.update(provider, &entity.header.id, Some(value))?;
+        }
+        for tombstone in &delta.deleted_entities {
+            let value = tombstone_value_hash(provider, alg_suite, tombstone).map_err(crypto)?;
+            tree.entities.update(provider, &tombstone.id, Some(value))?;
+        }
+        if let Some((id, capability)) = consumed_capability.and_then(|id| self.state.capabilities.get_key_value(id)) {
+            let uses = self.state.capability_uses.get(id).copied().unwrap_or(0).saturating_add(1);
+            let value = capability_value_hash(provider, alg_suite, capability, uses).map_err(crypto)?;
+            tree.capabilities.update(provider, id, Some(value))?;
+        }
+        Ok(tree)
+    }
+}
+
+fn bit(key: &CID, depth: usize) -> bool {
+    key.0[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn leaf_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    key: &CID,
+    value: &TreeHash,
+) -> Result<TreeHash, CryptoError> {
+    let mut bytes = Vec::with_capacity(65);
+    bytes.push(0x00);
+    bytes.extend_from_slice(&key.0);
+    bytes.extend_from_slice(value);
+    provider.hash(&bytes, alg_suite)
+}
+
+fn node_hash<CP: CryptoProvider>(
+    provider: &CP,
+    alg_suite: AlgSuite,
+    left: &TreeHash,
+    right: &TreeHash,
+) -> Result<TreeHash, CryptoError> {
+    let mut bytes = Vec::with_capacity(65);
+    bytes.push(0x01);
+    bytes.extend_from_slice(left);
+    bytes.extend_from_slice(right);
+    provider.hash(&bytes, alg_suite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::DigestCryptoProvider;
+    use crate::primitives::CidBytes;
+
+    const ALG: AlgSuite = AlgSuite::CLASSIC;
+
+    fn key(first: u8, last: u8) -> CID {
+        let mut bytes = [0u8; 32];
+        bytes[0] = first;
+        bytes[31] = last;
+        CidBytes(bytes)
+    }
+
+    fn value(i: u8) -> TreeHash {
+        [i; 32]
+    }
+
+    fn tree_of(entries: &[(CID, TreeHash)]) -> SparseMerkleTree {
+        let mut tree = SparseMerkleTree::new(ALG);
+        for (k, v) in entries {
+            tree.update(&DigestCryptoProvider, k, Some(*v)).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn shape_depends_only_on_contents() {
+        // Keys sharing long prefixes, one differing only in the last bit.
+        let entries = [(key(0x80, 0), value(1)), (key(0x80, 1), value(2)), (key(0x00, 7), value(3)), (key(0xff, 9), value(4))];
+        let tree = tree_of(&entries);
+        let mut reversed = entries;
+        reversed.reverse();
+        assert_eq!(tree, tree_of(&reversed));
+        assert_eq!(tree.get(&key(0x80, 1)), Some(value(2)));
+        assert_eq!(tree.get(&key(0x80, 2)), None);
+
+        // Removing a key restores the tree it was added to, down to the shape.
+        let mut grown = tree_of(&entries[..3]);
+        let before = grown.clone();
+        grown.update(&DigestCryptoProvider, &entries[3].0, Some(entries[3].1)).unwrap();
+        assert_eq!(grown.root(), tree.root());
+        grown.update(&DigestCryptoProvider, &entries[3].0, None).unwrap();
+        assert_eq!(grown, before);
+        for (k, _) in &entries[..3] {
+            grown.update(&DigestCryptoProvider, k, None).unwrap();
+        }
+        assert_eq!(grown.root(), EMPTY_ROOT);
+    }
+
+    #[test]
+    fn proofs_verify_membership_and_absence() {
+        let p = DigestCryptoProvider;
+        let empty = SparseMerkleTree::new(ALG);
+        assert_eq!(empty.prove(&key(1, 1)).verify(&p, ALG, &EMPTY_ROOT, &key(1, 1), None), Ok(true));
+
+        let entries = [(key(0x80, 0), value(1)), (key(0x80, 1), value(2)), (key(0x00, 7), value(3))];
+        let tree = tree_of(&entries);
+        let root = tree.root();
+        for (k, v) in &entries {
+            let proof = tree.prove(k);
+            assert_eq!(proof.verify(&p, ALG, &root, k, Some(v)), Ok(true));
+            assert_eq!(proof.verify(&p, ALG, &root, k, Some(&value(9))), Ok(false), "Wrong value");
+            assert_eq!(proof.verify(&p, ALG, &root, k, None), Ok(false), "Present key claimed absent");
+        }
+        assert_eq!(tree.prove(&key(0x80, 0)).siblings.len(), 256, "Keys differing in the last bit sit at full depth");
+
+        // Absent keys end at an empty subtree or at another key's leaf.
+        for absent in [key(0x40, 0), key(0x00, 8), key(0x80, 2)] {
+            let proof = tree.prove(&absent);
+            assert_eq!(proof.verify(&p, ALG, &root, &absent, None), Ok(true));
+            assert_eq!(proof.verify(&p, ALG, &root, &absent, Some(&value(1))), Ok(false));
+        }
+        let mut forged = tree.prove(&key(0x80, 0));
+        forged.siblings[0] = value(7);
+        assert_eq!(forged.verify(&p, ALG, &root, &key(0x80, 0), Some(&value(1))), Ok(false));
+    }
+}
@@ -0,0 +1,714 @@
+//! Persistent storage for the event log.
+//!
+//! A `Storage` backend receives a `LogRecord` for every change the kernel
+//! makes to Σ through events: each committed local event with the delta it
+//! applied, and each event merged from another replica (which carries
+//! capability uses and advances the clocks). `Kernel::recover` replays the
+//! stored records on startup.
+//!
+//! Records are written ahead: a command or merged event whose record cannot be
+//! stored is refused and Σ, the clocks and the replica chains are left
+//! untouched. An `apply_batch` is stored as a single record so a crash never
+//! leaves half a batch.
+//!
+//! `MemoryStorage` keeps records in memory, for tests and ephemeral replicas.
+//! `FileWal` is an append-only file. Every record is framed with its length, a
+//! CRC-32 of the length and a CRC-32 of its payload; on open, a torn write at
+//! the end of the file is detected and truncated, while a damaged record or
+//! length followed by further data is reported as corruption rather than
+//! silently dropped.
+//!
+//! Records hold what events write, not state placed into Σ directly: a
+//! recovering kernel must start from the same genesis entities and
+//! capabilities, or the snapshot it booted from, as when logging began.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::crypto::CryptoProvider;
+use crate::error::{ErrorCause, KernelError};
+use crate::kernel::core::{Kernel, StateDelta};
+use crate::kernel::runtime::Runtime;
+use crate::kernel::snapshot::{put_header, put_len, put_optional_cid};
+use crate::primitives::{CidBytes, Entity, EntityHeader, Event, ReplicaIdBytes, VClock, CID};
+
+/// A change to Σ, as stored by a `Storage` backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogRecord {
+    /// Local events committed together (one `apply`, or one `apply_batch`),
+    /// each with the delta it applied.
+    Committed(Vec<(Event, StateDelta)>),
+    /// An event merged from another replica.
+    Merged(Box<Event>),
+}
+
+/// Errors raised by a `Storage` backend.
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("Storage I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    /// Stored data is damaged somewhere other than a torn tail.
+    #[error("Log is corrupt at byte {offset}: {reason}")]
+    Corrupt { offset: u64, reason: String },
+}
+
+/// Durable home of the event log.
+pub trait Storage: Send + std::fmt::Debug {
+    /// Appends `record`. Once this returns it survives a restart, subject to
+    /// the backend's sync policy.
+    fn append(&mut self, record: &LogRecord) -> Result<(), StorageError>;
+
+    /// Flushes every appended record to stable storage.
+    fn sync(&mut self) -> Result<(), StorageError>;
+
+    /// Every stored record, in append order.
+    fn records(&mut self) -> Result<Vec<LogRecord>, StorageError>;
+}
+
+/// Storage that lives as long as the process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStorage {
+    records: Vec<LogRecord>,
+}
+
+impl MemoryStorage {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn append(&mut self, record: &LogRecord) -> Result<(), StorageError> {
+        self.records.push(record.clone());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn records(&mut self) -> Result<Vec<LogRecord>, StorageError> {
+        Ok(self.records.clone())
+    }
+}
+
+/// When `FileWal` forces appended records to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FsyncPolicy {
+    /// After every record: nothing acknowledged is lost on power failure.
+    #[default]
+    Always,
+    /// After every `n` records; up to `n - 1` acknowledged records may be lost.
+    Every(usize),
+    /// Only on `Storage::sync`; the operating system flushes otherwise.
+    Manual,
+}
+
+/// What `FileWal::open` found in an existing log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Intact records in the log.
+    pub records: usize,
+    /// Bytes of a torn final record that were cut off.
+    pub truncated_bytes: u64,
+}
+
+const MAGIC: &[u8; 8] = b"AMWAL\0\0\x01";
+const FRAME_HEADER: u64 = 12;
+
+/// Append-only write-ahead log in a single file.
+///
+/// Layout: an 8-byte magic, then records framed as
+/// `len: u32 LE || crc32(len): u32 LE || crc32(payload): u32 LE || payload`.
+#[derive(Debug)]
+pub struct FileWal {
+    file: File,
+    path: PathBuf,
+    policy: FsyncPolicy,
+    /// Offset just past the last intact record.
+    end: u64,
+    unsynced: usize,
+    recovery: Recovery,
+}
+
+impl FileWal {
+    /// Opens the log at `path`, creating it if missing. A torn final record is
+    /// truncated; other damage fails with `StorageError::Corrupt`.
+    pub fn open(path: impl AsRef<Path>, policy: FsyncPolicy) -> Result<Self, StorageError> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let mut wal = FileWal { file, path, policy, end: 0, unsynced: 0, recovery: Recovery::default() };
+        let (records, truncated_bytes) = wal.scan()?;
+        wal.recovery = Recovery { records: records.len(), truncated_bytes };
+        Ok(wal)
+    }
+
+    /// Path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// What opening the log found.
+    pub fn recovery(&self) -> Recovery {
+        self.recovery
+    }
+
+    /// Reads every intact record, repairing the file first if it is new or
+    /// ends in a torn record. Returns the records and the bytes truncated.
+    fn scan(&mut self) -> Result<(Vec<LogRecord>, u64), StorageError> {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+        let total = bytes.len() as u64;
+
+        if bytes.len() < MAGIC.len() && MAGIC.starts_with(&bytes) {
+            // New file, or a crash while writing the magic.
+            self.file.set_len(0)?;
+            self.file.seek(SeekFrom::Start(0))?;
+            self.file.write_all(MAGIC)?;
+            self.file.sync_all()?;
+            self.end = MAGIC.len() as u64;
+            return Ok((Vec::new(), total));
+        }
+        if !bytes.starts_with(MAGIC) {
+            return Err(StorageError::Corrupt { offset: 0, reason: "not an amulet write-ahead log".to_string() });
+        }
+
+        let mut records = Vec::new();
+        let mut offset = MAGIC.len() as u64;
+        while offset < total {
+            let at = offset as usize;
+            if total - offset < FRAME_HEADER {
+                break;
+            }
+            let len_bytes = &bytes[at..at + 4];
+            let len_checksum = u32::from_le_bytes(bytes[at + 4..at + 8].try_into().expect("4 bytes"));
+            let checksum = u32::from_le_bytes(bytes[at + 8..at + 12].try_into().expect("4 bytes"));
+            // A complete header is never torn, so a bad length is damage
+            // wherever it sits; trusting it could truncate later records.
+            if crc32(len_bytes) != len_checksum {
+                return Err(StorageError::Corrupt { offset, reason: "record length checksum mismatch".to_string() });
+            }
+            let len = u32::from_le_bytes(len_bytes.try_into().expect("4 bytes")) as u64;
+            let frame_end = offset + FRAME_HEADER + len;
+            if frame_end > total {
+                // The length is intact and runs past the end of the file: the
+                // final record was cut off while being written.
+                break;
+            }
+            let payload = &bytes[at + FRAME_HEADER as usize..frame_end as usize];
+            if crc32(payload) != checksum {
+                if frame_end == total {
+                    break;
+                }
+                return Err(StorageError::Corrupt { offset, reason: "record checksum mismatch".to_string() });
+            }
+            let record = decode(payload).map_err(|reason| StorageError::Corrupt { offset, reason })?;
+            records.push(record);
+            offset = frame_end;
+        }
+
+        if offset < total {
+            self.file.set_len(offset)?;
+            self.file.sync_all()?;
+        }
+        self.end = offset;
+        Ok((records, total - offset))
+    }
+}
+
+impl Storage for FileWal {
+    fn append(&mut self, record: &LogRecord) -> Result<(), StorageError> {
+        let frame = frame(&encode(record));
+        let start = self.end;
+        self.file.seek(SeekFrom::Start(start))?;
+        if let Err(error) = self.file.write_all(&frame) {
+            // Do not leave a partial frame for later records to follow.
+            let _ = self.file.set_len(start);
+            return Err(error.into());
+        }
+        self.end += frame.len() as u64;
+        self.unsynced += 1;
+        let synced = match self.policy {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Every(n) if self.unsynced >= n => self.sync(),
+            FsyncPolicy::Every(_) | FsyncPolicy::Manual => Ok(()),
+        };
+        if synced.is_err() {
+            // The caller treats the record as never written, so it must not
+            // reappear on recovery.
+            let _ = self.file.set_len(start);
+            self.end = start;
+            self.unsynced -= 1;
+        }
+        synced
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    fn records(&mut self) -> Result<Vec<LogRecord>, StorageError> {
+        Ok(self.scan()?.0)
+    }
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Replays every record in the attached storage onto the current state,
+    /// e.g. right after constructing the kernel on startup. Without storage
+    /// this is a no-op.
+    ///
+    /// The kernel must hold the state logging started from: the same genesis
+    /// entities and capabilities, or the snapshot it booted from.
+    pub fn recover(mut self) -> Result<Self, KernelError> {
+        let Some(storage) = self.storage.take() else { return Ok(self) };
+        let records = lock(&storage)?.records()?;
+        for record in records {
+            match record {
+                LogRecord::Committed(events) => {
+                    for (event, delta) in &events {
+                        self.replay_committed(event, delta)?;
+                    }
+                }
+                LogRecord::Merged(event) => self.process_incoming_event(&event)?,
+            }
+        }
+        self.storage = Some(storage);
+        self.report_event_log_length();
+        Ok(self)
+    }
+}
+
+/// Locks a storage backend shared with other kernel clones.
+pub(crate) fn lock(storage: &Mutex<dyn Storage>) -> Result<MutexGuard<'_, dyn Storage + 'static>, KernelError> {
+    storage.lock().map_err(|_| KernelError::Storage(ErrorCause::msg("storage lock poisoned by a panic")))
+}
+
+fn encode(record: &LogRecord) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match record {
+        LogRecord::Committed(events) => {
+            bytes.push(1);
+            put_len(&mut bytes, events.len());
+            for (event, delta) in events {
+                put_event(&mut bytes, event);
+                put_delta(&mut bytes, delta);
+            }
+        }
+        LogRecord::Merged(event) => {
+            bytes.push(2);
+            put_event(&mut bytes, event);
+        }
+    }
+    bytes
+}
+
+fn decode(payload: &[u8]) -> Result<LogRecord, String> {
+    let mut reader = Reader { bytes: payload, at: 0 };
+    let record = match reader.u8()? {
+        1 => {
+            let count = reader.len()?;
+            let mut events = Vec::with_capacity(count.min(1024));
+            for _ in 0..count {
+                events.push((reader.event()?, reader.delta()?));
+            }
+            LogRecord::Committed(events)
+        }
+        2 => LogRecord::Merged(Box::new(reader.event()?)),
+        tag => return Err(format!("unknown record tag {tag}")),
+    };
+    if reader.at != payload.len() {
+        return Err(format!("{} trailing bytes", payload.len() - reader.at));
+    }
+    Ok(record)
+}
+
+fn put_cids(bytes: &mut Vec<u8>, cids: &[CID]) {
+    put_len(bytes, cids.len());
+    for cid in cids {
+        bytes.extend_from_slice(&cid.0);
+    }
+}
+
+fn put_entity(bytes: &mut Vec<u8>, entity: &Entity<Vec<u8>>) {
+    put_header(bytes, &entity.header);
+    put_len(bytes, entity.body.len());
+    bytes.extend_from_slice(&entity.body);
+}
+
+fn put_event(bytes: &mut Vec<u8>, event: &Event) {
+    bytes.extend_from_slice(&event.id.0);
+    bytes.push(event.alg_suite);
+    bytes.extend_from_slice(&event.replica.0);
+    bytes.extend_from_slice(&event.caused_by.0);
+    bytes.extend_from_slice(&event.lclock.to_le_bytes());
+    bytes.extend_from_slice(&event.epoch.to_le_bytes());
+    let mut vclock: Vec<_> = event.vclock.0.iter().collect();
+    vclock.sort();
+    put_len(bytes, vclock.len());
+    for (replica, lclock) in vclock {
+        bytes.extend_from_slice(&replica.0);
+        bytes.extend_from_slice(&lclock.to_le_bytes());
+    }
+    put_cids(bytes, &event.new_entities);
+    put_cids(bytes, &event.updated_entities);
+    put_cids(bytes, &event.deleted_entities);
+    put_optional_cid(bytes, event.consumed_capability.as_ref());
+    put_optional_cid(bytes, event.prev_event.as_ref());
+    put_len(bytes, event.reserved.len());
+    bytes.extend_from_slice(&event.reserved);
+}
+
+fn put_delta(bytes: &mut Vec<u8>, delta: &StateDelta) {
+    put_len(bytes, delta.new_entities.len());
+    for entity in &delta.new_entities {
+        put_entity(bytes, entity);
+    }
+    put_len(bytes, delta.updated_entities.len());
+    for entity in &delta.updated_entities {
+        put_entity(bytes, entity);
+    }
+    put_len(bytes, delta.deleted_entities.len());
+    for tombstone in &delta.deleted_entities {
+        put_header(bytes, tombstone);
+    }
+}
+
+/// Cursor over an encoded record; errors name what could not be read.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let slice = self
+            .bytes
+            .get(self.at..self.at.saturating_add(n))
+            .ok_or_else(|| format!("record ends at byte {} inside a {n}-byte field", self.bytes.len()))?;
+        self.at += n;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().expect("take returns N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        self.u32().map(|len| len as usize)
+    }
+
+    fn cid(&mut self) -> Result<CID, String> {
+        self.array().map(CidBytes)
+    }
+
+    fn optional_cid(&mut self) -> Result<Option<CID>, String> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => self.cid().map(Some),
+            flag => Err(format!("invalid presence flag {flag}")),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.len()?;
+        self.take(len).map(<[u8]>::to_vec)
+    }
+
+    fn cids(&mut self) -> Result<Vec<CID>, String> {
+        let count = self.len()?;
+        (0..count).map(|_| self.cid()).collect()
+    }
+
+    fn header(&mut self) -> Result<EntityHeader, String> {
+        Ok(EntityHeader { id: self.cid()?, version: self.u64()?, lclock: self.u64()?, parent: self.optional_cid()? })
+    }
+
+    fn entity(&mut self) -> Result<Entity<Vec<u8>>, String> {
+        Ok(Entity { header: self.header()?, body: self.bytes()? })
+    }
+
+    fn event(&mut self) -> Result<Event, String> {
+        let id = self.cid()?;
+        let alg_suite = self.u8()?;
+        let replica = self.array().map(ReplicaIdBytes)?;
+        let caused_by = self.cid()?;
+        let lclock = self.u64()?;
+        let epoch = self.u32()?;
+        let mut vclock = VClock::default();
+        for _ in 0..self.len()? {
+            let replica = self.array().map(ReplicaIdBytes)?;
+            vclock.0.insert(replica, self.u64()?);
+        }
+        Ok(Event {
+            id,
+            alg_suite,
+            replica,
+            caused_by,
+            lclock,
+            epoch,
+            vclock,
+            new_entities: self.cids()?,
+            updated_entities: self.cids()?,
+            deleted_entities: self.cids()?,
+            consumed_capability: self.optional_cid()?,
+            prev_event: self.optional_cid()?,
+            reserved: self.bytes()?,
+        })
+    }
+
+    fn delta(&mut self) -> Result<StateDelta, String> {
+        let new_count = self.len()?;
+        let new_entities = (0..new_count).map(|_| self.entity()).collect::<Result<_, _>>()?;
+        let updated_count = self.len()?;
+        let updated_entities = (0..updated_count).map(|_| self.entity()).collect::<Result<_, _>>()?;
+        let deleted_count = self.len()?;
+        let deleted_entities = (0..deleted_count).map(|_| self.header()).collect::<Result<_, _>>()?;
+        Ok(StateDelta { new_entities, updated_entities, deleted_entities })
+    }
+}
+
+/// Frames `payload` for `FileWal`.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + FRAME_HEADER as usize);
+    put_len(&mut frame, payload.len());
+    frame.extend_from_slice(&crc32(&frame[..4]).to_le_bytes());
+    frame.extend_from_slice(&crc32(payload).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// CRC-32 (IEEE 802.3, reflected), as used by zlib and gzip.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn cid(byte: u8) -> CID {
+        CidBytes([byte; 32])
+    }
+
+    fn entity(byte: u8, version: u64) -> Entity<Vec<u8>> {
+        Entity { header: EntityHeader { id: cid(byte), version, lclock: version, parent: Some(cid(9)) }, body: vec![byte; 3] }
+    }
+
+    fn event(lclock: u64) -> Event {
+        let mut vclock = VClock::default();
+        vclock.0.insert(ReplicaIdBytes([1; 16]), lclock);
+        vclock.0.insert(ReplicaIdBytes([2; 16]), 4);
+        Event {
+            id: cid(lclock as u8),
+            alg_suite: 1,
+            replica: ReplicaIdBytes([1; 16]),
+            caused_by: cid(40),
+            lclock,
+            epoch: 2,
+            vclock,
+            new_entities: vec![cid(1)],
+            updated_entities: vec![cid(2)],
+            deleted_entities: vec![cid(3)],
+            consumed_capability: Some(cid(50)),
+            prev_event: (lclock > 1).then(|| cid(lclock as u8 - 1)),
+            reserved: vec![7],
+        }
+    }
+
+    fn committed(lclock: u64) -> LogRecord {
+        let delta = StateDelta {
+            new_entities: vec![entity(1, 1)],
+            updated_entities: vec![entity(2, 2)],
+            deleted_entities: vec![EntityHeader { id: cid(3), version: 4, lclock, parent: None }],
+        };
+        LogRecord::Committed(vec![(event(lclock), delta)])
+    }
+
+    fn len(path: &Path) -> u64 {
+        fs::metadata(path).unwrap().len()
+    }
+
+    #[test]
+    fn records_round_trip_through_the_codec() {
+        let records = [committed(1), LogRecord::Merged(Box::new(event(2))), LogRecord::Committed(Vec::new())];
+        for record in records {
+            assert_eq!(decode(&encode(&record)), Ok(record));
+        }
+        let mut bytes = encode(&committed(1));
+        bytes.push(0);
+        assert!(decode(&bytes).is_err(), "Trailing bytes are rejected");
+        bytes.truncate(bytes.len() - 2);
+        assert!(decode(&bytes).is_err(), "Short records are rejected");
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn file_wal_reopens_with_its_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.wal");
+        let records = vec![committed(1), LogRecord::Merged(Box::new(event(2))), committed(3)];
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(wal.recovery(), Recovery::default());
+        for record in &records {
+            wal.append(record).unwrap();
+        }
+        assert_eq!(wal.records().unwrap(), records);
+        drop(wal);
+
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(wal.recovery(), Recovery { records: 3, truncated_bytes: 0 });
+        assert_eq!(wal.records().unwrap(), records);
+        assert_eq!(wal.path(), path);
+    }
+
+    #[test]
+    fn file_wal_truncates_a_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.wal");
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        wal.append(&committed(1)).unwrap();
+        drop(wal);
+        let intact = len(&path);
+
+        // A frame cut off part-way through its payload.
+        let mut frame = frame(&encode(&committed(2)));
+        frame.truncate(frame.len() / 2);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&frame).unwrap();
+
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(wal.recovery(), Recovery { records: 1, truncated_bytes: frame.len() as u64 });
+        assert_eq!(len(&path), intact);
+        wal.append(&committed(2)).unwrap();
+        assert_eq!(wal.records().unwrap(), vec![committed(1), committed(2)]);
+        drop(wal);
+
+        // A complete final frame whose payload was not fully written.
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(wal.recovery().records, 1);
+        assert!(wal.recovery().truncated_bytes > 0);
+        assert_eq!(wal.records().unwrap(), vec![committed(1)]);
+
+        // A header cut short.
+        drop(wal);
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[3, 0]).unwrap();
+        let wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        assert_eq!(wal.recovery(), Recovery { records: 1, truncated_bytes: 2 });
+    }
+
+    #[test]
+    fn file_wal_reports_damage_before_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.wal");
+        let mut wal = FileWal::open(&path, FsyncPolicy::Always).unwrap();
+        wal.append(&committed(1)).unwrap();
+        wal.append(&committed(2)).unwrap();
+        drop(wal);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[MAGIC.len() + FRAME_HEADER as usize] ^= 0xFF;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            FileWal::open(&path, FsyncPolicy::Always),
+            Err(StorageError::Corrupt { offset, .. }) if offset == MAGIC.len() as u64
+        ));
+        assert_eq!(len(&path), bytes.len() as u64, "Corrupt logs are left for inspection");
+
+        // A damaged length in a middle record is corruption, not a torn tail,
+        // even when it points past the end of the file.
+        let mut wal = FileWal::open(dir.path().join("middle.wal"), FsyncPolicy::Always).unwrap();
+        for lclock in 1..=3 {
+            wal.append(&committed(lclock)).unwrap();
+        }
+        drop(wal);
+        let path = dir.path().join("middle.wal");
+        let mut bytes = fs::read(&path).unwrap();
+        let second = MAGIC.len() + frame(&encode(&committed(1))).len();
+        bytes[second + 3] ^= 0x80;
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            FileWal::open(&path, FsyncPolicy::Always),
+            Err(StorageError::Corrupt { offset, .. }) if offset == second as u64
+        ));
+        assert_eq!(len(&path), bytes.len() as u64);
+
+        fs::write(&path, b"not a log at all").unwrap();
+        assert!(matches!(FileWal::open(&path, FsyncPolicy::Always), Err(StorageError::Corrupt { offset: 0, .. })));
+    }
+
+    #[test]
+    fn file_wal_repairs_a_partial_magic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.wal");
+        fs::write(&path, &MAGIC[..3]).unwrap();
+        let mut wal = FileWal::open(&path, FsyncPolicy::Manual).unwrap();
+        assert_eq!(wal.recovery(), Recovery { records: 0, truncated_bytes: 3 });
+        wal.append(&committed(1)).unwrap();
+        wal.sync().unwrap();
+        drop(wal);
+        assert_eq!(FileWal::open(&path, FsyncPolicy::Always).unwrap().records().unwrap(), vec![committed(1)]);
+    }
+
+    #[test]
+    fn file_wal_syncs_by_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut wal = FileWal::open(dir.path().join("every.wal"), FsyncPolicy::Every(2)).unwrap();
+        wal.append(&committed(1)).unwrap();
+        assert_eq!(wal.unsynced, 1);
+        wal.append(&committed(2)).unwrap();
+        assert_eq!(wal.unsynced, 0);
+
+        let mut wal = FileWal::open(dir.path().join("manual.wal"), FsyncPolicy::Manual).unwrap();
+        wal.append(&committed(1)).unwrap();
+        wal.append(&committed(2)).unwrap();
+        assert_eq!(wal.unsynced, 2);
+        wal.sync().unwrap();
+        assert_eq!(wal.unsynced, 0);
+
+        let mut wal = FileWal::open(dir.path().join("always.wal"), FsyncPolicy::default()).unwrap();
+        wal.append(&committed(1)).unwrap();
+        assert_eq!(wal.unsynced, 0);
+    }
+
+    #[test]
+    fn memory_storage_keeps_records_in_order() {
+        let mut storage = MemoryStorage::new();
+        storage.append(&committed(1)).unwrap();
+        storage.append(&LogRecord::Merged(Box::new(event(2)))).unwrap();
+        storage.sync().unwrap();
+        assert_eq!(storage.records().unwrap(), vec![committed(1), LogRecord::Merged(Box::new(event(2)))]);
+    }
+}
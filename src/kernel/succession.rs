@@ -0,0 +1,211 @@
+//! Replica succession at Lamport overflow (Kernel Spec §7.1.5).
+//!
+//! A replica whose Lamport clock reaches `u64::MAX` can no longer apply
+//! commands. Instead of dying it hands off: `Kernel::hand_off` produces a
+//! final `Handoff` record naming a successor `ReplicaID` and carrying a
+//! snapshot of its state, after which the replica refuses commands with
+//! `KernelError::ReplicaRetired`.
+//!
+//! The successor boots from the handoff into the next clock epoch. Lamport
+//! times order by `(epoch, lclock)`, so the successor restarts `local_lc` from
+//! zero without ordering any event before its predecessor's. Everything else
+//! carries over: the predecessor's vector clock, extended with an entry for the
+//! successor, and every entity and tombstone with the lclock it was written at.
+//! Peers admit the successor with `Kernel::accept_handoff`. Merging one of its
+//! events moves a peer into the later epoch as well, while the peer's
+//! `local_lc` keeps growing as usual, so the peer's next event orders after
+//! it. Events claiming an epoch that neither this replica has reached nor a
+//! verified handoff admitted their replica to are refused with `UnadmittedEpoch`.
+//!
+//! Capability bounds in Lamport time (`expiry_lc` and `Caveat::BeforeLc`) do not
+//! name an epoch and cannot be carried into one. `hand_off` is therefore refused
+//! while any bound has not yet passed, and the successor drops the capabilities
+//! whose bounds have: they no longer authorize anything. The successor's event
+//! log and version history restart from a base snapshot of the inherited state.
+
+use std::sync::Arc;
+
+use crate::crypto::{CryptoError, CryptoProvider};
+use crate::error::KernelError;
+use crate::kernel::core::{Kernel, SystemState};
+use crate::kernel::receipts::ReceiptSigner;
+use crate::kernel::runtime::Runtime;
+use crate::kernel::snapshot::Snapshot;
+use crate::kernel::state_tree::StateTree;
+use crate::primitives::{Capability, Caveat, PublicKey, ReplicaID, Signature};
+use crate::types::AlgSuite;
+
+/// Final record of a retiring replica, naming its successor.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Handoff {
+    /// The retiring replica.
+    pub predecessor: ReplicaID,
+    /// The replica that continues from the predecessor's state.
+    pub successor: ReplicaID,
+    /// Clock epoch the successor starts in; one past the snapshot's epoch.
+    pub epoch: u32,
+    /// The predecessor's final state.
+    pub snapshot: Snapshot,
+    /// Predecessor signature over `signing_bytes`, if signed.
+    pub signature: Option<Signature>,
+}
+
+impl Handoff {
+    /// Deterministic byte encoding of every field except the signature. The
+    /// snapshot is bound by its content id.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"amulet/handoff/v1");
+        bytes.extend_from_slice(&self.predecessor.0);
+        bytes.extend_from_slice(&self.successor.0);
+        bytes.extend_from_slice(&self.epoch.to_le_bytes());
+        bytes.extend_from_slice(&self.snapshot.id.0);
+        bytes
+    }
+
+    /// Signs the handoff with `signer`, replacing any previous signature.
+    pub fn sign(&mut self, signer: &dyn ReceiptSigner) -> Result<(), CryptoError> {
+        self.signature = Some(signer.sign(&self.signing_bytes())?);
+        Ok(())
+    }
+}
+
+impl<CP, R> Kernel<CP, R>
+where
+    CP: CryptoProvider + Clone,
+    R: Runtime<CP> + Clone + std::fmt::Debug,
+{
+    /// Retires this replica in favour of `successor` and returns the unsigned
+    /// handoff record, with a snapshot content-addressed under `alg_suite`.
+    ///
+    /// Normally called once `apply` fails with `LamportClockExhausted`, but a
+    /// replica may also be retired early, as long as every capability bounded
+    /// in Lamport time has expired; otherwise the handoff is refused with
+    /// `InvalidHandoff`. Afterwards every command is refused with
+    /// `ReplicaRetired`; incoming events are still merged.
+    pub fn hand_off(&mut self, successor: ReplicaID, alg_suite: AlgSuite) -> Result<Handoff, KernelError> {
+        if let Some(successor) = self.successor {
+            return Err(KernelError::ReplicaRetired { successor });
+        }
+        for (id, cap) in &self.state.capabilities {
+            if let Some(bound) = lamport_bounds(cap).find(|bound| self.local_lc < *bound) {
+                return Err(KernelError::InvalidHandoff(format!(
+                    "capability {:?} is bounded by Lamport time {}, which has not passed",
+                    id, bound
+                )));
+            }
+        }
+        let epoch = self.epoch.checked_add(1).ok_or(KernelError::LamportClockExhausted)?;
+        let snapshot = self.snapshot(u64::MAX, alg_suite)?;
+        self.successor = Some(successor);
+        Ok(Handoff { predecessor: self.replica_id, successor, epoch, snapshot, signature: None })
+    }
+
+    /// Checks that `handoff` carries an intact snapshot and a valid signature
+    /// by the predecessor's `predecessor_key`.
+    pub fn verify_handoff(
+        &self,
+        handoff: &Handoff,
+        predecessor_key: &PublicKey,
+        alg_suite: AlgSuite,
+    ) -> Result<(), KernelError> {
+        self.check_snapshot_id(&handoff.snapshot)?;
+        let signature = handoff
+            .signature
+            .as_ref()
+            .ok_or(KernelError::Crypto(CryptoError::InvalidSignature))?;
+        self.crypto_provider
+            .verify(&handoff.signing_bytes(), signature, predecessor_key, alg_suite)
+            .map_err(KernelError::Crypto)
+    }
+
+    /// Admits the successor named by `handoff` after verifying it as in
+    /// `verify_handoff`: its events in the handoff's epoch are merged from then
+    /// on. This replica's epoch, clocks and state are left as they are until
+    /// it merges one of them.
+    pub fn accept_handoff(
+        &mut self,
+        handoff: &Handoff,
+        predecessor_key: &PublicKey,
+        alg_suite: AlgSuite,
+    ) -> Result<(), KernelError> {
+        check_handoff(handoff)?;
+        self.verify_handoff(handoff, predecessor_key, alg_suite)?;
+        self.replica_epochs.insert(handoff.successor, handoff.epoch);
+        Ok(())
+    }
+
+    /// Boots this replica as the successor named by `handoff`: restores the
+    /// predecessor's snapshot and enters the handoff's epoch.
+    ///
+    /// Signatures are not checked here; call `verify_handoff` first.
+    pub fn boot_as_successor(self, handoff: &Handoff) -> Result<Self, KernelError> {
+        if handoff.successor != self.replica_id {
+            return Err(KernelError::InvalidHandoff(format!("successor is {:?}, not this replica", handoff.successor)));
+        }
+        check_handoff(handoff)?;
+        let mut kernel = self.boot_from_snapshot(handoff.snapshot.clone(), &[])?;
+        // The successor starts a hash chain of its own.
+        kernel.chain_head = None;
+        kernel.enter_epoch(handoff.epoch, handoff.snapshot.alg_suite)?;
+        Ok(kernel)
+    }
+
+    /// Moves the replica into clock epoch `epoch`, compacting Σ into a new base
+    /// snapshot content-addressed under `alg_suite`. Nothing changes on error.
+    pub(crate) fn enter_epoch(&mut self, epoch: u32, alg_suite: u8) -> Result<(), KernelError> {
+        let alg_suite = AlgSuite::try_from(alg_suite)
+            .map_err(|_| KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(alg_suite)))?;
+
+        // `hand_off` only hands over Lamport bounds that have passed.
+        let mut state = SystemState {
+            entities: self.state.entities.clone(),
+            tombstones: self.state.tombstones.clone(),
+            ..SystemState::default()
+        };
+        for (id, cap) in &self.state.capabilities {
+            if lamport_bounds(cap).next().is_some() {
+                tracing::info!(capability = ?id, "dropping capability whose Lamport bound passed in an earlier epoch");
+                continue;
+            }
+            state.capabilities.insert(*id, cap.clone());
+            if let Some(uses) = self.state.capability_uses.get(id) {
+                state.capability_uses.insert(*id, *uses);
+            }
+        }
+        let mut vclock = self.local_vc.clone();
+        vclock.0.entry(self.replica_id).or_insert(0);
+        let base = self.snapshot_of(&state, 0, epoch, vclock.clone(), self.chain_head, self.log_tree.size(), alg_suite)?;
+        let state_tree = StateTree::from_state(&self.crypto_provider, self.state_tree.alg_suite(), &state)?;
+
+        self.state = state;
+        self.state_tree = state_tree;
+        self.epoch = epoch;
+        self.local_lc = 0;
+        self.local_vc = vclock;
+        self.history.clear();
+        self.base_snapshot = Some(Arc::new(base));
+        self.report_event_log_length();
+        Ok(())
+    }
+}
+
+/// The Lamport times bounding `cap`: its `expiry_lc` and every `BeforeLc` caveat.
+fn lamport_bounds(cap: &Capability) -> impl Iterator<Item = u64> + '_ {
+    cap.expiry_lc.into_iter().chain(cap.caveats.iter().filter_map(|caveat| match caveat {
+        Caveat::BeforeLc(lc) => Some(*lc),
+        _ => None,
+    }))
+}
+
+/// `handoff` must carry its predecessor's snapshot and start the epoch after it.
+fn check_handoff(handoff: &Handoff) -> Result<(), KernelError> {
+    let invalid = |reason: String| Err(KernelError::InvalidHandoff(reason));
+    if handoff.snapshot.replica != handoff.predecessor {
+        return invalid(format!("snapshot was taken by {:?}, not the predecessor", handoff.snapshot.replica));
+    }
+    if handoff.snapshot.epoch.checked_add(1) != Some(handoff.epoch) {
+        return invalid(format!("epoch {} does not follow snapshot epoch {}", handoff.epoch, handoff.snapshot.epoch));
+    }
+    Ok(())
+}
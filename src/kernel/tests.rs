@@ -1,13 +1,28 @@
 #![cfg(test)]
 
 use std::collections::{HashMap};
-use crate::kernel::core::{Kernel, SystemState, StateDelta};
+use std::sync::Arc;
+use crate::kernel::core::{Kernel, SystemState, StateDelta, UnobservedParentPolicy};
 use crate::primitives::{VClock, CID, ReplicaID, Event, Entity, EntityHeader, Capability, Command, CidBytes, ReplicaIdBytes, SignatureBytes, PublicKeyBytes};
 use crate::types::AlgSuite;
 use crate::command_traits::{EncodedCmd, CommandTraitError};
-use crate::crypto::{PlaceholderCryptoProvider, CryptoProvider};
+use crate::crypto::{ConfigurableCryptoProvider, CryptoError, DigestCryptoProvider, PlaceholderCryptoProvider, CryptoProvider};
 use crate::kernel::runtime::{DefaultRuntime, Runtime};
-use crate::error::KernelError;
+use crate::error::{BatchError, ErrorCause, Invariant, KernelError};
+use crate::rights::{self, RightsAlgebra, RightsOverlay, RightsRegistry};
+use crate::kernel::caveats;
+use crate::kernel::history::{EntityVersion, RetentionPolicy};
+use crate::kernel::metrics::KernelMetrics;
+use crate::kernel::observer::{CapabilityChange, KernelObserver};
+use crate::kernel::explain::Check;
+use crate::kernel::receipts::{ReceiptSigner, RejectionLog};
+use crate::kernel::equivocation::{EquivocationLog, EquivocationPolicy, EquivocationProof};
+use crate::kernel::storage::{self, FileWal, FsyncPolicy, LogRecord, MemoryStorage, Storage, StorageError};
+use crate::kernel::state_tree::{capability_value_hash, entity_value_hash, tombstone_value_hash, StateRoot, StateTree, EMPTY_ROOT};
+use crate::primitives::Caveat;
+use crate::kernel::overlay::{CommandView, KindValidator, OverlayRegistry, UnknownKindPolicy};
+use crate::kernel::snapshot::Snapshot;
+use crate::kernel::succession::Handoff;
 
 // --- Test Utilities ---
 
@@ -130,6 +145,8 @@ fn create_test_capability(
         nonce: 0,
         expiry_lc,
         kind: 0,
+        caveats: Vec::new(),
+        max_uses: None,
         signature: SignatureBytes([0u8; 64]), // Placeholder signature
     }
 }
@@ -202,23 +219,36 @@ fn test_lamport_clock_on_process_incoming_event() {
         replica: TEST_REPLICA_ID_2,
         caused_by: generate_test_cid(11),
         lclock: 3, // Lower than kernel.local_lc
+        epoch: 0,
         new_entities: Vec::new(),
         updated_entities: Vec::new(),
+        deleted_entities: Vec::new(),
+        consumed_capability: None,
+        prev_event: None,
         vclock: VClock::default(),
         reserved: Vec::new(),
     };
+    incoming_event.id = kernel.event_id(&incoming_event).unwrap();
 
     // Case 1: evt.lclock < kernel.local_lc
     kernel.process_incoming_event(&incoming_event).expect("Process event failed");
     assert_eq!(kernel.local_lc, 5, "Kernel lclock should remain unchanged");
 
+    // Each later case is the next event in R2's chain.
+    let author = create_test_kernel(TEST_REPLICA_ID_2);
+    let next = |prev: &Event, command: u8, lclock: u64| {
+        let mut event = Event { caused_by: generate_test_cid(command), lclock, prev_event: Some(prev.id), ..prev.clone() };
+        event.id = author.event_id(&event).unwrap();
+        event
+    };
+
     // Case 2: evt.lclock == kernel.local_lc
-    incoming_event.lclock = 5;
+    let incoming_event = next(&incoming_event, 12, 5);
     kernel.process_incoming_event(&incoming_event).expect("Process event failed");
     assert_eq!(kernel.local_lc, 5, "Kernel lclock should remain unchanged");
     
     // Case 3: evt.lclock > kernel.local_lc
-    incoming_event.lclock = 10;
+    let incoming_event = next(&incoming_event, 13, 10);
     kernel.process_incoming_event(&incoming_event).expect("Process event failed");
     assert_eq!(kernel.local_lc, 10, "Kernel lclock should be updated to event lclock");
 }
@@ -249,9 +279,7 @@ fn test_lamport_clock_overflow() {
     let cmd_overflow = create_test_command(cmd_payload_overflow, kernel.local_lc , TEST_REPLICA_ID_1, cap_id, 201, None); 
     
     match kernel.apply(&cmd_overflow) {
-        Err(KernelError::Other(msg)) => {
-            assert!(msg.contains("Replica has reached maximum Lamport clock value"), "Error message mismatch for overflow");
-        }
+        Err(KernelError::LamportClockExhausted) => {}
         Ok(_) => panic!("Apply should fail due to Lamport clock overflow"),
         Err(e) => panic!("Unexpected error type for Lamport overflow: {:?}", e),
     }
@@ -299,18 +327,23 @@ fn test_vector_clock_on_process_incoming_event() {
     // Event from R2, only knows about R2
     let mut vc_r2_event = VClock::default();
     vc_r2_event.0.insert(TEST_REPLICA_ID_2, 2);
-    let event_from_r2 = Event {
+    let mut event_from_r2 = Event {
         id: generate_test_cid(20),
         alg_suite: AlgSuite::CLASSIC as u8,
         replica: TEST_REPLICA_ID_2,
         caused_by: generate_test_cid(21),
         lclock: 2, 
+        epoch: 0,
         new_entities: Vec::new(),
         updated_entities: Vec::new(),
+        deleted_entities: Vec::new(),
+        consumed_capability: None,
+        prev_event: None,
         vclock: vc_r2_event,
         reserved: Vec::new(),
     };
 
+    event_from_r2.id = kernel_r1.event_id(&event_from_r2).unwrap();
     kernel_r1.process_incoming_event(&event_from_r2).expect("Process R2 event failed");
     assert_eq!(kernel_r1.local_lc, 2, "R1 LC should update to R2 event LC");
     assert_eq!(kernel_r1.local_vc.0.len(), 2, "R1 VC should have 2 entries");
@@ -324,17 +357,22 @@ fn test_vector_clock_on_process_incoming_event() {
     let mut vc_r3_event = VClock::default();
     vc_r3_event.0.insert(TEST_REPLICA_ID_2, 2); // R3 has older view of R2
     vc_r3_event.0.insert(TEST_REPLICA_ID_3, 4); // R3 is at 4
-    let event_from_r3 = Event {
+    let mut event_from_r3 = Event {
         id: generate_test_cid(30),
         alg_suite: AlgSuite::CLASSIC as u8,
         replica: TEST_REPLICA_ID_3,
         caused_by: generate_test_cid(31),
         lclock: 4, 
+        epoch: 0,
         new_entities: Vec::new(),
         updated_entities: Vec::new(),
+        deleted_entities: Vec::new(),
+        consumed_capability: None,
+        prev_event: None,
         vclock: vc_r3_event,
         reserved: Vec::new(),
     };
+    event_from_r3.id = kernel_r1.event_id(&event_from_r3).unwrap();
     kernel_r1.process_incoming_event(&event_from_r3).expect("Process R3 event failed");
     assert_eq!(kernel_r1.local_lc, 4, "R1 LC should update to R3 event LC");
     assert_eq!(kernel_r1.local_vc.0.len(), 3, "R1 VC should have 3 entries");
@@ -357,7 +395,7 @@ impl<CP: CryptoProvider> Runtime<CP> for MockRuntimeWithDelta {
     ) -> Result<StateDelta, KernelError> {
         match &self.delta_to_produce {
             Some(delta) => Ok(delta.clone()),
-            None => Ok(StateDelta { new_entities: Vec::new(), updated_entities: Vec::new() })
+            None => Ok(StateDelta::default())
         }
     }
 }
@@ -366,7 +404,7 @@ impl<CP: CryptoProvider> Runtime<CP> for MockRuntimeWithDelta {
 fn test_materialise_event_content() {
     let replica_id = TEST_REPLICA_ID_1;
     let runtime = MockRuntimeWithDelta::default(); // No delta for this specific test part
-    let mut kernel = Kernel::new(replica_id, runtime, PlaceholderCryptoProvider::default());
+    let mut kernel = Kernel::new(replica_id, runtime, PlaceholderCryptoProvider);
     
     let cap_id = generate_test_cid(100);
     let capability = create_test_capability(cap_id, [1u8;32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
@@ -377,11 +415,12 @@ fn test_materialise_event_content() {
 
     // Add initial version of the entity that will be updated
     let initial_updated_entity = create_test_entity(51, 1, kernel.local_lc, None); // version 1, current lc
-    kernel.state.entities.insert(updated_entity_cid, initial_updated_entity.clone());
+    kernel.state.entities.insert(updated_entity_cid, Arc::new(initial_updated_entity.clone()));
     
     let mock_delta = StateDelta {
         new_entities: vec![create_test_entity(50, 1, 0, None)], // lclock will be set by kernel
         updated_entities: vec![create_test_entity(51, initial_updated_entity.header.version + 1, 0, None)], // lclock will be set by kernel, ensure version increments correctly
+        deleted_entities: Vec::new(),
     };
 
     kernel.runtime = MockRuntimeWithDelta { delta_to_produce: Some(mock_delta) }; // Inject mock delta
@@ -416,14 +455,15 @@ fn test_append_delta_invariants() {
 
     // 1. New Entity CID Uniqueness
     let existing_entity_cid = generate_test_cid(1);
-    kernel.state.entities.insert(existing_entity_cid, create_test_entity(1, 1, 0, None));
+    kernel.state.entities.insert(existing_entity_cid, Arc::new(create_test_entity(1, 1, 0, None)));
     let delta_conflict = StateDelta {
         new_entities: vec![create_test_entity(1, 1, event_lclock, None)], // Conflicting CID
         updated_entities: Vec::new(),
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_conflict, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("New entity CID already exists"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) =>
+            assert_eq!(invariant, Invariant::EntityAlreadyExists(existing_entity_cid)),
         _ => panic!("Should fail due to new entity CID conflict"),
     }
 
@@ -432,33 +472,36 @@ fn test_append_delta_invariants() {
     let delta_update_non_existent = StateDelta {
         new_entities: Vec::new(),
         updated_entities: vec![create_test_entity(2, 1, event_lclock, None)], // Non-existent CID
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_update_non_existent, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("Updated entity with CID"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) =>
+            assert_eq!(invariant, Invariant::EntityNotFound(generate_test_cid(2))),
         _ => panic!("Should fail due to update non-existent entity"),
     }
 
     let entity_v1_cid = generate_test_cid(3);
-    kernel.state.entities.insert(entity_v1_cid, create_test_entity(3, 1, 0, None));
+    kernel.state.entities.insert(entity_v1_cid, Arc::new(create_test_entity(3, 1, 0, None)));
     
     let delta_update_version_same = StateDelta {
         new_entities: Vec::new(),
         updated_entities: vec![create_test_entity(3, 1, event_lclock, None)], // Version not incremented
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_update_version_same, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("Entity version monotonicity violated"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) =>
+            assert_eq!(invariant, Invariant::VersionNotMonotonic { entity: entity_v1_cid, current: 1, proposed: 1 }),
         _ => panic!("Should fail due to version not incremented"),
     }
 
     let delta_update_version_skip = StateDelta {
         new_entities: Vec::new(),
         updated_entities: vec![create_test_entity(3, 3, event_lclock, None)], // Version incremented by >1
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_update_version_skip, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("Entity version monotonicity violated"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) =>
+            assert_eq!(invariant, Invariant::VersionNotMonotonic { entity: entity_v1_cid, current: 1, proposed: 3 }),
         _ => panic!("Should fail due to version skipped"),
     }
 
@@ -466,32 +509,39 @@ fn test_append_delta_invariants() {
     let delta_new_entity_wrong_lclock = StateDelta {
         new_entities: vec![create_test_entity(4, 1, event_lclock + 1, None)], // Wrong lclock
         updated_entities: Vec::new(),
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_new_entity_wrong_lclock, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("Entity lclock must equal event lclock"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) => assert_eq!(
+            invariant,
+            Invariant::LClockMismatch { entity: generate_test_cid(4), entity_lclock: event_lclock + 1, event_lclock }
+        ),
         _ => panic!("Should fail due to new entity wrong lclock"),
     }
     
     let entity_for_update_cid = generate_test_cid(5);
-    kernel.state.entities.insert(entity_for_update_cid, create_test_entity(5,1,0,None));
+    kernel.state.entities.insert(entity_for_update_cid, Arc::new(create_test_entity(5,1,0,None)));
     let delta_updated_entity_wrong_lclock = StateDelta {
         new_entities: Vec::new(),
         updated_entities: vec![create_test_entity(5, 2, event_lclock + 1, None)], // Wrong lclock
+        deleted_entities: Vec::new(),
     };
     match kernel.append_delta(&delta_updated_entity_wrong_lclock, event_lclock) {
-        Err(KernelError::InvariantViolation(msg)) => 
-            assert!(msg.contains("Entity lclock must equal event lclock"), "Wrong invariant msg: {}", msg),
+        Err(KernelError::InvariantViolation(invariant)) => assert_eq!(
+            invariant,
+            Invariant::LClockMismatch { entity: entity_for_update_cid, entity_lclock: event_lclock + 1, event_lclock }
+        ),
         _ => panic!("Should fail due to updated entity wrong lclock"),
     }
 
     // 4. Successful Append
     let new_ok_cid = generate_test_cid(6);
     let update_ok_cid = generate_test_cid(7);
-    kernel.state.entities.insert(update_ok_cid, create_test_entity(7,1,0,None));
+    kernel.state.entities.insert(update_ok_cid, Arc::new(create_test_entity(7,1,0,None)));
     let delta_ok = StateDelta {
         new_entities: vec![create_test_entity(6, 1, event_lclock, None)],
         updated_entities: vec![create_test_entity(7, 2, event_lclock, None)],
+        deleted_entities: Vec::new(),
     };
     kernel.append_delta(&delta_ok, event_lclock).expect("Successful append_delta failed");
     assert!(kernel.state.entities.contains_key(&new_ok_cid));
@@ -516,7 +566,7 @@ fn test_validate_command() {
     // Case 1: Capability Not Found
     let cmd_no_cap = create_test_command(payload.clone(), cmd_lclock, TEST_REPLICA_ID_1, generate_test_cid(101), 40, None);
     match kernel.validate_command(&cmd_no_cap, current_lc) {
-        Err(KernelError::CapabilityNotFound) => {}, // Expected
+        Err(KernelError::CapabilityNotFound(missing)) => assert_eq!(missing, generate_test_cid(101)),
         _ => panic!("Should fail: CapabilityNotFound"),
     }
 
@@ -527,7 +577,9 @@ fn test_validate_command() {
     let cmd_algo_mismatch = create_test_command(payload.clone(), cmd_lclock, TEST_REPLICA_ID_1, cap_id, 41, None);
     // Command uses CLASSIC by default from helper
     match kernel.validate_command(&cmd_algo_mismatch, current_lc) {
-        Err(KernelError::AlgorithmSuiteMismatch) => {},
+        Err(KernelError::AlgorithmSuiteMismatch { command, capability }) => {
+            assert_eq!((command, capability), (AlgSuite::CLASSIC as u8, AlgSuite::FIPS as u8));
+        }
         res => panic!("Should fail: AlgorithmSuiteMismatch, got {:?}", res),
     }
     kernel.state.capabilities.insert(cap_id, valid_capability.clone()); // Reset to valid capability
@@ -538,7 +590,7 @@ fn test_validate_command() {
     kernel.state.capabilities.insert(cap_expired.id, cap_expired.clone());
     let cmd_cap_expired = create_test_command(payload.clone(), cmd_lclock, TEST_REPLICA_ID_1, cap_id, 42, None);
     match kernel.validate_command(&cmd_cap_expired, current_lc) {
-        Err(KernelError::CapabilityExpired) => {},
+        Err(KernelError::CapabilityExpired { expiry_lc, local_lc }) => assert_eq!((expiry_lc, local_lc), (current_lc, current_lc)),
         res => panic!("Should fail: CapabilityExpired (expiry == current), got {:?}", res),
     }
     
@@ -549,7 +601,7 @@ fn test_validate_command() {
         kernel.state.capabilities.insert(cap_expired_just.id, cap_expired_just.clone());
         // cmd_cap_expired still uses cap_id which now points to cap_expired_just
         match kernel.validate_command(&cmd_cap_expired, current_lc) {
-             Err(KernelError::CapabilityExpired) => {},
+             Err(KernelError::CapabilityExpired { .. }) => {},
              res => panic!("Should fail: CapabilityExpired (expiry < current), got {:?}", res),
         }
     }
@@ -559,7 +611,7 @@ fn test_validate_command() {
     if current_lc > 0 { // Only test if current_lc allows for a smaller cmd_lclock
         let cmd_invalid_lclock = create_test_command(payload.clone(), current_lc - 1, TEST_REPLICA_ID_1, cap_id, 43, None);
         match kernel.validate_command(&cmd_invalid_lclock, current_lc) {
-            Err(KernelError::InvalidCommandLClock) => {},
+            Err(KernelError::InvalidCommandLClock { proposed, local_lc }) => assert_eq!((proposed, local_lc), (current_lc - 1, current_lc)),
             res => panic!("Should fail: InvalidCommandLClock, got {:?}", res),
         }
     }
@@ -583,15 +635,15 @@ fn test_apply_full_workflow() {
     let mut kernel = Kernel::new(
         replica_id,
         MockRuntimeWithDelta::default(), // Will set specific delta later
-        PlaceholderCryptoProvider::default()
+        PlaceholderCryptoProvider
     );
     
-    let capability = create_test_capability(cap_id, [1u8;32], generate_test_cid(0), 0xFF, Some(kernel.local_lc + 100), AlgSuite::CLASSIC);
+    let capability = create_test_capability(cap_id, [1u8;32], generate_test_cid(0), rights::core::ALL, Some(kernel.local_lc + 100), AlgSuite::CLASSIC);
     kernel.state.capabilities.insert(cap_id, capability.clone());
     
     let initial_entity_lclock = kernel.local_lc; // or some earlier clock
     let entity_to_update = create_test_entity(121, 1, initial_entity_lclock, None);
-    kernel.state.entities.insert(entity_to_update_cid, entity_to_update.clone());
+    kernel.state.entities.insert(entity_to_update_cid, Arc::new(entity_to_update.clone()));
 
     // Configure mock runtime to produce a delta
     let event_lclock_expected = kernel.local_lc + 1; // Command lclock will be kernel.local_lc
@@ -617,6 +669,7 @@ fn test_apply_full_workflow() {
     let mock_delta = StateDelta {
         new_entities: vec![delta_new_entity],
         updated_entities: vec![delta_updated_entity],
+        deleted_entities: Vec::new(),
     };
     kernel.runtime = MockRuntimeWithDelta { delta_to_produce: Some(mock_delta.clone()) };
 
@@ -691,19 +744,19 @@ fn test_event_hash_input_deterministic() {
     let reserved_empty: Vec<u8> = Vec::new(); // Define reserved_empty for this test
 
     // Test with new_entities varying order
-    let input1_new = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &[], &vclock1, &reserved_empty);
-    let input2_new = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids2, &[], &vclock1, &reserved_empty);
+    let input1_new = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &[], &[], None, &vclock1, &reserved_empty, 0, None);
+    let input2_new = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids2, &[], &[], None, &vclock1, &reserved_empty, 0, None);
     assert_eq!(input1_new, input2_new, "Event hash input should be deterministic for new_entities order");
 
     // Test with updated_entities varying order
-    let input1_updated = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &[], &cids1, &vclock1, &reserved_empty);
-    let input2_updated = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &[], &cids2, &vclock1, &reserved_empty);
+    let input1_updated = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &[], &cids1, &[], None, &vclock1, &reserved_empty, 0, None);
+    let input2_updated = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &[], &cids2, &[], None, &vclock1, &reserved_empty, 0, None);
     assert_eq!(input1_updated, input2_updated, "Event hash input should be deterministic for updated_entities order");
 
     // Test with vector_clock entries varying order (VClock wrapper handles HashMap iteration order internally if sorted for digest)
     // The append_vector_clock_for_digest sorts by ReplicaID, so this should be deterministic.
-    let input1_vc = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &cids1, &vclock1, &reserved_empty);
-    let input2_vc = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &cids1, &vclock2, &reserved_empty);
+    let input1_vc = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &cids1, &[], None, &vclock1, &reserved_empty, 0, None);
+    let input2_vc = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids1, &cids1, &[], None, &vclock2, &reserved_empty, 0, None);
     assert_eq!(input1_vc, input2_vc, "Event hash input should be deterministic for vector_clock entry order");
 }
 
@@ -723,12 +776,1946 @@ fn test_event_hash_input_reserved_bytes_deterministic() {
     let reserved_empty: Vec<u8> = Vec::new();
 
 
-    let input_empty_reserved = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &vclock, &reserved_empty);
-    let input_reserved1 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &vclock, &reserved_bytes1);
-    let input_reserved2 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &vclock, &reserved_bytes2);
-    let input_reserved3 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &vclock, &reserved_bytes3);
+    let input_empty_reserved = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &[], None, &vclock, &reserved_empty, 0, None);
+    let input_reserved1 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &[], None, &vclock, &reserved_bytes1, 0, None);
+    let input_reserved2 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &[], None, &vclock, &reserved_bytes2, 0, None);
+    let input_reserved3 = kernel.get_event_hash_input_for_test(&cmd_id, event_lc, &TEST_REPLICA_ID_1, alg_suite_tag, &cids, &cids, &[], None, &vclock, &reserved_bytes3, 0, None);
 
     assert_ne!(input_empty_reserved, input_reserved1, "Input with empty reserved_bytes should differ from non-empty");
     assert_eq!(input_reserved1, input_reserved2, "Input should be deterministic for identical reserved_bytes");
     assert_ne!(input_reserved1, input_reserved3, "Input should differ for different reserved_bytes content");
-} 
\ No newline at end of file
+} 
+// --- Capability overlay tests ---
+
+/// Overlay that admits commands whose encoded payload is at most `max_len` bytes
+/// and only while the target entity exists in state.
+#[derive(Debug)]
+struct PayloadLimitValidator {
+    max_len: usize,
+}
+
+impl KindValidator for PayloadLimitValidator {
+    fn validate(&self, capability: &Capability, command: &CommandView<'_>, state: &SystemState) -> Result<(), String> {
+        if !state.entities.contains_key(&capability.target_entity) {
+            return Err("target entity not found".into());
+        }
+        if command.payload.len() > self.max_len {
+            return Err(format!("payload of {} bytes exceeds limit {}", command.payload.len(), self.max_len));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_overlay_unknown_kind_policy() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    capability.kind = 7;
+    kernel.state.capabilities.insert(cap_id, capability);
+
+    let cmd = create_test_command(MockEncodedCmd::new("cmd", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert_eq!(kernel.validate_command(&cmd, 0), Err(KernelError::UnknownCapabilityKind(7)));
+
+    kernel.overlays.set_unknown_kind_policy(UnknownKindPolicy::Allow);
+    assert!(kernel.validate_command(&cmd, 0).is_ok(), "Allow policy should admit unknown kinds");
+}
+
+#[test]
+fn test_overlay_validator_dispatch() {
+    let mut overlays = OverlayRegistry::new();
+    assert!(overlays.register(0, PayloadLimitValidator { max_len: 4 }).is_err(), "Kind 0 must not be overlaid");
+    assert!(overlays.register(3, PayloadLimitValidator { max_len: 4 }).unwrap().is_none());
+
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_overlays(overlays);
+    let target = generate_test_cid(9);
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], target, 0, None, AlgSuite::CLASSIC);
+    capability.kind = 3;
+    kernel.state.capabilities.insert(cap_id, capability);
+
+    let short = create_test_command(MockEncodedCmd::new("ok", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    let long = create_test_command(MockEncodedCmd::new("too long", 0), 0, TEST_REPLICA_ID_1, cap_id, 2, None);
+
+    // The validator sees the current state: the target entity does not exist yet.
+    match kernel.validate_command(&short, 0) {
+        Err(KernelError::OverlayRejected(3, reason)) => assert!(reason.contains("target entity"), "Wrong reason: {}", reason),
+        res => panic!("Should fail: OverlayRejected, got {:?}", res),
+    }
+
+    kernel.state.entities.insert(target, Arc::new(create_test_entity(9, 1, 0, None)));
+    assert!(kernel.validate_command(&short, 0).is_ok(), "Short payload should be admitted");
+    match kernel.apply(&long) {
+        Err(KernelError::OverlayRejected(3, reason)) => assert!(reason.contains("exceeds limit"), "Wrong reason: {}", reason),
+        res => panic!("Should fail: OverlayRejected, got {:?}", res),
+    }
+    assert!(kernel.state.event_log.is_empty(), "Rejected command must not produce an event");
+
+    // Plain capabilities never consult the registry.
+    kernel.state.capabilities.get_mut(&cap_id).unwrap().kind = 0;
+    assert!(kernel.validate_command(&long, 0).is_ok(), "Plain capability should ignore overlays");
+}
+
+// --- Rights algebra enforcement tests ---
+
+#[test]
+fn test_validate_command_rejects_unallocated_rights_bits() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    let reserved_bit = 1 << 9;
+    let capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::READ | reserved_bit, None, AlgSuite::CLASSIC);
+    kernel.state.capabilities.insert(cap_id, capability);
+
+    let cmd = create_test_command(MockEncodedCmd::new("read", rights::core::READ), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert_eq!(kernel.validate_command(&cmd, 0), Err(KernelError::UnallocatedRightsBits(reserved_bit)));
+
+    // Reserved bits in the payload's requirement are refused as well.
+    kernel.state.capabilities.get_mut(&cap_id).unwrap().rights = rights::core::ALL;
+    let cmd_reserved_need = create_test_command(MockEncodedCmd::new("odd", reserved_bit), 0, TEST_REPLICA_ID_1, cap_id, 2, None);
+    assert_eq!(kernel.validate_command(&cmd_reserved_need, 0), Err(KernelError::UnallocatedRightsBits(reserved_bit)));
+    assert!(kernel.validate_command(&cmd, 0).is_ok());
+}
+
+#[test]
+fn test_older_rights_version_refuses_audit() {
+    assert!(create_test_kernel(TEST_REPLICA_ID_1).with_rights_version(99).is_err(), "Unknown versions must be refused");
+
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_rights_version(1).expect("Version 1 is known");
+    let cap_id = generate_test_cid(100);
+    let capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::AUDIT, None, AlgSuite::CLASSIC);
+    kernel.state.capabilities.insert(cap_id, capability);
+
+    let cmd = create_test_command(MockEncodedCmd::new("read", rights::core::READ), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert_eq!(kernel.validate_command(&cmd, 0), Err(KernelError::UnallocatedRightsBits(rights::core::AUDIT)));
+
+    kernel = kernel.with_rights_version(rights::ALGEBRA_VERSION).expect("Current version is known");
+    assert!(kernel.validate_command(&cmd, 0).is_ok(), "AUDIT implies READ under version 2");
+}
+
+#[test]
+fn test_kernel_uses_configured_rights_algebra() {
+    let mut registry = RightsRegistry::new();
+    registry
+        .register(RightsOverlay::new("finance").right("TRANSFER", 1 << 16).right("SETTLE", 1 << 17).implies("SETTLE", "TRANSFER"))
+        .expect("finance overlay is valid");
+    let transfer = registry.parse("finance:TRANSFER").unwrap();
+    let settle = registry.parse("finance:SETTLE").unwrap();
+
+    let cap_id = generate_test_cid(100);
+    let capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), settle, None, AlgSuite::CLASSIC);
+    let cmd = create_test_command(MockEncodedCmd::new("transfer", transfer), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+
+    // Without the overlay, SETTLE is an opaque extension bit and does not imply TRANSFER.
+    let mut plain_kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    plain_kernel.state.capabilities.insert(cap_id, capability.clone());
+    assert_eq!(plain_kernel.validate_command(&cmd, 0), Err(KernelError::InsufficientRights { granted: settle, required: transfer }));
+
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_rights_algebra(registry);
+    kernel.state.capabilities.insert(cap_id, capability);
+    assert!(kernel.validate_command(&cmd, 0).is_ok(), "SETTLE should imply TRANSFER under the finance overlay");
+}
+
+// --- Capability caveat tests ---
+
+#[test]
+fn test_caveats_evaluated_in_validate_command() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let root = generate_test_cid(1);
+    let account = generate_test_cid(2);
+    kernel.state.entities.insert(root, Arc::new(create_test_entity(1, 1, 0, None)));
+    kernel.state.entities.insert(account, Arc::new(create_test_entity(2, 1, 0, Some(1))));
+
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], account, 0, None, AlgSuite::CLASSIC);
+    capability.caveats = vec![
+        Caveat::BeforeLc(5),
+        Caveat::UnderParent(root),
+        Caveat::Opcode(b'p'),
+        Caveat::FromReplica(TEST_REPLICA_ID_1),
+    ];
+    kernel.state.capabilities.insert(cap_id, capability.clone());
+
+    let pay = create_test_command(MockEncodedCmd::new("pay", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert!(kernel.validate_command(&pay, 0).is_ok(), "All caveats hold");
+
+    // Caveats are checked against the validating replica's clock.
+    assert!(matches!(kernel.validate_command(&pay, 5), Err(KernelError::CaveatViolated(_))));
+    let refund = create_test_command(MockEncodedCmd::new("refund", 0), 0, TEST_REPLICA_ID_1, cap_id, 2, None);
+    assert!(matches!(kernel.validate_command(&refund, 0), Err(KernelError::CaveatViolated(_))));
+    let foreign = create_test_command(MockEncodedCmd::new("pay", 0), 0, TEST_REPLICA_ID_2, cap_id, 3, None);
+    assert!(matches!(kernel.validate_command(&foreign, 0), Err(KernelError::CaveatViolated(_))));
+
+    // Re-parenting the target outside `root` breaks the ancestry caveat.
+    Arc::make_mut(kernel.state.entities.get_mut(&account).unwrap()).header.parent = None;
+    assert!(matches!(kernel.validate_command(&pay, 0), Err(KernelError::CaveatViolated(_))));
+}
+
+#[test]
+fn test_under_parent_caveat_bounds_delta() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let root = generate_test_cid(1);
+    let account = generate_test_cid(2);
+    kernel.state.entities.insert(root, Arc::new(create_test_entity(1, 1, 0, None)));
+    kernel.state.entities.insert(account, Arc::new(create_test_entity(2, 1, 0, Some(1))));
+    kernel.state.entities.insert(generate_test_cid(3), Arc::new(create_test_entity(3, 1, 0, None)));
+
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], account, 0, None, AlgSuite::CLASSIC);
+    capability.caveats = vec![Caveat::UnderParent(root)];
+    kernel.state.capabilities.insert(cap_id, capability);
+    let cmd = |byte| create_test_command(MockEncodedCmd::new("write", 0), 0, TEST_REPLICA_ID_1, cap_id, byte, None);
+
+    // A new entity may hang off another new entity inside the subtree.
+    kernel.runtime.delta_to_produce = Some(StateDelta {
+        new_entities: vec![create_test_entity(4, 1, 0, Some(2)), create_test_entity(5, 1, 0, Some(4))],
+        ..Default::default()
+    });
+    assert!(kernel.simulate(&cmd(1)).is_ok(), "Writes under the parent are admitted");
+
+    let outside = [
+        StateDelta { updated_entities: vec![create_test_entity(3, 2, 0, None)], ..Default::default() },
+        StateDelta { new_entities: vec![create_test_entity(6, 1, 0, Some(3))], ..Default::default() },
+        StateDelta { updated_entities: vec![create_test_entity(2, 2, 0, Some(3))], ..Default::default() },
+        StateDelta { deleted_entities: vec![create_test_tombstone(3, 2, 0)], ..Default::default() },
+    ];
+    for delta in outside {
+        kernel.runtime.delta_to_produce = Some(delta.clone());
+        assert!(matches!(kernel.apply(&cmd(2)), Err(KernelError::CaveatViolated(_))), "Delta escapes the subtree: {:?}", delta);
+    }
+    assert!(kernel.state.event_log.is_empty());
+}
+
+#[test]
+fn test_caveat_delegation_only_narrows() {
+    let kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let mut parent = create_test_capability(generate_test_cid(100), [1u8; 32], generate_test_cid(1), rights::core::WRITE | rights::core::DELEGATE, Some(50), AlgSuite::CLASSIC);
+    parent.caveats = vec![Caveat::Opcode(1)];
+
+    let mut child = parent.clone();
+    child.id = generate_test_cid(101);
+    child.rights = rights::core::READ;
+    child.expiry_lc = Some(40);
+    child.caveats = caveats::attenuate(&parent.caveats, &[Caveat::FromReplica(TEST_REPLICA_ID_2)]);
+    assert!(kernel.check_delegation(&parent, &child).is_ok(), "Narrowed child should be accepted");
+
+    let mut dropped = child.clone();
+    dropped.caveats = vec![Caveat::FromReplica(TEST_REPLICA_ID_2)];
+    assert!(matches!(kernel.check_delegation(&parent, &dropped), Err(KernelError::InvalidDelegation(_))));
+
+    let mut wider = child.clone();
+    wider.rights = rights::core::ISSUE;
+    assert!(matches!(kernel.check_delegation(&parent, &wider), Err(KernelError::InvalidDelegation(_))));
+
+    let mut longer = child.clone();
+    longer.expiry_lc = None;
+    assert!(matches!(kernel.check_delegation(&parent, &longer), Err(KernelError::InvalidDelegation(_))));
+
+    // Re-issuing under another kind, e.g. plain, would bypass the parent's overlay validator.
+    let mut rekinded = child.clone();
+    rekinded.kind = parent.kind + 1;
+    assert!(matches!(kernel.check_delegation(&parent, &rekinded), Err(KernelError::InvalidDelegation(_))));
+    let mut typed_parent = parent.clone();
+    typed_parent.kind = 0x100;
+    assert!(matches!(kernel.check_delegation(&typed_parent, &child), Err(KernelError::InvalidDelegation(_))), "A typed parent cannot yield a plain child");
+
+    let mut counted = child.clone();
+    counted.max_uses = Some(2);
+    assert!(kernel.check_delegation(&parent, &counted).is_ok(), "An unlimited parent may delegate a use-limited child");
+
+    let mut no_delegate = parent.clone();
+    no_delegate.rights = rights::core::WRITE;
+    assert!(matches!(kernel.check_delegation(&no_delegate, &child), Err(KernelError::InvalidDelegation(_))));
+}
+
+#[test]
+fn test_use_limit_holds_across_delegation() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let parent_id = generate_test_cid(100);
+    let mut parent = create_test_capability(parent_id, [1u8; 32], generate_test_cid(0), rights::core::WRITE | rights::core::DELEGATE, None, AlgSuite::CLASSIC);
+    parent.max_uses = Some(1);
+    kernel.state.capabilities.insert(parent_id, parent.clone());
+
+    // No child of a one-shot parent can be delegated, whatever its own limit,
+    // so only the parent's single use is ever available.
+    for max_uses in [None, Some(1), Some(0)] {
+        let child = Capability { id: generate_test_cid(101), rights: rights::core::WRITE, max_uses, ..parent.clone() };
+        assert!(matches!(kernel.check_delegation(&parent, &child), Err(KernelError::InvalidDelegation(_))), "{:?} uses", max_uses);
+    }
+    kernel.apply(&create_test_command(MockEncodedCmd::new("once", 0), 0, TEST_REPLICA_ID_1, parent_id, 1, None)).unwrap();
+    let again = create_test_command(MockEncodedCmd::new("twice", 0), kernel.local_lc, TEST_REPLICA_ID_1, parent_id, 2, None);
+    assert_eq!(kernel.apply(&again), Err(KernelError::CapabilityUsesExhausted));
+}
+
+#[test]
+fn test_caveats_participate_in_capability_id() {
+    let mut cap = create_test_capability(generate_test_cid(100), [1u8; 32], generate_test_cid(1), rights::core::READ, None, AlgSuite::CLASSIC);
+    let plain_input = cap.id_hash_input();
+
+    cap.caveats = vec![Caveat::Opcode(1), Caveat::BeforeLc(9)];
+    let with_caveats = cap.id_hash_input();
+    assert_ne!(plain_input, with_caveats, "Caveats must change the capability id input");
+
+    cap.caveats = vec![Caveat::BeforeLc(9), Caveat::Opcode(1), Caveat::Opcode(1)];
+    assert_eq!(cap.id_hash_input(), with_caveats, "Caveat encoding must be canonical");
+    assert!(create_test_kernel(TEST_REPLICA_ID_1).capability_id(&cap).is_ok());
+}
+
+// --- Use-limited capability tests ---
+
+#[test]
+fn test_use_limited_capability_exhausts() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(2);
+    kernel.state.capabilities.insert(cap_id, voucher);
+
+    for i in 0..2u8 {
+        let cmd = create_test_command(MockEncodedCmd::new("use", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 10 + i, None);
+        let event = kernel.apply(&cmd).expect("Voucher use within limit should succeed");
+        assert_eq!(event.consumed_capability, Some(cap_id), "Event should record the consumed capability");
+    }
+    assert_eq!(kernel.state.capability_uses.get(&cap_id), Some(&2));
+
+    let cmd = create_test_command(MockEncodedCmd::new("use", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 12, None);
+    assert_eq!(kernel.apply(&cmd), Err(KernelError::CapabilityUsesExhausted));
+    assert_eq!(kernel.state.capability_uses.get(&cap_id), Some(&2), "Rejected command must not consume a use");
+    assert_eq!(kernel.state.event_log.len(), 2);
+}
+
+#[test]
+fn test_unlimited_capability_not_counted() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+
+    let cmd = create_test_command(MockEncodedCmd::new("use", 0), 0, TEST_REPLICA_ID_1, cap_id, 10, None);
+    let event = kernel.apply(&cmd).expect("Apply failed");
+    assert_eq!(event.consumed_capability, None);
+    assert!(kernel.state.capability_uses.is_empty());
+}
+
+#[test]
+fn test_capability_uses_replicate_via_events() {
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(1);
+
+    let mut origin = create_test_kernel(TEST_REPLICA_ID_1);
+    origin.state.capabilities.insert(cap_id, voucher.clone());
+    let mut peer = create_test_kernel(TEST_REPLICA_ID_2);
+    peer.state.capabilities.insert(cap_id, voucher);
+
+    let cmd = create_test_command(MockEncodedCmd::new("once", 0), 0, TEST_REPLICA_ID_1, cap_id, 10, None);
+    let event = origin.apply(&cmd).expect("First use should succeed");
+    peer.process_incoming_event(&event).expect("Process event failed");
+    assert_eq!(peer.state.capability_uses, origin.state.capability_uses, "Counters should agree after replication");
+
+    // Neither the origin's own event nor a redelivery is counted again.
+    origin.process_incoming_event(&event).unwrap();
+    peer.process_incoming_event(&event).unwrap();
+    assert_eq!(peer.state.capability_uses, origin.state.capability_uses);
+    assert_eq!(origin.state.capability_uses[&cap_id], 1);
+
+    // Nor is an older event replayed once the origin's chain has moved past it.
+    let plain_id = generate_test_cid(101);
+    origin.state.capabilities.insert(plain_id, create_test_capability(plain_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    let later = origin.apply(&create_test_command(MockEncodedCmd::new("later", 0), origin.local_lc, TEST_REPLICA_ID_1, plain_id, 12, None)).unwrap();
+    peer.process_incoming_event(&later).unwrap();
+    assert!(matches!(peer.process_incoming_event(&event), Err(KernelError::BrokenEventChain { .. })));
+    assert_eq!(peer.state.capability_uses[&cap_id], 1);
+
+    let peer_cmd = create_test_command(MockEncodedCmd::new("twice", 0), peer.local_lc, TEST_REPLICA_ID_2, cap_id, 11, None);
+    assert_eq!(peer.apply(&peer_cmd), Err(KernelError::CapabilityUsesExhausted), "Peer must see the voucher as spent");
+}
+
+#[test]
+fn test_event_hash_input_includes_consumed_capability() {
+    let kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cmd_id = generate_test_cid(1);
+    let cap_id = generate_test_cid(2);
+    let vclock = VClock::default();
+    let without = kernel.get_event_hash_input_for_test(&cmd_id, 1, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[], &[], &[], None, &vclock, &[], 0, None);
+    let with = kernel.get_event_hash_input_for_test(&cmd_id, 1, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[], &[], &[], Some(&cap_id), &vclock, &[], 0, None);
+    assert_ne!(without, with, "Consumed capability must be bound into the event id");
+}
+
+#[test]
+fn test_event_hash_input_keeps_original_encoding_without_new_fields() {
+    let kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cmd_id = generate_test_cid(1);
+    let new_cid = generate_test_cid(3);
+    let input = kernel.get_event_hash_input_for_test(&cmd_id, 7, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[new_cid], &[], &[], None, &VClock::default(), &[], 0, None);
+    let mut original = Vec::new();
+    original.extend_from_slice(&cmd_id.0);
+    original.extend_from_slice(&7u64.to_le_bytes());
+    original.extend_from_slice(&TEST_REPLICA_ID_1.0);
+    original.push(AlgSuite::CLASSIC as u8);
+    original.extend_from_slice(&new_cid.0);
+    original.push(1); // vector clock present, no entries
+    original.extend_from_slice(&0u32.to_le_bytes()); // no reserved bytes
+    assert_eq!(input, original, "Events using no later field keep their original ids");
+}
+
+// --- Simulation tests ---
+
+#[test]
+fn test_simulate_matches_apply_without_mutation() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    kernel.state.entities.insert(generate_test_cid(51), Arc::new(create_test_entity(51, 1, 0, None)));
+    kernel.local_vc.0.insert(TEST_REPLICA_ID_2, 4);
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta {
+            new_entities: vec![create_test_entity(50, 1, 0, None)],
+            updated_entities: vec![create_test_entity(51, 2, 0, None)],
+            deleted_entities: Vec::new(),
+        }),
+    };
+
+    let command = create_test_command(MockEncodedCmd::new("preview", 0), 3, TEST_REPLICA_ID_1, cap_id, 30, None);
+    let state_before = kernel.state.clone();
+    let (lc_before, vc_before) = (kernel.local_lc, kernel.local_vc.clone());
+
+    let simulation = kernel.simulate(&command).expect("Simulation failed");
+    assert_eq!(simulation.event.lclock, 3, "Simulated event should get the would-be lclock");
+    assert!(simulation.delta.new_entities.iter().all(|e| e.header.lclock == 3), "Delta lclocks should be assigned");
+    assert_eq!(kernel.local_lc, lc_before, "simulate must not advance local_lc");
+    assert_eq!(kernel.local_vc, vc_before, "simulate must not touch local_vc");
+    assert_eq!(kernel.state, state_before, "simulate must not touch state");
+
+    let event = kernel.apply(&command).expect("Apply failed");
+    assert_eq!(simulation.event, event, "Simulation should predict the applied event exactly");
+}
+
+#[test]
+fn test_simulate_reports_rejections() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let command = create_test_command(MockEncodedCmd::new("preview", 0), 0, TEST_REPLICA_ID_1, generate_test_cid(100), 30, None);
+    assert_eq!(kernel.simulate(&command), Err(KernelError::CapabilityNotFound(command.capability)));
+
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { new_entities: Vec::new(), updated_entities: vec![create_test_entity(9, 2, 0, None)], deleted_entities: Vec::new() }),
+    };
+    assert!(matches!(kernel.simulate(&command), Err(KernelError::InvariantViolation(_))), "Invariant failures should surface");
+    assert!(kernel.state.event_log.is_empty());
+}
+
+// --- Batch tests ---
+
+#[test]
+fn test_apply_batch_commits_all() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let debit_cap = generate_test_cid(100);
+    let credit_cap = generate_test_cid(101);
+    kernel.state.capabilities.insert(debit_cap, create_test_capability(debit_cap, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    kernel.state.capabilities.insert(credit_cap, create_test_capability(credit_cap, [2u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("debit", 0), 0, TEST_REPLICA_ID_1, debit_cap, 1, None),
+        create_test_command(MockEncodedCmd::new("credit", 0), 1, TEST_REPLICA_ID_1, credit_cap, 2, None),
+    ];
+    let events = kernel.apply_batch(&batch).expect("Batch should commit");
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].lclock, 1);
+    assert_eq!(events[1].lclock, 2, "Later commands see the clock advanced by earlier ones");
+    assert_eq!(kernel.state.event_log, events);
+    assert_eq!(kernel.local_lc, 2);
+}
+
+#[test]
+fn test_apply_batch_rolls_back_on_failure() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(1);
+    kernel.state.capabilities.insert(cap_id, voucher);
+    let before = kernel.clone();
+
+    // The second command sees the voucher already consumed by the first.
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("first", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None),
+        create_test_command(MockEncodedCmd::new("second", 0), 1, TEST_REPLICA_ID_1, cap_id, 2, None),
+    ];
+    let err = kernel.apply_batch(&batch).expect_err("Batch should fail");
+    assert_eq!(err, BatchError { index: 1, error: KernelError::CapabilityUsesExhausted });
+    assert_eq!(kernel.local_lc, before.local_lc);
+    assert_eq!(kernel.local_vc, before.local_vc);
+    assert_eq!(kernel.state, before.state, "Failed batch must leave state untouched");
+}
+
+// --- Transactional apply tests ---
+
+#[test]
+fn test_apply_rolls_back_on_event_hash_failure() {
+    let crypto = ConfigurableCryptoProvider {
+        hash_outcome: Err(CryptoError::HashingFailure("injected".into())),
+        ..Default::default()
+    };
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), crypto);
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(3);
+    kernel.state.capabilities.insert(cap_id, voucher);
+    kernel.state.entities.insert(generate_test_cid(51), Arc::new(create_test_entity(51, 1, 0, None)));
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta {
+            new_entities: vec![create_test_entity(50, 1, 0, None)],
+            updated_entities: vec![create_test_entity(51, 2, 0, None)],
+            deleted_entities: Vec::new(),
+        }),
+    };
+    let before = kernel.clone();
+
+    let command = create_test_command(MockEncodedCmd::new("doomed", 0), 4, TEST_REPLICA_ID_1, cap_id, 30, None);
+    assert_eq!(
+        kernel.apply(&command),
+        Err(KernelError::Crypto(CryptoError::HashingFailure("injected".into())))
+    );
+    assert_eq!(kernel.state, before.state, "No entity, event or capability use may be committed");
+    assert_eq!(kernel.local_lc, before.local_lc, "local_lc must not advance");
+    assert_eq!(kernel.local_vc, before.local_vc, "local_vc must not advance");
+
+    // Once hashing recovers the same command commits in full.
+    kernel.crypto_provider.hash_outcome = Ok([7u8; 32]);
+    let event = kernel.apply(&command).expect("Apply should succeed after recovery");
+    assert_eq!(kernel.state.event_log, vec![event]);
+    assert_eq!(kernel.state.entities.len(), 2);
+    assert_eq!(kernel.local_lc, 4);
+}
+
+#[test]
+fn test_apply_rolls_back_on_invariant_violation() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    // The new entity is valid but the update targets a missing CID.
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta {
+            new_entities: vec![create_test_entity(50, 1, 0, None)],
+            updated_entities: vec![create_test_entity(52, 2, 0, None)],
+            deleted_entities: Vec::new(),
+        }),
+    };
+    let before = kernel.clone();
+
+    let command = create_test_command(MockEncodedCmd::new("partial", 0), 1, TEST_REPLICA_ID_1, cap_id, 31, None);
+    assert!(matches!(kernel.apply(&command), Err(KernelError::InvariantViolation(_))));
+    assert_eq!(kernel.state, before.state, "Valid parts of a rejected delta must not be committed");
+    assert_eq!(kernel.local_lc, before.local_lc);
+}
+
+// --- Rejection receipt tests ---
+
+/// Signer that "signs" by echoing a fixed-size prefix of the signing bytes.
+#[derive(Debug)]
+struct EchoSigner;
+
+impl ReceiptSigner for EchoSigner {
+    fn sign(&self, data: &[u8]) -> Result<crate::primitives::Signature, CryptoError> {
+        let mut sig = [0u8; 64];
+        for (dst, src) in sig.iter_mut().zip(data.iter().rev()) {
+            *dst = *src;
+        }
+        Ok(SignatureBytes(sig))
+    }
+}
+
+#[test]
+fn test_rejection_receipts_recorded() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_rejection_log(RejectionLog::with_signer(EchoSigner));
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::READ, None, AlgSuite::CLASSIC));
+    kernel.local_lc = 3;
+
+    let denied = create_test_command(MockEncodedCmd::new("write", rights::core::WRITE), 3, TEST_REPLICA_ID_1, cap_id, 40, None);
+    let insufficient = KernelError::InsufficientRights { granted: rights::core::READ, required: rights::core::WRITE };
+    assert_eq!(kernel.apply(&denied), Err(insufficient.clone()));
+
+    let log = kernel.rejections.as_ref().expect("Rejection log enabled");
+    assert_eq!(log.len(), 1);
+    let receipt = &log.receipts()[0];
+    assert_eq!(receipt.command_id, denied.id);
+    assert_eq!(receipt.capability, cap_id);
+    assert_eq!(receipt.reason_code, insufficient.code());
+    assert_eq!(receipt.replica, TEST_REPLICA_ID_1);
+    assert_eq!(receipt.local_lc, 3);
+    assert_eq!(receipt.signature, Some(EchoSigner.sign(&receipt.signing_bytes()).unwrap()));
+    assert!(kernel.verify_receipt(receipt, &PublicKeyBytes([1u8; 32]), AlgSuite::CLASSIC).is_ok());
+    assert_eq!(log.for_command(&denied.id).count(), 1);
+    assert_eq!(log.for_capability(&generate_test_cid(5)).count(), 0);
+
+    // Receipts never touch the event log or clocks.
+    assert!(kernel.state.event_log.is_empty());
+    assert_eq!(kernel.local_lc, 3);
+
+    // Accepted commands leave no receipt.
+    let read = create_test_command(MockEncodedCmd::new("read", rights::core::READ), 3, TEST_REPLICA_ID_1, cap_id, 41, None);
+    kernel.apply(&read).expect("Read should be accepted");
+    assert_eq!(kernel.rejections.as_ref().unwrap().len(), 1);
+}
+
+#[test]
+fn test_rejection_log_disabled_and_unsigned() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cmd = create_test_command(MockEncodedCmd::new("x", 0), 0, TEST_REPLICA_ID_1, generate_test_cid(100), 40, None);
+    assert_eq!(kernel.apply(&cmd), Err(KernelError::CapabilityNotFound(cmd.capability)));
+    assert!(kernel.rejections.is_none(), "No log is kept unless enabled");
+
+    let mut kernel = kernel.with_rejection_log(RejectionLog::new());
+    assert_eq!(kernel.apply(&cmd), Err(KernelError::CapabilityNotFound(cmd.capability)));
+    let receipt = kernel.rejections.as_ref().unwrap().receipts()[0].clone();
+    assert_eq!(receipt.signature, None);
+    assert_eq!(receipt.reason_code, KernelError::CapabilityNotFound(cmd.capability).code());
+    assert!(kernel.verify_receipt(&receipt, &PublicKeyBytes([1u8; 32]), AlgSuite::CLASSIC).is_err(), "Unsigned receipts cannot verify");
+}
+
+#[derive(Debug)]
+struct BrokenSigner;
+
+impl ReceiptSigner for BrokenSigner {
+    fn sign(&self, _data: &[u8]) -> Result<crate::primitives::Signature, CryptoError> {
+        Err(CryptoError::InvalidSignature)
+    }
+}
+
+#[test]
+fn test_rejection_receipts_only_for_refused_commands() {
+    // A signer that fails leaves no unsigned receipt behind, and is counted.
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_rejection_log(RejectionLog::with_signer(BrokenSigner));
+    let cmd = create_test_command(MockEncodedCmd::new("x", 0), 0, TEST_REPLICA_ID_1, generate_test_cid(100), 40, None);
+    assert_eq!(kernel.apply(&cmd), Err(KernelError::CapabilityNotFound(cmd.capability)));
+    let log = kernel.rejections.as_ref().unwrap();
+    assert!(log.is_empty());
+    assert_eq!(log.signing_failures(), 1);
+
+    // A storage failure is the replica's, not a refusal of the command.
+    let cap_id = generate_test_cid(100);
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_storage(FailingStorage).with_rejection_log(RejectionLog::new());
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    let cmd = create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert!(matches!(kernel.apply(&cmd), Err(KernelError::Storage(_))));
+    assert!(kernel.apply_batch(std::slice::from_ref(&cmd)).is_err());
+    assert!(kernel.rejections.as_ref().unwrap().is_empty());
+}
+
+// --- Explain tests ---
+
+#[test]
+fn test_explain_traces_every_check_in_order() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    kernel.local_lc = 10;
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::WRITE, Some(8), AlgSuite::CLASSIC);
+    capability.caveats = vec![Caveat::FromReplica(TEST_REPLICA_ID_2)];
+    kernel.state.capabilities.insert(cap_id, capability);
+
+    let cmd = create_test_command(MockEncodedCmd::new("issue", rights::core::ISSUE), 4, TEST_REPLICA_ID_1, cap_id, 50, None);
+    let explanation = kernel.explain(&cmd);
+
+    let checks: Vec<Check> = explanation.steps.iter().map(|step| step.check.clone()).collect();
+    assert_eq!(checks, vec![
+        Check::CapabilityLookup { capability: cap_id, found: true },
+        Check::AlgSuite { command: AlgSuite::CLASSIC as u8, capability: AlgSuite::CLASSIC as u8 },
+        Check::Expiry { expiry_lc: Some(8), local_lc: 10 },
+        Check::UseLimit { max_uses: None, used: 0 },
+        Check::Signature,
+        Check::Rights { granted: rights::core::WRITE, canonical: rights::core::WRITE | rights::core::READ, required: rights::core::ISSUE },
+        Check::Caveat(Caveat::FromReplica(TEST_REPLICA_ID_2)),
+        Check::Overlay { kind: 0 },
+        Check::LClock { command: 4, local_lc: 10 },
+    ]);
+
+    // Every failing check is reported; the verdict is the first one.
+    let failed: Vec<u16> = explanation.failures().map(|step| step.outcome.clone().unwrap_err().code()).collect();
+    assert_eq!(failed, vec![
+        KernelError::CapabilityExpired { expiry_lc: 8, local_lc: 10 }.code(),
+        KernelError::InsufficientRights { granted: rights::core::WRITE, required: rights::core::ISSUE }.code(),
+        KernelError::CaveatViolated(String::new()).code(),
+        KernelError::InvalidCommandLClock { proposed: 4, local_lc: 10 }.code(),
+    ]);
+    assert!(!explanation.is_allowed());
+    assert_eq!(explanation.verdict(), kernel.validate_command(&cmd, kernel.local_lc));
+    assert_eq!(explanation.verdict(), Err(KernelError::CapabilityExpired { expiry_lc: 8, local_lc: 10 }));
+}
+
+#[test]
+fn test_explain_verdict_matches_validate_command() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::WRITE, None, AlgSuite::CLASSIC));
+
+    let missing = create_test_command(MockEncodedCmd::new("x", 0), 0, TEST_REPLICA_ID_1, generate_test_cid(101), 50, None);
+    let explanation = kernel.explain(&missing);
+    assert_eq!(explanation.steps.len(), 1, "Nothing else can be checked without a capability");
+    assert_eq!(explanation.verdict(), Err(KernelError::CapabilityNotFound(missing.capability)));
+
+    let allowed = create_test_command(MockEncodedCmd::new("read", rights::core::READ), 0, TEST_REPLICA_ID_1, cap_id, 51, None);
+    let explanation = kernel.explain(&allowed);
+    assert!(explanation.is_allowed());
+    assert_eq!(explanation.first_failure(), None);
+    assert_eq!(explanation.verdict(), kernel.validate_command(&allowed, kernel.local_lc));
+
+    let mut mismatched = allowed.clone();
+    mismatched.alg_suite = AlgSuite::FIPS as u8;
+    assert_eq!(kernel.explain(&mismatched).verdict(), kernel.validate_command(&mismatched, kernel.local_lc));
+    assert_eq!(kernel.explain(&mismatched).verdict(), Err(KernelError::AlgorithmSuiteMismatch { command: AlgSuite::FIPS as u8, capability: AlgSuite::CLASSIC as u8 }));
+}
+
+// --- Structured error tests ---
+
+#[derive(Debug)]
+struct LayeredError {
+    message: &'static str,
+    source: Option<Box<LayeredError>>,
+}
+
+impl std::fmt::Display for LayeredError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+impl std::error::Error for LayeredError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+#[test]
+fn test_kernel_error_codes_are_stable() {
+    let cid = generate_test_cid(1);
+    let cases = [
+        (KernelError::CapabilityNotFound(cid), 100),
+        (KernelError::AlgorithmSuiteMismatch { command: 0, capability: 1 }, 101),
+        (KernelError::Crypto(CryptoError::InvalidSignature), 102),
+        (KernelError::InsufficientRights { granted: 0, required: 1 }, 103),
+        (KernelError::CapabilityExpired { expiry_lc: 1, local_lc: 2 }, 106),
+        (KernelError::ReservedCapabilityKind(0), 112),
+        (KernelError::InvalidCommandLClock { proposed: 1, local_lc: 2 }, 200),
+        (KernelError::LamportClockExhausted, 201),
+        (KernelError::BrokenEventChain { replica: TEST_REPLICA_ID_1, expected: cid, found: None }, 204),
+        (KernelError::Equivocation { replica: TEST_REPLICA_ID_1, first: cid, second: cid }, 205),
+        (KernelError::ReplicaQuarantined(TEST_REPLICA_ID_1), 206),
+        (KernelError::UnadmittedEpoch { replica: TEST_REPLICA_ID_1, epoch: 1 }, 208),
+        (KernelError::EventIdMismatch { expected: cid, computed: cid }, 209),
+        (KernelError::InvariantViolation(Invariant::EntityNotFound(cid)), 300),
+        (KernelError::RuntimeError(ErrorCause::msg("boom")), 301),
+        (KernelError::StateRootMismatch { expected: Box::new(StateRoot { entities: EMPTY_ROOT, capabilities: EMPTY_ROOT }), computed: Box::new(StateRoot { entities: [1; 32], capabilities: EMPTY_ROOT }) }, 309),
+        (KernelError::Command(ErrorCause::msg("bad payload")), 400),
+        (KernelError::Storage(ErrorCause::msg("disk full")), 500),
+        (KernelError::Other("misc".into()), 900),
+    ];
+    for (error, code) in cases {
+        assert_eq!(error.code(), code, "Code changed for {:?}", error);
+    }
+}
+
+#[test]
+fn test_kernel_error_serde_roundtrip() {
+    let errors = vec![
+        KernelError::CapabilityNotFound(generate_test_cid(7)),
+        KernelError::InvalidCommandLClock { proposed: 3, local_lc: 9 },
+        KernelError::Crypto(CryptoError::UnsupportedAlgorithmSuite(42)),
+        KernelError::Rights(crate::rights::RightsError::UnknownRight("X".into())),
+        KernelError::InvariantViolation(Invariant::VersionNotMonotonic { entity: generate_test_cid(2), current: 1, proposed: 3 }),
+        KernelError::RuntimeError(ErrorCause { message: "outer".into(), causes: vec!["inner".into()] }),
+    ];
+    for error in errors {
+        let json = serde_json::to_string(&error).expect("serialise");
+        let back: KernelError = serde_json::from_str(&json).expect("deserialise");
+        assert_eq!(back, error);
+    }
+    let json = serde_json::to_value(KernelError::InvalidCommandLClock { proposed: 3, local_lc: 9 }).unwrap();
+    assert_eq!(json["InvalidCommandLClock"]["proposed"], 3);
+    assert_eq!(json["InvalidCommandLClock"]["local_lc"], 9);
+}
+
+#[test]
+fn test_kernel_error_preserves_cause_chain() {
+    let error = LayeredError {
+        message: "ledger update failed",
+        source: Some(Box::new(LayeredError { message: "account locked", source: None })),
+    };
+    let kernel_error = KernelError::runtime(&error);
+    assert_eq!(
+        kernel_error,
+        KernelError::RuntimeError(ErrorCause { message: "ledger update failed".into(), causes: vec!["account locked".into()] })
+    );
+    assert_eq!(kernel_error.to_string(), "Runtime error: ledger update failed: account locked");
+
+    let from_command: KernelError = crate::command_traits::CommandTraitError::Encoding("truncated".into()).into();
+    assert_eq!(from_command, KernelError::Command(ErrorCause::msg("CommandEncodingError: truncated")));
+    assert_eq!(from_command.code(), 400);
+}
+
+// --- Observer tests ---
+
+/// Observer recording every callback as a short string.
+#[derive(Debug, Default)]
+struct RecordingObserver {
+    calls: std::sync::Mutex<Vec<String>>,
+}
+
+impl RecordingObserver {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+
+    fn push(&self, call: String) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl KernelObserver for RecordingObserver {
+    fn on_command_accepted(&self, event: &Event) {
+        self.push(format!("accepted lc={}", event.lclock));
+    }
+    fn on_command_rejected(&self, _command_id: &CID, _capability: &CID, error: &KernelError) {
+        self.push(format!("rejected code={}", error.code()));
+    }
+    fn on_entity_created(&self, entity: &Entity<Vec<u8>>, _event: &Event) {
+        self.push(format!("created {}", entity.header.id.0[0]));
+    }
+    fn on_entity_updated(&self, entity: &Entity<Vec<u8>>, _event: &Event) {
+        self.push(format!("updated {} v{}", entity.header.id.0[0], entity.header.version));
+    }
+    fn on_capability_changed(&self, capability: &CID, change: &CapabilityChange) {
+        let CapabilityChange::Used { uses } = change;
+        self.push(format!("capability {} uses={}", capability.0[0], uses));
+    }
+    fn on_event_merged(&self, event: &Event) {
+        self.push(format!("merged lc={}", event.lclock));
+    }
+}
+
+#[test]
+fn test_observers_notified_after_apply() {
+    let observer = Arc::new(RecordingObserver::default());
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    kernel.add_observer(observer.clone());
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(1);
+    kernel.state.capabilities.insert(cap_id, voucher);
+    kernel.state.entities.insert(generate_test_cid(51), Arc::new(create_test_entity(51, 1, 0, None)));
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta {
+            new_entities: vec![create_test_entity(50, 1, 0, None)],
+            updated_entities: vec![create_test_entity(51, 2, 0, None)],
+            deleted_entities: Vec::new(),
+        }),
+    };
+
+    let cmd = create_test_command(MockEncodedCmd::new("spend", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    kernel.apply(&cmd).expect("Apply should succeed");
+    assert_eq!(observer.take(), vec!["accepted lc=1", "created 50", "updated 51 v2", "capability 100 uses=1"]);
+
+    let again = create_test_command(MockEncodedCmd::new("spend", 0), 1, TEST_REPLICA_ID_1, cap_id, 2, None);
+    assert!(kernel.apply(&again).is_err());
+    assert_eq!(observer.take(), vec![format!("rejected code={}", KernelError::CapabilityUsesExhausted.code())]);
+}
+
+#[test]
+fn test_observers_see_batches_only_after_commit() {
+    let observer = Arc::new(RecordingObserver::default());
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    kernel.add_observer(observer.clone());
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(2);
+    kernel.state.capabilities.insert(cap_id, voucher);
+
+    let batch: Vec<_> = (0..3u8)
+        .map(|i| create_test_command(MockEncodedCmd::new("use", 0), i as u64, TEST_REPLICA_ID_1, cap_id, 10 + i, None))
+        .collect();
+    assert!(kernel.apply_batch(&batch).is_err());
+    assert_eq!(observer.take(), vec![format!("rejected code={}", KernelError::CapabilityUsesExhausted.code())], "Rolled back commands are never reported");
+
+    let events = kernel.apply_batch(&batch[..2]).expect("Batch should commit");
+    assert_eq!(
+        observer.take(),
+        vec!["accepted lc=1", "capability 100 uses=1", "accepted lc=2", "capability 100 uses=2"],
+        "Each event reports the use count right after its own commit"
+    );
+
+    let mut peer = create_test_kernel(TEST_REPLICA_ID_2).with_observer(RecordingObserver::default());
+    peer.add_observer(observer.clone());
+    peer.process_incoming_event(&events[0]).expect("Merge should succeed");
+    assert_eq!(observer.take(), vec!["merged lc=1", "capability 100 uses=1"]);
+}
+
+// --- Tracing tests ---
+
+/// Layer recording span names and the `outcome` field recorded on them.
+#[cfg(feature = "tracing-subscriber")]
+#[derive(Clone, Default)]
+struct SpanRecorder {
+    spans: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(feature = "tracing-subscriber")]
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+    fn on_new_span(&self, attrs: &tracing::span::Attributes<'_>, _id: &tracing::span::Id, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        self.spans.lock().unwrap().push(attrs.metadata().name().to_string());
+    }
+
+    fn on_record(&self, _id: &tracing::span::Id, values: &tracing::span::Record<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        struct Outcome<'a>(&'a std::sync::Mutex<Vec<String>>);
+        impl tracing::field::Visit for Outcome<'_> {
+            fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                if field.name() == "outcome" {
+                    self.0.lock().unwrap().push(format!("outcome={}", value));
+                }
+            }
+            fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+        }
+        values.record(&mut Outcome(&self.spans));
+    }
+}
+
+#[cfg(feature = "tracing-subscriber")]
+#[test]
+fn test_apply_emits_pipeline_spans() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let recorder = SpanRecorder::default();
+    let subscriber = tracing_subscriber::registry().with(recorder.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+        let cap_id = generate_test_cid(100);
+        kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+        let cmd = create_test_command(MockEncodedCmd::new("ok", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+        let event = kernel.apply(&cmd).expect("Apply should succeed");
+        let missing = create_test_command(MockEncodedCmd::new("no", 0), 1, TEST_REPLICA_ID_1, generate_test_cid(101), 2, None);
+        assert!(kernel.apply(&missing).is_err());
+        create_test_kernel(TEST_REPLICA_ID_2).process_incoming_event(&event).unwrap();
+    });
+
+    assert_eq!(*recorder.spans.lock().unwrap(), vec![
+        "apply", "validate_command", "runtime_execute", "append_delta", "materialise_event", "outcome=accepted",
+        "apply", "validate_command", "outcome=rejected",
+        "process_incoming_event",
+    ]);
+}
+
+// --- Metrics tests ---
+
+#[test]
+fn test_kernel_reports_metrics() {
+    let metrics = Arc::new(KernelMetrics::new());
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider)
+        .with_metrics(metrics.clone());
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::READ, None, AlgSuite::CLASSIC));
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { new_entities: vec![create_test_entity(50, 1, 0, None)], ..Default::default() }),
+    };
+
+    let cmd = create_test_command(MockEncodedCmd::new("create", rights::core::READ), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    kernel.apply(&cmd).expect("Apply should succeed");
+    let denied = create_test_command(MockEncodedCmd::new("write", rights::core::WRITE), 1, TEST_REPLICA_ID_1, cap_id, 2, None);
+    assert!(kernel.apply(&denied).is_err());
+    let missing = create_test_command(MockEncodedCmd::new("x", 0), 1, TEST_REPLICA_ID_1, generate_test_cid(101), 3, None);
+    assert!(kernel.apply(&missing).is_err());
+
+    let mut remote_vc = VClock::default();
+    remote_vc.0.insert(TEST_REPLICA_ID_2, 4);
+    let remote = Event { lclock: 4, replica: TEST_REPLICA_ID_2, vclock: remote_vc, ..kernel.state.event_log[0].clone() };
+    kernel.process_incoming_event(&remote).unwrap();
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.commands_applied, 1);
+    assert_eq!(snapshot.entities_created, 1);
+    assert_eq!(snapshot.entities_updated, 0);
+    assert_eq!(snapshot.event_log_length, 1);
+    assert_eq!(snapshot.events_merged, 1);
+    assert_eq!(snapshot.vclock_width, 2);
+    assert_eq!(snapshot.apply_latency.count, 1);
+    assert_eq!(snapshot.rejections_total(), 2);
+    assert_eq!(snapshot.rejections.get("InsufficientRights"), Some(&1));
+    assert_eq!(snapshot.rejections.get("CapabilityNotFound"), Some(&1));
+    assert!(std::ptr::eq(kernel.metrics().unwrap().as_ref(), metrics.as_ref()));
+}
+
+#[test]
+fn test_event_log_gauge_follows_log_changes() {
+    let (kernel, _, steps) = kernel_with_three_events();
+    let gauge = |metrics: &KernelMetrics| metrics.snapshot().event_log_length;
+    let metrics = Arc::new(KernelMetrics::new());
+    let mut kernel = kernel.with_metrics(metrics.clone());
+    assert_eq!(gauge(&metrics), 3, "Attaching a registry reports the current log");
+    let snapshot = kernel.snapshot(steps[1].0.lclock, AlgSuite::CLASSIC).unwrap();
+    kernel.compact(snapshot.clone()).unwrap();
+    assert_eq!(gauge(&metrics), 1);
+
+    let tail = vec![(steps[2].0.clone(), steps[2].1.clone())];
+    let fresh = || {
+        let metrics = Arc::new(KernelMetrics::new());
+        (Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider).with_metrics(metrics.clone()), metrics)
+    };
+    let (booting, metrics) = fresh();
+    booting.boot_from_snapshot(snapshot.clone(), &tail).unwrap();
+    assert_eq!(gauge(&metrics), 1);
+
+    let mut storage = MemoryStorage::new();
+    storage.append(&LogRecord::Committed(tail)).unwrap();
+    let (recovering, metrics) = fresh();
+    recovering.boot_from_snapshot(snapshot, &[]).unwrap().with_storage(storage).recover().unwrap();
+    assert_eq!(gauge(&metrics), 1);
+}
+
+// --- Tombstone tests ---
+
+fn create_test_tombstone(id_byte: u8, version: u64, lclock: u64) -> EntityHeader {
+    EntityHeader { id: generate_test_cid(id_byte), version, lclock, parent: None }
+}
+
+#[test]
+fn test_apply_deletes_entity_with_tombstone() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    kernel.state.entities.insert(generate_test_cid(60), Arc::new(create_test_entity(60, 3, 0, None)));
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { deleted_entities: vec![create_test_tombstone(60, 4, 0)], ..Default::default() }),
+    };
+
+    let cmd = create_test_command(MockEncodedCmd::new("burn", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    let event = kernel.apply(&cmd).expect("Deletion should commit");
+    assert_eq!(event.deleted_entities, vec![generate_test_cid(60)]);
+    assert!(!kernel.state.entities.contains_key(&generate_test_cid(60)));
+    assert_eq!(kernel.state.tombstones.get(&generate_test_cid(60)), Some(&create_test_tombstone(60, 4, event.lclock)), "Kernel stamps the tombstone lclock");
+}
+
+#[test]
+fn test_tombstone_invariants() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let lc = 1;
+    kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 0, None)));
+
+    let skip_version = StateDelta { deleted_entities: vec![create_test_tombstone(1, 3, lc)], ..Default::default() };
+    assert_eq!(
+        kernel.append_delta(&skip_version, lc),
+        Err(KernelError::InvariantViolation(Invariant::VersionNotMonotonic { entity: generate_test_cid(1), current: 1, proposed: 3 }))
+    );
+    let wrong_lclock = StateDelta { deleted_entities: vec![create_test_tombstone(1, 2, lc + 1)], ..Default::default() };
+    assert!(matches!(kernel.append_delta(&wrong_lclock, lc), Err(KernelError::InvariantViolation(Invariant::LClockMismatch { .. }))));
+    let missing = StateDelta { deleted_entities: vec![create_test_tombstone(2, 1, lc)], ..Default::default() };
+    assert_eq!(kernel.append_delta(&missing, lc), Err(KernelError::InvariantViolation(Invariant::EntityNotFound(generate_test_cid(2)))));
+
+    kernel.append_delta(&StateDelta { deleted_entities: vec![create_test_tombstone(1, 2, lc)], ..Default::default() }, lc).expect("Delete should succeed");
+    let tombstoned = Err(KernelError::InvariantViolation(Invariant::EntityTombstoned(generate_test_cid(1))));
+
+    let recreate = StateDelta { new_entities: vec![create_test_entity(1, 1, 2, None)], ..Default::default() };
+    assert_eq!(kernel.append_delta(&recreate, 2), tombstoned, "Tombstoned CIDs cannot be re-created");
+    let update = StateDelta { updated_entities: vec![create_test_entity(1, 3, 2, None)], ..Default::default() };
+    assert_eq!(kernel.append_delta(&update, 2), tombstoned, "Tombstoned CIDs cannot be updated");
+    let delete_again = StateDelta { deleted_entities: vec![create_test_tombstone(1, 3, 2)], ..Default::default() };
+    assert_eq!(kernel.append_delta(&delete_again, 2), tombstoned, "Tombstoned CIDs cannot be deleted twice");
+}
+
+#[test]
+fn test_deleted_entities_bound_into_event_hash() {
+    let kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let cmd_id = generate_test_cid(1);
+    let cid = [generate_test_cid(2)];
+    let vclock = VClock::default();
+    let updated = kernel.get_event_hash_input_for_test(&cmd_id, 1, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[], &cid, &[], None, &vclock, &[], 0, None);
+    let deleted = kernel.get_event_hash_input_for_test(&cmd_id, 1, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[], &[], &cid, None, &vclock, &[], 0, None);
+    assert_ne!(updated, deleted, "Deleting a CID must not hash like updating it");
+}
+
+// --- Parent causal bound tests ---
+
+#[test]
+fn test_parent_invariants() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 5, None)));
+
+    // Parent observed in Σ, or staged in the same delta.
+    let ok = StateDelta {
+        new_entities: vec![create_test_entity(2, 1, 5, Some(1)), create_test_entity(3, 1, 5, Some(2))],
+        ..Default::default()
+    };
+    kernel.check_delta(&ok, 5).expect("Observed parents with parent.lclock <= child.lclock are valid");
+
+    let too_old = StateDelta { new_entities: vec![create_test_entity(2, 1, 4, Some(1))], ..Default::default() };
+    assert_eq!(
+        kernel.check_delta(&too_old, 4),
+        Err(KernelError::InvariantViolation(Invariant::ParentCausalBound {
+            entity: generate_test_cid(2),
+            parent: generate_test_cid(1),
+            parent_lclock: 5,
+            entity_lclock: 4,
+        }))
+    );
+
+    let orphan = StateDelta { new_entities: vec![create_test_entity(2, 1, 5, Some(9))], ..Default::default() };
+    assert_eq!(
+        kernel.check_delta(&orphan, 5),
+        Err(KernelError::InvariantViolation(Invariant::ParentNotFound { entity: generate_test_cid(2), parent: generate_test_cid(9) }))
+    );
+    let permissive = kernel.clone().with_unobserved_parent_policy(UnobservedParentPolicy::Allow);
+    permissive.check_delta(&orphan, 5).expect("Unobserved parents may be explicitly allowed");
+
+    let own_parent = StateDelta { new_entities: vec![create_test_entity(2, 1, 5, Some(2))], ..Default::default() };
+    assert_eq!(kernel.check_delta(&own_parent, 5), Err(KernelError::InvariantViolation(Invariant::ParentCycle(generate_test_cid(2)))));
+
+    // Re-parenting an existing entity under its own descendant closes a loop.
+    kernel.append_delta(&StateDelta { new_entities: vec![create_test_entity(2, 1, 6, Some(1))], ..Default::default() }, 6).unwrap();
+    let loop_back = StateDelta { updated_entities: vec![create_test_entity(1, 2, 7, Some(2))], ..Default::default() };
+    assert_eq!(kernel.check_delta(&loop_back, 7), Err(KernelError::InvariantViolation(Invariant::ParentCycle(generate_test_cid(1)))));
+}
+
+// --- Version history tests ---
+
+#[test]
+fn test_point_in_time_entity_reads() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let id = generate_test_cid(1);
+    // Genesis entity placed directly into Σ is captured on its first replacement.
+    kernel.state.entities.insert(id, Arc::new(create_test_entity(1, 1, 0, None)));
+    for (version, lclock) in [(2, 3), (3, 7)] {
+        let delta = StateDelta { updated_entities: vec![create_test_entity(1, version, lclock, None)], ..Default::default() };
+        kernel.append_delta(&delta, lclock).unwrap();
+    }
+
+    assert_eq!(kernel.get_entity_at_version(&id, 1).unwrap().header.lclock, 0);
+    assert_eq!(kernel.get_entity_at_version(&id, 2).unwrap().header.lclock, 3);
+    assert_eq!(kernel.get_entity_at_version(&id, 3).as_ref(), kernel.state.entities.get(&id));
+    assert!(kernel.get_entity_at_version(&id, 4).is_none());
+
+    assert_eq!(kernel.get_entity_as_of(&id, 2).unwrap().header.version, 1);
+    assert_eq!(kernel.get_entity_as_of(&id, 6).unwrap().header.version, 2);
+    assert_eq!(kernel.get_entity_as_of(&id, 100).unwrap().header.version, 3);
+
+    // Reads after deletion see the entity up to, but not at, its tombstone.
+    let tombstone = EntityHeader { id, version: 4, lclock: 9, parent: None };
+    kernel.append_delta(&StateDelta { deleted_entities: vec![tombstone], ..Default::default() }, 9).unwrap();
+    assert_eq!(kernel.get_entity_as_of(&id, 8).unwrap().header.version, 3);
+    assert!(kernel.get_entity_as_of(&id, 9).is_none());
+    assert!(kernel.get_entity_at_version(&id, 4).is_none());
+    assert_eq!(kernel.history.history(&id).len(), 4);
+
+    // Entities never written are unknown at every point in time.
+    assert!(kernel.get_entity_as_of(&generate_test_cid(2), u64::MAX).is_none());
+}
+
+#[test]
+fn test_version_history_retention_and_sharing() {
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_version_retention(RetentionPolicy::KeepLast(2));
+    let id = generate_test_cid(1);
+    kernel.append_delta(&StateDelta { new_entities: vec![create_test_entity(1, 1, 1, None)], ..Default::default() }, 1).unwrap();
+    for version in 2..=4 {
+        let delta = StateDelta { updated_entities: vec![create_test_entity(1, version, version, None)], ..Default::default() };
+        kernel.append_delta(&delta, version).unwrap();
+    }
+    assert!(kernel.get_entity_at_version(&id, 2).is_none(), "Pruned versions are no longer readable");
+    assert!(kernel.get_entity_as_of(&id, 2).is_none());
+    assert_eq!(kernel.get_entity_at_version(&id, 3).unwrap().header.version, 3);
+
+    // Σ and the history hold the live version once; clones share every version.
+    let live = kernel.get_entity_at_version(&id, 4).unwrap();
+    assert!(Arc::ptr_eq(&live, &kernel.state.entities[&id]));
+    assert!(matches!(kernel.history.at_version(&id, 4), Some(EntityVersion::Live(recorded)) if Arc::ptr_eq(recorded, &live)));
+    let clone = kernel.clone();
+    assert!(Arc::ptr_eq(&kernel.get_entity_at_version(&id, 3).unwrap(), &clone.get_entity_at_version(&id, 3).unwrap()));
+}
+
+#[test]
+fn test_failed_batch_restores_history() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(1);
+    kernel.state.capabilities.insert(cap_id, voucher);
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { new_entities: vec![create_test_entity(50, 1, 0, None)], ..Default::default() }),
+    };
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("first", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None),
+        create_test_command(MockEncodedCmd::new("second", 0), 1, TEST_REPLICA_ID_1, cap_id, 2, None),
+    ];
+    assert!(kernel.apply_batch(&batch).is_err());
+    assert!(kernel.history.is_empty(), "Rolled back versions must not linger in history");
+}
+
+#[test]
+fn test_failed_batch_undoes_its_writes() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    let mut capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    capability.max_uses = Some(5);
+    kernel.state.capabilities.insert(cap_id, capability);
+    kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 0, None)));
+    kernel.state.entities.insert(generate_test_cid(2), Arc::new(create_test_entity(2, 1, 0, None)));
+    kernel.rebuild_state_tree().unwrap();
+    kernel.runtime.delta_to_produce = Some(StateDelta { updated_entities: vec![create_test_entity(1, 2, 0, None)], ..Default::default() });
+    kernel.apply(&create_test_command(MockEncodedCmd::new("setup", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None)).unwrap();
+    let before = kernel.clone();
+
+    // The first command writes every kind of change; replaying it fails on versions.
+    kernel.runtime.delta_to_produce = Some(StateDelta {
+        new_entities: vec![create_test_entity(3, 1, 0, None)],
+        updated_entities: vec![create_test_entity(1, 3, 0, None)],
+        deleted_entities: vec![create_test_tombstone(2, 2, 0)],
+    });
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("a", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 2, None),
+        create_test_command(MockEncodedCmd::new("b", 0), kernel.local_lc + 1, TEST_REPLICA_ID_1, cap_id, 3, None),
+    ];
+    assert_eq!(kernel.apply_batch(&batch).map_err(|err| err.index), Err(1));
+    assert_eq!(kernel.state, before.state);
+    assert_eq!(kernel.history, before.history);
+    assert_eq!((kernel.local_lc, &kernel.local_vc, kernel.chain_head), (before.local_lc, &before.local_vc, before.chain_head));
+    assert_eq!((kernel.state_root(), kernel.log_root()), (before.state_root(), before.log_root()));
+}
+
+// --- State reconstruction tests ---
+
+/// A kernel with a genesis entity and three applied commands that create,
+/// update and delete entities. Returns the genesis state and, for each command,
+/// its event, the delta it applied and the state right after it.
+#[allow(clippy::type_complexity)]
+fn kernel_with_three_events() -> (
+    Kernel<PlaceholderCryptoProvider, MockRuntimeWithDelta>,
+    SystemState,
+    Vec<(Event, StateDelta, SystemState)>,
+) {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    let capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::ALL, None, AlgSuite::CLASSIC);
+    kernel.state.capabilities.insert(cap_id, capability);
+    kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 0, None)));
+    let genesis = kernel.state.clone();
+
+    let deltas = [
+        StateDelta {
+            new_entities: vec![create_test_entity(2, 1, 1, None)],
+            updated_entities: vec![create_test_entity(1, 2, 1, None)],
+            ..Default::default()
+        },
+        StateDelta {
+            updated_entities: vec![create_test_entity(2, 2, 2, None)],
+            deleted_entities: vec![create_test_tombstone(1, 3, 2)],
+            ..Default::default()
+        },
+        StateDelta { new_entities: vec![create_test_entity(3, 1, 3, None)], ..Default::default() },
+    ];
+    let mut steps = Vec::new();
+    for (command_byte, delta) in (50u8..).zip(deltas) {
+        kernel.runtime = MockRuntimeWithDelta { delta_to_produce: Some(delta.clone()) };
+        let cmd = create_test_command(MockEncodedCmd::new("step", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, command_byte, None);
+        let event = kernel.apply(&cmd).expect("Command should apply");
+        steps.push((event, delta, kernel.state.clone()));
+    }
+    (kernel, genesis, steps)
+}
+
+#[test]
+fn test_state_reconstruction_as_of_event_and_lclock() {
+    let (mut kernel, genesis, steps) = kernel_with_three_events();
+    for (event, _, expected) in &steps {
+        assert_eq!(&kernel.state_as_of_event(&event.id).unwrap(), expected, "State after event at lclock {}", event.lclock);
+        assert_eq!(&kernel.state_as_of_lclock(event.lclock).unwrap(), expected);
+    }
+    assert_eq!(kernel.state_as_of_lclock(0).unwrap(), genesis, "Before any event only genesis entities exist");
+    assert_eq!(kernel.state_as_of_lclock(u64::MAX).unwrap(), kernel.state);
+    assert_eq!(kernel.state_as_of_event(&generate_test_cid(99)), Err(KernelError::EventNotFound(generate_test_cid(99))));
+
+    // A log that contradicts the recorded versions is reported, not replayed.
+    let mut tampered = kernel.clone();
+    let replayed = tampered.state.event_log[2].clone();
+    tampered.state.event_log.push(replayed.clone());
+    assert_eq!(
+        tampered.state_as_of_lclock(u64::MAX),
+        Err(KernelError::ReplayMismatch { event: replayed.id, invariant: Box::new(Invariant::EntityAlreadyExists(generate_test_cid(3))) })
+    );
+
+    // Pruned versions cannot be replayed.
+    kernel.history.set_retention(RetentionPolicy::KeepLast(1));
+    assert_eq!(
+        kernel.state_as_of_lclock(0),
+        Err(KernelError::HistoryUnavailable { entity: generate_test_cid(1), lclock: 1 })
+    );
+}
+
+// --- Snapshot and compaction tests ---
+
+#[test]
+fn test_snapshot_is_content_addressed_and_signed() {
+    let (kernel, _, _) = kernel_with_three_events();
+    let mut snapshot = kernel.snapshot(u64::MAX, AlgSuite::CLASSIC).unwrap();
+    assert_eq!(snapshot.lclock, kernel.local_lc, "Snapshots cannot run ahead of the local clock");
+    assert_eq!(snapshot.vclock, kernel.local_vc);
+    assert_eq!(snapshot.last_event, kernel.state.event_log.last().map(|e| e.id));
+    assert_eq!(snapshot.id, kernel.snapshot_id(&snapshot).unwrap());
+    assert_eq!(snapshot.to_state(), SystemState { event_log: Vec::new(), ..kernel.state.clone() });
+
+    // The canonical encoding does not depend on map iteration order.
+    let mut reordered = snapshot.clone();
+    let mut entries: Vec<_> = snapshot.entities.clone().into_iter().collect();
+    entries.reverse();
+    reordered.entities = entries.into_iter().collect();
+    assert_eq!(reordered.content_bytes(), snapshot.content_bytes());
+    let mut changed = snapshot.clone();
+    Arc::make_mut(changed.entities.get_mut(&generate_test_cid(2)).unwrap()).body.push(0);
+    assert_ne!(changed.content_bytes(), snapshot.content_bytes());
+
+    let replica_key = PublicKeyBytes([7; 32]);
+    assert_eq!(
+        kernel.verify_snapshot(&snapshot, &replica_key, AlgSuite::CLASSIC),
+        Err(KernelError::Crypto(CryptoError::InvalidSignature)),
+        "Unsigned snapshots do not verify"
+    );
+    snapshot.sign(&EchoSigner).unwrap();
+    kernel.verify_snapshot(&snapshot, &replica_key, AlgSuite::CLASSIC).expect("Signed snapshot should verify");
+
+    let mut forged = snapshot.clone();
+    forged.id = generate_test_cid(9);
+    assert_eq!(
+        kernel.verify_snapshot(&forged, &replica_key, AlgSuite::CLASSIC),
+        Err(KernelError::SnapshotMismatch { expected: generate_test_cid(9), computed: snapshot.id })
+    );
+}
+
+#[test]
+fn test_boot_verifies_tail_ids_and_state_root() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    kernel.rebuild_state_tree().unwrap();
+    kernel.runtime.delta_to_produce = Some(StateDelta { new_entities: vec![create_test_entity(1, 1, 0, None)], ..Default::default() });
+    kernel.apply(&create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None)).unwrap();
+    let snapshot = kernel.snapshot(kernel.local_lc, AlgSuite::CLASSIC).unwrap();
+    assert_eq!(snapshot.state_root, Some(kernel.state_root()));
+    kernel.runtime.delta_to_produce = Some(StateDelta { updated_entities: vec![create_test_entity(1, 2, 0, None)], ..Default::default() });
+    let event = kernel.apply(&create_test_command(MockEncodedCmd::new("b", 0), 1, TEST_REPLICA_ID_1, cap_id, 2, None)).unwrap();
+    let delta = StateDelta { updated_entities: vec![create_test_entity(1, 2, event.lclock, None)], ..Default::default() };
+
+    let boot = |snapshot: &Snapshot, tail: &[(Event, StateDelta)]| {
+        Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider).boot_from_snapshot(snapshot.clone(), tail)
+    };
+    let booted = boot(&snapshot, &[(event.clone(), delta.clone())]).unwrap();
+    assert_eq!((booted.state_root(), booted.log_root()), (kernel.state_root(), kernel.log_root()));
+
+    // A tail event altered after the fact no longer matches its id.
+    let mut forged = event.clone();
+    forged.caused_by = generate_test_cid(9);
+    assert!(matches!(boot(&snapshot, &[(forged, delta)]), Err(KernelError::InvalidSnapshotTail { .. })));
+
+    // A re-addressed snapshot whose recorded root differs from its contents is refused.
+    let mut lying = snapshot.clone();
+    lying.state_root = Some(StateRoot { entities: EMPTY_ROOT, capabilities: EMPTY_ROOT });
+    lying.id = kernel.snapshot_id(&lying).unwrap();
+    assert!(matches!(boot(&lying, &[]), Err(KernelError::StateRootMismatch { .. })));
+}
+
+#[test]
+fn test_compaction_and_boot_from_snapshot() {
+    let (mut kernel, _, steps) = kernel_with_three_events();
+    let (second, _, after_second) = &steps[1];
+    let snapshot = kernel.snapshot(second.lclock, AlgSuite::CLASSIC).unwrap();
+    assert_eq!(snapshot.last_event, Some(second.id));
+    assert_eq!(snapshot.vclock, second.vclock);
+    assert_eq!(snapshot.entities, after_second.entities);
+
+    // A snapshot that does not match local history cannot replace it.
+    let mut foreign = snapshot.clone();
+    foreign.entities.remove(&generate_test_cid(2));
+    foreign.id = kernel.snapshot_id(&foreign).unwrap();
+    assert!(matches!(kernel.compact(foreign), Err(KernelError::SnapshotMismatch { .. })));
+
+    let before = kernel.state.clone();
+    assert_eq!(kernel.compact(snapshot.clone()), Ok(2));
+    assert_eq!(kernel.state.event_log, before.event_log[2..]);
+    assert_eq!(kernel.state_as_of_lclock(0), Err(KernelError::LogCompacted(snapshot.lclock)));
+    assert_eq!(kernel.state_as_of_event(&steps[0].0.id), Err(KernelError::EventNotFound(steps[0].0.id)));
+    assert_eq!(kernel.state_as_of_lclock(u64::MAX).unwrap(), kernel.state, "Replay starts from the base snapshot");
+
+    // A fresh replica boots from the snapshot plus the tail and ends up identical.
+    let tail = vec![(steps[2].0.clone(), steps[2].1.clone())];
+    let booted = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider)
+        .boot_from_snapshot(snapshot.clone(), &tail)
+        .unwrap();
+    assert_eq!(booted.state, kernel.state);
+    assert_eq!(booted.local_lc, kernel.local_lc);
+    assert_eq!(booted.local_vc, kernel.local_vc);
+    assert_eq!(booted.snapshot(u64::MAX, AlgSuite::CLASSIC), kernel.snapshot(u64::MAX, AlgSuite::CLASSIC));
+
+    let boot = |tail: &[(Event, StateDelta)]| {
+        Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider)
+            .boot_from_snapshot(snapshot.clone(), tail)
+            .map(|_| ())
+    };
+    let stale = vec![(steps[1].0.clone(), steps[1].1.clone())];
+    assert!(matches!(boot(&stale), Err(KernelError::InvalidSnapshotTail { .. })), "Tail must start after the snapshot");
+
+    let mut unlisted = tail.clone();
+    let lclock = unlisted[0].0.lclock;
+    unlisted[0].1.new_entities.push(create_test_entity(4, 1, lclock, None));
+    assert!(matches!(boot(&unlisted), Err(KernelError::InvalidSnapshotTail { .. })), "Delta must match the event");
+
+    let mut recreate = tail.clone();
+    recreate[0].0.new_entities = vec![generate_test_cid(2)];
+    recreate[0].1.new_entities = vec![create_test_entity(2, 1, recreate[0].0.lclock, None)];
+    assert_eq!(
+        boot(&recreate),
+        Err(KernelError::ReplayMismatch {
+            event: recreate[0].0.id,
+            invariant: Box::new(Invariant::EntityAlreadyExists(generate_test_cid(2))),
+        })
+    );
+}
+
+// --- Event log accumulator tests ---
+
+#[test]
+fn test_event_log_accumulator_proofs() {
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(6);
+    kernel.state.capabilities.insert(cap_id, voucher);
+    assert_eq!(kernel.log_tree.size(), 0);
+
+    let mut roots = vec![kernel.log_root().unwrap()];
+    for command_byte in 1..=5u8 {
+        let cmd = create_test_command(MockEncodedCmd::new("append", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, command_byte, None);
+        kernel.apply(&cmd).expect("Command should apply");
+        roots.push(kernel.log_root().unwrap());
+    }
+    assert_eq!(kernel.log_tree.size(), 5);
+
+    let provider = DigestCryptoProvider;
+    let root = kernel.log_root().unwrap();
+    for (index, event) in kernel.state.event_log.iter().enumerate() {
+        let proof = kernel.event_inclusion_proof(&event.id).unwrap();
+        assert_eq!(proof.leaf_index, index as u64);
+        assert_eq!(proof.verify(&provider, AlgSuite::CLASSIC, &event.id, &root), Ok(true));
+        assert_eq!(proof.verify(&provider, AlgSuite::CLASSIC, &generate_test_cid(99), &root), Ok(false));
+    }
+    assert_eq!(kernel.event_inclusion_proof(&generate_test_cid(99)), Err(KernelError::EventNotFound(generate_test_cid(99))));
+
+    for old_size in 1..=5u64 {
+        let proof = kernel.log_consistency_proof(old_size, 5).unwrap();
+        assert_eq!(proof.verify(&provider, AlgSuite::CLASSIC, &roots[old_size as usize], &root), Ok(true));
+        assert_eq!(proof.verify(&provider, AlgSuite::CLASSIC, &roots[0], &root), Ok(false));
+    }
+    assert!(kernel.log_consistency_proof(5, 4).is_err());
+
+    // A failed batch leaves the accumulator as it was.
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("sixth", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 6, None),
+        create_test_command(MockEncodedCmd::new("seventh", 0), kernel.local_lc + 1, TEST_REPLICA_ID_1, cap_id, 7, None),
+    ];
+    assert_eq!(kernel.apply_batch(&batch).map_err(|err| err.error), Err(KernelError::CapabilityUsesExhausted));
+    assert_eq!(kernel.log_tree.size(), 5);
+    assert_eq!(kernel.log_root().unwrap(), root);
+
+    // Compaction keeps the accumulator, and a replica booted from a snapshot
+    // continues it from the snapshot's peaks.
+    let third = kernel.state.event_log[2].clone();
+    let snapshot = kernel.snapshot(third.lclock, AlgSuite::CLASSIC).unwrap();
+    assert_eq!(snapshot.log_size, 3);
+    assert_eq!(kernel.compact(snapshot.clone()), Ok(3));
+    let fifth = kernel.state.event_log[1].clone();
+    let proof = kernel.event_inclusion_proof(&fifth.id).unwrap();
+    assert_eq!(proof.leaf_index, 4);
+    assert_eq!(proof.verify(&provider, AlgSuite::CLASSIC, &fifth.id, &root), Ok(true));
+
+    let tail: Vec<(Event, StateDelta)> = kernel.state.event_log.iter().map(|event| (event.clone(), StateDelta::default())).collect();
+    let booted = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider)
+        .boot_from_snapshot(snapshot, &tail)
+        .unwrap();
+    assert_eq!(booted.log_tree.size(), 5);
+    assert_eq!(booted.log_root().unwrap(), root);
+}
+
+// --- State commitment tests ---
+
+#[test]
+fn test_state_root_tracks_sigma_with_proofs() {
+    let seed = |kernel: &mut Kernel<DigestCryptoProvider, MockRuntimeWithDelta>, cap_id: CID| {
+        let mut capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::ALL, None, AlgSuite::CLASSIC);
+        capability.max_uses = Some(10);
+        kernel.state.capabilities.insert(cap_id, capability);
+        kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 0, None)));
+        kernel.rebuild_state_tree().unwrap();
+    };
+    let cap_id = generate_test_cid(100);
+    let mut kernel = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+    let mut peer = Kernel::new(TEST_REPLICA_ID_2, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+    assert_eq!(kernel.state_root(), StateRoot { entities: EMPTY_ROOT, capabilities: EMPTY_ROOT });
+    seed(&mut kernel, cap_id);
+    seed(&mut peer, cap_id);
+    assert_eq!(kernel.state_root(), peer.state_root(), "Replicas seeded alike agree");
+
+    let deltas = [
+        StateDelta {
+            new_entities: vec![create_test_entity(2, 1, 1, None)],
+            updated_entities: vec![create_test_entity(1, 2, 1, None)],
+            ..Default::default()
+        },
+        StateDelta {
+            updated_entities: vec![create_test_entity(2, 2, 2, None)],
+            deleted_entities: vec![create_test_tombstone(1, 3, 2)],
+            ..Default::default()
+        },
+        StateDelta { new_entities: vec![create_test_entity(3, 1, 3, None)], ..Default::default() },
+    ];
+    let mut roots = vec![kernel.state_root()];
+    for (command_byte, delta) in (50u8..).zip(deltas) {
+        kernel.runtime = MockRuntimeWithDelta { delta_to_produce: Some(delta) };
+        let cmd = create_test_command(MockEncodedCmd::new("step", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, command_byte, None);
+        let event = kernel.apply(&cmd).expect("Command should apply");
+        let rebuilt = StateTree::from_state(&DigestCryptoProvider, AlgSuite::CLASSIC, &kernel.state).unwrap();
+        assert_eq!(kernel.state_root(), rebuilt.root(), "Incremental updates match a rebuild after lclock {}", event.lclock);
+        assert!(!roots.contains(&kernel.state_root()));
+        roots.push(kernel.state_root());
+
+        // Merged events replicate capability uses into the peer's commitment.
+        peer.process_incoming_event(&event).unwrap();
+        assert_eq!(peer.state_root().capabilities, kernel.state_root().capabilities);
+    }
+
+    let p = DigestCryptoProvider;
+    let root = kernel.state_root();
+    let live = &kernel.state.entities[&generate_test_cid(2)];
+    let live_value = entity_value_hash(&p, AlgSuite::CLASSIC, live).unwrap();
+    let proof = kernel.entity_proof(&generate_test_cid(2));
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &root.entities, &generate_test_cid(2), Some(&live_value)), Ok(true));
+    let stale = entity_value_hash(&p, AlgSuite::CLASSIC, &create_test_entity(2, 1, 1, None)).unwrap();
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &root.entities, &generate_test_cid(2), Some(&stale)), Ok(false));
+
+    let tombstone = tombstone_value_hash(&p, AlgSuite::CLASSIC, &kernel.state.tombstones[&generate_test_cid(1)]).unwrap();
+    let proof = kernel.entity_proof(&generate_test_cid(1));
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &root.entities, &generate_test_cid(1), Some(&tombstone)), Ok(true));
+
+    let proof = kernel.entity_proof(&generate_test_cid(99));
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &root.entities, &generate_test_cid(99), None), Ok(true));
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &roots[0].entities, &generate_test_cid(99), None), Ok(false));
+
+    let capability = &kernel.state.capabilities[&cap_id];
+    let used_thrice = capability_value_hash(&p, AlgSuite::CLASSIC, capability, 3).unwrap();
+    let proof = kernel.capability_proof(&cap_id);
+    assert_eq!(proof.verify(&p, AlgSuite::CLASSIC, &root.capabilities, &cap_id, Some(&used_thrice)), Ok(true));
+
+    // A failed batch leaves the commitments as they were.
+    kernel.runtime = MockRuntimeWithDelta { delta_to_produce: Some(StateDelta { new_entities: vec![create_test_entity(4, 1, 4, None)], ..Default::default() }) };
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("fourth", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 60, None),
+        create_test_command(MockEncodedCmd::new("fourth-again", 0), kernel.local_lc + 1, TEST_REPLICA_ID_1, cap_id, 61, None),
+    ];
+    assert!(kernel.apply_batch(&batch).is_err(), "Second command recreates entity 4");
+    assert_eq!(kernel.state_root(), root);
+
+    // Booting from a snapshot recomputes the commitments.
+    let snapshot = kernel.snapshot(u64::MAX, AlgSuite::CLASSIC).unwrap();
+    let booted = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), DigestCryptoProvider)
+        .boot_from_snapshot(snapshot, &[])
+        .unwrap();
+    assert_eq!(booted.state_root(), root);
+}
+
+// --- Replica succession tests ---
+
+#[test]
+fn test_replica_succession_on_lamport_overflow() {
+    let mut predecessor = Kernel::new(TEST_REPLICA_ID_1, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    let (cap_id, timed_cap_id) = (generate_test_cid(100), generate_test_cid(101));
+    let capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::ALL, None, AlgSuite::CLASSIC);
+    let timed = create_test_capability(timed_cap_id, [1u8; 32], generate_test_cid(0), rights::core::ALL, Some(u64::MAX), AlgSuite::CLASSIC);
+    predecessor.state.capabilities.insert(cap_id, capability);
+    predecessor.state.capabilities.insert(timed_cap_id, timed);
+    predecessor.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { new_entities: vec![create_test_entity(2, 1, 0, None)], ..Default::default() }),
+    };
+    predecessor.local_lc = 1000;
+    let create = create_test_command(MockEncodedCmd::new("create", 0), 1000, TEST_REPLICA_ID_1, cap_id, 50, None);
+    predecessor.apply(&create).unwrap();
+
+    // Lamport bounds cannot be carried into another epoch, so a replica
+    // holding one that has not passed cannot retire.
+    assert!(matches!(predecessor.hand_off(TEST_REPLICA_ID_2, AlgSuite::CLASSIC), Err(KernelError::InvalidHandoff(_))));
+    assert_eq!(predecessor.successor, None);
+
+    predecessor.local_lc = u64::MAX;
+    predecessor.local_vc.0.insert(TEST_REPLICA_ID_1, u64::MAX);
+    let late = create_test_command(MockEncodedCmd::new("late", 0), u64::MAX, TEST_REPLICA_ID_1, cap_id, 51, None);
+    assert_eq!(predecessor.apply(&late), Err(KernelError::LamportClockExhausted));
+
+    let mut handoff = predecessor.hand_off(TEST_REPLICA_ID_2, AlgSuite::CLASSIC).unwrap();
+    assert_eq!((handoff.predecessor, handoff.successor, handoff.epoch), (TEST_REPLICA_ID_1, TEST_REPLICA_ID_2, 1));
+    assert_eq!(handoff.snapshot.lclock, u64::MAX);
+    handoff.sign(&EchoSigner).unwrap();
+    predecessor.verify_handoff(&handoff, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC).unwrap();
+    let retired = KernelError::ReplicaRetired { successor: TEST_REPLICA_ID_2 };
+    assert_eq!(predecessor.apply(&late), Err(retired.clone()), "A retired replica refuses commands");
+    assert_eq!(predecessor.hand_off(TEST_REPLICA_ID_2, AlgSuite::CLASSIC), Err(retired));
+
+    let stranger = Kernel::new(TEST_REPLICA_ID_3, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    assert!(matches!(stranger.boot_as_successor(&handoff), Err(KernelError::InvalidHandoff(_))));
+
+    // The successor inherits Σ and the vector clock in a fresh epoch; only
+    // the expired time-bounded capability is left behind.
+    let mut successor = Kernel::new(TEST_REPLICA_ID_2, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider)
+        .boot_as_successor(&handoff)
+        .unwrap();
+    assert_eq!((successor.epoch, successor.local_lc), (1, 0));
+    let mut inherited_vc = predecessor.local_vc.clone();
+    inherited_vc.0.insert(TEST_REPLICA_ID_2, 0);
+    assert_eq!(successor.local_vc, inherited_vc);
+    assert_eq!(successor.state.entities[&generate_test_cid(2)].header.lclock, 1001, "Entities keep their lclock");
+    assert!(successor.state.capabilities.contains_key(&cap_id));
+    assert!(!successor.state.capabilities.contains_key(&timed_cap_id));
+    assert_eq!(successor.state_as_of_lclock(0).unwrap(), successor.state);
+    assert!(successor.get_entity_as_of(&generate_test_cid(2), 0).is_some());
+
+    // Entities written in an earlier epoch precede every entity of this one.
+    successor.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta { new_entities: vec![create_test_entity(3, 1, 1, Some(2))], ..Default::default() }),
+    };
+    let update = create_test_command(MockEncodedCmd::new("update", 0), 0, TEST_REPLICA_ID_2, cap_id, 52, None);
+    let event = successor.apply(&update).unwrap();
+    assert_eq!((event.epoch, event.lclock), (1, 1));
+    assert_eq!(event.vclock.0.get(&TEST_REPLICA_ID_2), Some(&1));
+    assert_eq!(event.vclock.0.get(&TEST_REPLICA_ID_1), Some(&u64::MAX));
+
+    // A peer exhausted by the predecessor's clock admits the successor only
+    // through a verified handoff, and keeps its clock and state.
+    let mut peer = create_test_kernel(TEST_REPLICA_ID_3);
+    let last_words = Event { lclock: u64::MAX, vclock: predecessor.local_vc.clone(), ..predecessor.state.event_log[0].clone() };
+    peer.process_incoming_event(&last_words).unwrap();
+    assert_eq!(peer.local_lc, u64::MAX);
+    let before = (peer.local_lc, peer.local_vc.clone(), peer.state.clone());
+    assert_eq!(
+        peer.process_incoming_event(&event),
+        Err(KernelError::UnadmittedEpoch { replica: TEST_REPLICA_ID_2, epoch: 1 })
+    );
+    assert_eq!((peer.local_lc, peer.local_vc.clone(), peer.state.clone()), before);
+    let unsigned = Handoff { signature: None, ..handoff.clone() };
+    assert!(peer.accept_handoff(&unsigned, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC).is_err());
+    let skipping = Handoff { epoch: 2, ..handoff.clone() };
+    assert!(matches!(peer.accept_handoff(&skipping, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC), Err(KernelError::InvalidHandoff(_))));
+    assert!(peer.replica_epochs.is_empty());
+
+    peer.accept_handoff(&handoff, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC).unwrap();
+    peer.process_incoming_event(&event).unwrap();
+    assert_eq!((peer.epoch, peer.local_lc), (1, u64::MAX), "The peer moves into the epoch but keeps its clock");
+    assert_eq!(peer.local_vc.0.get(&TEST_REPLICA_ID_2), Some(&1));
+    assert_eq!(peer.state, before.2, "Nor does it wipe the peer's state");
+    peer.process_incoming_event(&last_words).unwrap();
+    assert_eq!(peer.local_vc.0.get(&TEST_REPLICA_ID_1), Some(&u64::MAX), "Vector clocks never regress");
+
+    // A peer that applies a command after merging the successor's event
+    // orders its own event after it, and others admit that event once they
+    // have reached the epoch themselves.
+    let mut follower = Kernel::new(TEST_REPLICA_ID_3, MockRuntimeWithDelta::default(), PlaceholderCryptoProvider);
+    follower.state.capabilities.insert(cap_id, successor.state.capabilities[&cap_id].clone());
+    follower.local_lc = 10;
+    follower.accept_handoff(&handoff, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC).unwrap();
+    follower.process_incoming_event(&event).unwrap();
+    assert_eq!((follower.epoch, follower.local_lc), (1, 10));
+    let reply = create_test_command(MockEncodedCmd::new("reply", 0), follower.local_lc, TEST_REPLICA_ID_3, cap_id, 53, None);
+    let answer = follower.apply(&reply).unwrap();
+    assert!((answer.epoch, answer.lclock) > (event.epoch, event.lclock));
+    assert_eq!(answer.vclock.0.get(&TEST_REPLICA_ID_2), Some(&1));
+
+    let mut witness = create_test_kernel(ReplicaIdBytes([4u8; 16]));
+    assert_eq!(
+        witness.process_incoming_event(&answer),
+        Err(KernelError::UnadmittedEpoch { replica: TEST_REPLICA_ID_3, epoch: 1 })
+    );
+    witness.accept_handoff(&handoff, &PublicKeyBytes([7; 32]), AlgSuite::CLASSIC).unwrap();
+    witness.process_incoming_event(&event).unwrap();
+    witness.process_incoming_event(&answer).unwrap();
+    assert_eq!((witness.epoch, witness.local_lc), (1, answer.lclock));
+}
+
+#[test]
+fn test_event_hash_input_binds_epoch() {
+    let kernel = create_test_kernel(TEST_REPLICA_ID_1);
+    let input = |epoch| {
+        kernel.get_event_hash_input_for_test(&generate_test_cid(1), 1, &TEST_REPLICA_ID_1, AlgSuite::CLASSIC as u8, &[], &[], &[], None, &VClock::default(), &[], epoch, None)
+    };
+    assert_ne!(input(0), input(1));
+    assert_ne!(input(1), input(2));
+}
+
+// --- Event chain tests ---
+
+#[test]
+fn test_events_chain_per_replica() {
+    let mut author = create_test_kernel(TEST_REPLICA_ID_2);
+    let cap_id = generate_test_cid(100);
+    author.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    let first = author.apply(&create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_2, cap_id, 1, None)).unwrap();
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("b", 0), 1, TEST_REPLICA_ID_2, cap_id, 2, None),
+        create_test_command(MockEncodedCmd::new("c", 0), 2, TEST_REPLICA_ID_2, cap_id, 3, None),
+    ];
+    let rest = author.apply_batch(&batch).unwrap();
+    let (second, third) = (rest[0].clone(), rest[1].clone());
+    assert_eq!(first.prev_event, None);
+    assert_eq!(second.prev_event, Some(first.id));
+    assert_eq!(third.prev_event, Some(second.id), "Events within a batch chain too");
+    assert_eq!(author.chain_head, Some(third.id));
+
+    // A failed batch leaves the chain where it was.
+    let failing = vec![
+        create_test_command(MockEncodedCmd::new("d", 0), 3, TEST_REPLICA_ID_2, cap_id, 4, None),
+        create_test_command(MockEncodedCmd::new("e", 0), 4, TEST_REPLICA_ID_2, generate_test_cid(101), 5, None),
+    ];
+    assert!(author.apply_batch(&failing).is_err());
+    assert_eq!(author.chain_head, Some(third.id));
+
+    let observer = Arc::new(RecordingObserver::default());
+    let mut peer = create_test_kernel(TEST_REPLICA_ID_1).with_storage(MemoryStorage::new());
+    peer.add_observer(observer.clone());
+    peer.process_incoming_event(&first).unwrap();
+    peer.process_incoming_event(&first).expect("Redelivery of the latest event is tolerated");
+    assert_eq!(observer.take(), vec!["merged lc=1"], "A redelivered head is not merged again");
+    assert_eq!(storage::lock(peer.storage.as_ref().unwrap()).unwrap().records().unwrap().len(), 1);
+
+    // An event whose id does not match its contents is refused before any check trusts the id.
+    let forged = Event { id: generate_test_cid(78), ..second.clone() };
+    assert_eq!(
+        peer.process_incoming_event(&forged),
+        Err(KernelError::EventIdMismatch { expected: forged.id, computed: second.id })
+    );
+    peer.process_incoming_event(&second).unwrap();
+    assert_eq!(peer.replica_heads.get(&TEST_REPLICA_ID_2), Some(&second.id));
+
+    // A fork: another event claiming to follow `first`, at a clock position
+    // no merged event holds (same-position conflicts are equivocations).
+    let mut fork = Event { caused_by: generate_test_cid(77), lclock: 9, vclock: VClock::default(), ..second.clone() };
+    fork.id = author.event_id(&fork).unwrap();
+    let before = (peer.local_lc, peer.local_vc.clone(), peer.state.clone());
+    assert_eq!(
+        peer.process_incoming_event(&fork),
+        Err(KernelError::BrokenEventChain { replica: TEST_REPLICA_ID_2, expected: second.id, found: Some(first.id) })
+    );
+    assert_eq!((peer.local_lc, peer.local_vc.clone(), peer.state.clone()), before, "Refused events are not merged");
+
+    // An omission: `third` reaches a peer that has only seen `first`.
+    let mut gapped = create_test_kernel(TEST_REPLICA_ID_3);
+    gapped.process_incoming_event(&first).unwrap();
+    assert_eq!(
+        gapped.process_incoming_event(&third),
+        Err(KernelError::BrokenEventChain { replica: TEST_REPLICA_ID_2, expected: first.id, found: Some(second.id) })
+    );
+    peer.process_incoming_event(&third).unwrap();
+
+    // The link is bound into the event id.
+    let input = |prev: Option<&CID>| {
+        author.get_event_hash_input_for_test(&generate_test_cid(1), 1, &TEST_REPLICA_ID_2, AlgSuite::CLASSIC as u8, &[], &[], &[], None, &VClock::default(), &[], 0, prev)
+    };
+    assert_ne!(input(None), input(Some(&first.id)));
+    assert_ne!(input(Some(&first.id)), input(Some(&second.id)));
+}
+
+// --- Equivocation tests ---
+
+#[test]
+fn test_equivocation_detected_with_portable_proof() {
+    let mut author = Kernel::new(TEST_REPLICA_ID_2, DefaultRuntime, DigestCryptoProvider);
+    let cap_id = generate_test_cid(100);
+    author.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    let mut fork = author.clone();
+    let first = author.apply(&create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_2, cap_id, 1, None)).unwrap();
+    let second = author.apply(&create_test_command(MockEncodedCmd::new("b", 0), 1, TEST_REPLICA_ID_2, cap_id, 2, None)).unwrap();
+    // The same replica state, diverging at lclock 1.
+    let rival = fork.apply(&create_test_command(MockEncodedCmd::new("z", 0), 0, TEST_REPLICA_ID_2, cap_id, 9, None)).unwrap();
+    assert_eq!((rival.lclock, rival.prev_event), (first.lclock, first.prev_event));
+
+    let mut peer = Kernel::new(TEST_REPLICA_ID_1, DefaultRuntime, DigestCryptoProvider);
+    peer.process_incoming_event(&first).unwrap();
+    let before = (peer.local_lc, peer.local_vc.clone(), peer.state.clone());
+    assert_eq!(
+        peer.process_incoming_event(&rival),
+        Err(KernelError::Equivocation { replica: TEST_REPLICA_ID_2, first: first.id, second: rival.id })
+    );
+    assert_eq!((peer.local_lc, peer.local_vc.clone(), peer.state.clone()), before, "Conflicting events are not merged");
+    let proof = peer.equivocations.proofs()[0].clone();
+    assert_eq!(proof, EquivocationProof { first: first.clone(), second: rival.clone() });
+    assert_eq!(proof.replica(), TEST_REPLICA_ID_2);
+    peer.process_incoming_event(&second).expect("Without quarantine the replica's chain continues");
+
+    // Any kernel can check the proof from the events alone.
+    let auditor = Kernel::new(TEST_REPLICA_ID_3, DefaultRuntime, DigestCryptoProvider);
+    assert_eq!(auditor.verify_equivocation(&proof), Ok(()));
+    let mut tampered = proof.clone();
+    tampered.second.new_entities.push(generate_test_cid(5));
+    assert!(matches!(auditor.verify_equivocation(&tampered), Err(KernelError::InvalidEquivocationProof(_))));
+    let sequential = EquivocationProof { first: first.clone(), second: second.clone() };
+    assert!(matches!(auditor.verify_equivocation(&sequential), Err(KernelError::InvalidEquivocationProof(_))));
+    let same = EquivocationProof { first: first.clone(), second: first.clone() };
+    assert!(matches!(auditor.verify_equivocation(&same), Err(KernelError::InvalidEquivocationProof(_))));
+
+    // Reusing the replica's own vector-clock entry at another lclock also conflicts.
+    let mut skewed = Event { lclock: first.lclock + 5, ..rival.clone() };
+    skewed.id = auditor.event_id(&skewed).unwrap();
+    assert_eq!(auditor.verify_equivocation(&EquivocationProof { first: first.clone(), second: skewed }), Ok(()));
+
+    // Under quarantine an authenticated conflict refuses the replica's later events too.
+    let mut strict = Kernel::new(TEST_REPLICA_ID_3, DefaultRuntime, DigestCryptoProvider)
+        .with_equivocation_log(EquivocationLog::new(EquivocationPolicy::Quarantine));
+    strict.process_authenticated_event(&first).unwrap();
+    // A conflict whose id was forged proves nothing: no proof, no quarantine.
+    let forged = Event { id: generate_test_cid(66), ..rival.clone() };
+    assert!(matches!(strict.process_authenticated_event(&forged), Err(KernelError::EventIdMismatch { .. })));
+    assert!(strict.equivocations.proofs().is_empty());
+    assert!(!strict.equivocations.is_quarantined(&TEST_REPLICA_ID_2));
+    assert!(matches!(strict.process_authenticated_event(&rival), Err(KernelError::Equivocation { .. })));
+    assert!(strict.equivocations.is_quarantined(&TEST_REPLICA_ID_2));
+    assert_eq!(strict.process_incoming_event(&second), Err(KernelError::ReplicaQuarantined(TEST_REPLICA_ID_2)));
+    assert!(strict.equivocations.release(&TEST_REPLICA_ID_2));
+    strict.process_incoming_event(&second).unwrap();
+
+    // Events are unsigned, so anyone can build an id-valid event in an honest
+    // replica's name. Delivered without authentication, such a conflict is
+    // recorded but does not quarantine the victim.
+    let mut framed = Kernel::new(TEST_REPLICA_ID_3, DefaultRuntime, DigestCryptoProvider)
+        .with_equivocation_log(EquivocationLog::new(EquivocationPolicy::Quarantine));
+    framed.process_authenticated_event(&first).unwrap();
+    let mut impostor = Event { caused_by: generate_test_cid(99), ..first.clone() };
+    impostor.id = framed.event_id(&impostor).unwrap();
+    assert!(matches!(framed.process_incoming_event(&impostor), Err(KernelError::Equivocation { .. })));
+    assert_eq!(framed.equivocations.proofs().len(), 1);
+    assert!(!framed.equivocations.is_quarantined(&TEST_REPLICA_ID_2));
+    framed.process_authenticated_event(&second).expect("The honest replica's chain continues");
+
+    // Conflicts older than the window are not detected.
+    let mut forgetful = Kernel::new(TEST_REPLICA_ID_3, DefaultRuntime, DigestCryptoProvider)
+        .with_equivocation_log(EquivocationLog::default().with_window(0));
+    forgetful.process_incoming_event(&first).unwrap();
+    assert!(matches!(forgetful.process_incoming_event(&rival), Err(KernelError::BrokenEventChain { .. })));
+    assert!(forgetful.equivocations.proofs().is_empty());
+}
+
+// --- Durable storage tests ---
+
+#[derive(Debug)]
+struct FailingStorage;
+
+impl Storage for FailingStorage {
+    fn append(&mut self, _record: &LogRecord) -> Result<(), StorageError> {
+        Err(StorageError::Io(std::io::Error::other("disk full")))
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn records(&mut self) -> Result<Vec<LogRecord>, StorageError> {
+        Ok(Vec::new())
+    }
+}
+
+#[test]
+fn test_kernel_recovers_from_write_ahead_log() {
+    let cap_id = generate_test_cid(100);
+    let voucher_id = generate_test_cid(101);
+    let genesis = |replica: ReplicaID| {
+        let mut kernel = Kernel::new(replica, MockRuntimeWithDelta::default(), DigestCryptoProvider);
+        let mut capability = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), rights::core::ALL, None, AlgSuite::CLASSIC);
+        capability.max_uses = Some(10);
+        kernel.state.capabilities.insert(cap_id, capability);
+        let mut voucher = create_test_capability(voucher_id, [2u8; 32], generate_test_cid(0), rights::core::ALL, None, AlgSuite::CLASSIC);
+        voucher.max_uses = Some(1);
+        kernel.state.capabilities.insert(voucher_id, voucher);
+        kernel.state.entities.insert(generate_test_cid(1), Arc::new(create_test_entity(1, 1, 0, None)));
+        kernel.rebuild_state_tree().unwrap();
+        kernel
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("events.wal");
+    let mut kernel = genesis(TEST_REPLICA_ID_1).with_storage(FileWal::open(&path, FsyncPolicy::Always).unwrap());
+    let mut peer = genesis(TEST_REPLICA_ID_2);
+
+    kernel.runtime = MockRuntimeWithDelta {
+        delta_to_produce: Some(StateDelta {
+            new_entities: vec![create_test_entity(2, 1, 1, None)],
+            updated_entities: vec![create_test_entity(1, 2, 1, None)],
+            ..Default::default()
+        }),
+    };
+    kernel.apply(&create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_1, cap_id, 50, None)).unwrap();
+    kernel.process_incoming_event(&peer.apply(&create_test_command(MockEncodedCmd::new("p", 0), 0, TEST_REPLICA_ID_2, cap_id, 60, None)).unwrap()).unwrap();
+
+    kernel.runtime = MockRuntimeWithDelta::default();
+    let batch = vec![
+        create_test_command(MockEncodedCmd::new("b", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 51, None),
+        create_test_command(MockEncodedCmd::new("c", 0), kernel.local_lc + 1, TEST_REPLICA_ID_1, voucher_id, 52, None),
+    ];
+    kernel.apply_batch(&batch).unwrap();
+    let failing = vec![
+        create_test_command(MockEncodedCmd::new("d", 0), kernel.local_lc, TEST_REPLICA_ID_1, cap_id, 53, None),
+        create_test_command(MockEncodedCmd::new("e", 0), kernel.local_lc + 1, TEST_REPLICA_ID_1, voucher_id, 54, None),
+    ];
+    assert!(kernel.apply_batch(&failing).is_err(), "The voucher is already used");
+
+    let records = FileWal::open(&path, FsyncPolicy::Always).unwrap().records().unwrap();
+    assert!(matches!(records.as_slice(), [LogRecord::Committed(a), LogRecord::Merged(_), LogRecord::Committed(b)] if a.len() == 1 && b.len() == 2));
+
+    // Restart: same genesis, state rebuilt from the log.
+    let recovered = genesis(TEST_REPLICA_ID_1).with_storage(FileWal::open(&path, FsyncPolicy::Always).unwrap()).recover().unwrap();
+    assert_eq!(recovered.state, kernel.state);
+    assert_eq!((recovered.local_lc, &recovered.local_vc), (kernel.local_lc, &kernel.local_vc));
+    assert_eq!((recovered.chain_head, &recovered.replica_heads), (kernel.chain_head, &kernel.replica_heads));
+    assert_eq!(recovered.state_root(), kernel.state_root());
+    assert_eq!(recovered.log_root(), kernel.log_root());
+
+    // The recovered kernel keeps logging where it left off.
+    let mut recovered = recovered;
+    recovered.apply(&create_test_command(MockEncodedCmd::new("f", 0), recovered.local_lc, TEST_REPLICA_ID_1, cap_id, 55, None)).unwrap();
+    assert_eq!(FileWal::open(&path, FsyncPolicy::Always).unwrap().recovery().records, 4);
+
+    // In-memory storage recovers the same way within a process.
+    let mut kernel = genesis(TEST_REPLICA_ID_1).with_storage(MemoryStorage::new());
+    kernel.apply(&create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_1, cap_id, 50, None)).unwrap();
+    let storage = kernel.storage.clone();
+    let mut restarted = genesis(TEST_REPLICA_ID_1);
+    restarted.storage = storage;
+    assert_eq!(restarted.recover().unwrap().state, kernel.state);
+}
+
+#[test]
+fn test_storage_failure_refuses_command() {
+    let cap_id = generate_test_cid(100);
+    let mut kernel = create_test_kernel(TEST_REPLICA_ID_1).with_storage(FailingStorage);
+    kernel.state.capabilities.insert(cap_id, create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC));
+    let before = kernel.clone();
+
+    let cmd = create_test_command(MockEncodedCmd::new("a", 0), 0, TEST_REPLICA_ID_1, cap_id, 1, None);
+    assert!(matches!(kernel.apply(&cmd), Err(KernelError::Storage(_))));
+    let batch = vec![cmd.clone(), create_test_command(MockEncodedCmd::new("b", 0), 1, TEST_REPLICA_ID_1, cap_id, 2, None)];
+    let err = kernel.apply_batch(&batch).expect_err("Batch is not logged");
+    assert!(matches!(err.error, KernelError::Storage(_)));
+    assert_eq!(kernel.state, before.state, "Unlogged commands leave Σ untouched");
+    assert_eq!((kernel.local_lc, kernel.chain_head), (before.local_lc, before.chain_head));
+    assert_eq!(kernel.log_root(), before.log_root());
+
+    // A merged event that cannot be logged is refused without being merged,
+    // so its redelivery is stored once storage recovers.
+    let mut voucher = create_test_capability(cap_id, [1u8; 32], generate_test_cid(0), 0, None, AlgSuite::CLASSIC);
+    voucher.max_uses = Some(2);
+    let mut author = create_test_kernel(TEST_REPLICA_ID_2);
+    author.state.capabilities.insert(cap_id, voucher.clone());
+    kernel.state.capabilities.insert(cap_id, voucher);
+    let remote = author.apply(&create_test_command(MockEncodedCmd::new("r", 0), 3, TEST_REPLICA_ID_2, cap_id, 3, None)).unwrap();
+    let before = kernel.clone();
+    assert!(matches!(kernel.process_incoming_event(&remote), Err(KernelError::Storage(_))));
+    assert_eq!((kernel.local_lc, kernel.local_vc.clone(), kernel.state.clone()), (before.local_lc, before.local_vc.clone(), before.state.clone()));
+    assert_eq!((kernel.replica_heads.clone(), kernel.state_root()), (before.replica_heads.clone(), before.state_root()));
+
+    kernel.storage = Some(Arc::new(std::sync::Mutex::new(MemoryStorage::new())));
+    kernel.process_incoming_event(&remote).unwrap();
+    assert_eq!(storage::lock(kernel.storage.as_ref().unwrap()).unwrap().records().unwrap(), vec![LogRecord::Merged(Box::new(remote))]);
+    assert_eq!(kernel.state.capability_uses[&cap_id], 1);
+}
@@ -34,6 +34,9 @@ pub mod error;
 // Module for Kernel logic.
 pub mod kernel;
 
+// Module for tracing helpers.
+pub mod telemetry;
+
 // Removed old module declarations as their contents are merged into primitives.rs:
 // pub mod events;
 // pub mod access;
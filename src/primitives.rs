@@ -86,9 +86,103 @@ pub struct Capability {
     pub nonce: u64,             // Nonce to prevent replay attacks
     pub expiry_lc: Option<u64>, // Optional Lamport clock expiry
     pub kind: u16,              // Reserved for overlay semantics (SpecPlan §1, §3)
+    #[serde(default)]
+    pub caveats: Vec<Caveat>,   // Attenuation conditions, all of which must hold
+    #[serde(default)]
+    pub max_uses: Option<u64>,  // Optional limit on the number of commands this capability may authorize
     pub signature: Signature,   // Signature by capability.holder
 }
 
+impl Capability {
+    /// Deterministic byte encoding of every field except `id` and `signature`,
+    /// used to derive the capability's content id. Caveats are encoded in
+    /// canonical (sorted, de-duplicated) order, so equivalent lists hash alike.
+    pub fn id_hash_input(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.push(self.alg_suite);
+        bytes.extend_from_slice(&self.holder.0);
+        bytes.extend_from_slice(&self.target_entity.0);
+        bytes.extend_from_slice(&self.rights.to_le_bytes());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        match self.expiry_lc {
+            Some(lc) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&lc.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&self.kind.to_le_bytes());
+        bytes.extend_from_slice(&Caveat::encode_list(&self.caveats));
+        match self.max_uses {
+            Some(uses) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&uses.to_le_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+}
+
+/// A macaroon-style condition attached to a capability. A command is only
+/// admitted if every caveat of its capability holds.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+pub enum Caveat {
+    /// Valid only while the replica's Lamport clock is below this value.
+    BeforeLc(u64),
+    /// The target entity must be, or descend from, this parent entity.
+    UnderParent(CID),
+    /// The first byte of the encoded payload (its opcode) must equal this value.
+    Opcode(u8),
+    /// The command must originate from this replica.
+    FromReplica(ReplicaID),
+}
+
+impl Caveat {
+    /// Stable byte encoding: a one-byte tag followed by a fixed-size body.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Caveat::BeforeLc(lc) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&lc.to_le_bytes());
+            }
+            Caveat::UnderParent(parent) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&parent.0);
+            }
+            Caveat::Opcode(opcode) => {
+                bytes.push(3);
+                bytes.push(*opcode);
+            }
+            Caveat::FromReplica(replica) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&replica.0);
+            }
+        }
+        bytes
+    }
+
+    /// Returns `caveats` sorted by encoding and without duplicates.
+    pub fn canonical(caveats: &[Caveat]) -> Vec<Caveat> {
+        let mut sorted = caveats.to_vec();
+        sorted.sort_by_key(Caveat::encode);
+        sorted.dedup();
+        sorted
+    }
+
+    /// Encodes a caveat list in canonical order, prefixed with its length.
+    pub fn encode_list(caveats: &[Caveat]) -> Vec<u8> {
+        let canonical = Self::canonical(caveats);
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(canonical.len() as u32).to_le_bytes());
+        for caveat in &canonical {
+            bytes.extend_from_slice(&caveat.encode());
+        }
+        bytes
+    }
+}
+
 // --- Command / Operation ----------------------------------------------------
 // kernel_spec.md §2.3 & SpecPlan §1
 
@@ -118,9 +212,17 @@ pub struct Event {
     pub replica: ReplicaID,     // ID of the replica that generated/validated this event
     pub caused_by: CID,         // Command.id that led to this event
     pub lclock: u64,            // Lamport timestamp assigned by the kernel
+    #[serde(default)]
+    pub epoch: u32,             // Clock epoch; events order by (epoch, lclock) (kernel_spec.md §7.1.5)
     pub vclock: VClock,         // Vector clock, now always present (SpecPlan §0, §1, §3)
     pub new_entities: Vec<CID>, // CIDs of entities created by this event
     pub updated_entities: Vec<CID>, // CIDs of entities updated by this event
+    #[serde(default)]
+    pub deleted_entities: Vec<CID>, // CIDs of entities tombstoned by this event
+    #[serde(default)]
+    pub consumed_capability: Option<CID>, // Use-limited capability whose counter this event increments
+    #[serde(default)]
+    pub prev_event: Option<CID>, // Previous event by the same replica; None for its first (hash chain)
     pub reserved: Vec<u8>,      // For unknown future fields, must be preserved bit-exact (kernel_spec.md §2.4, SpecPlan §1)
 }
 
@@ -5,7 +5,21 @@
 
 use crate::types::RightsMask; // RightsMask is u32
 
-/// Core rights bit flags (bits 0-4 defined, 5-15 reserved).
+/// Version of the rights algebra implemented by this crate.
+///
+/// * Version 1 allocates bits 0-4 (`READ`, `WRITE`, `DELEGATE`, `ISSUE`, `REVOKE`).
+/// * Version 2 additionally allocates bits 5-6 (`AUDIT`, `SEAL`).
+///
+/// A replica running an older version refuses any mask carrying kernel bits it
+/// has not allocated, rather than silently ignoring them.
+pub const ALGEBRA_VERSION: u16 = 2;
+
+/// Bits 5-15, reserved for kernel-level rights.
+pub const KERNEL_RESERVED_MASK: RightsMask = 0x0000_FFE0;
+/// Bits 16-31, available for domain-specific overlays and ignored by core kernel checks.
+pub const EXTENSION_MASK: RightsMask = 0xFFFF_0000;
+
+/// Core rights bit flags (bits 0-6 defined, 7-15 reserved).
 /// These are fundamental permissions recognized by the kernel.
 pub mod core {
     use super::RightsMask;
@@ -20,15 +34,52 @@ pub mod core {
     pub const ISSUE: RightsMask = 1 << 3; // 0b01000
     /// Permission to revoke an issued or delegated capability.
     pub const REVOKE: RightsMask = 1 << 4; // 0b10000
+    /// Permission to observe the audit trail of an entity (history, receipts, proofs). Implies `READ`.
+    /// Allocated in algebra version 2.
+    pub const AUDIT: RightsMask = 1 << 5;
+    /// Permission to seal an entity, finalising it against further mutation. Implies `READ`.
+    /// Allocated in algebra version 2.
+    pub const SEAL: RightsMask = 1 << 6;
 
-    // Bits 5-15 are reserved for future core rights and must be zero for now.
+    /// Every core right allocated by the current algebra version.
+    pub const ALL: RightsMask = READ | WRITE | DELEGATE | ISSUE | REVOKE | AUDIT | SEAL;
+
+    // Bits 7-15 are reserved for future core rights and must be zero for now.
     // Bits 16-31 are for application/user-defined extensions; ignored by core kernel checks but preserved.
 }
 
+/// Returns the kernel bits (0-15) allocated by the given algebra `version`,
+/// or `None` if this crate does not know that version.
+pub fn allocated_core_bits(version: u16) -> Option<RightsMask> {
+    match version {
+        1 => Some(core::READ | core::WRITE | core::DELEGATE | core::ISSUE | core::REVOKE),
+        2 => Some(core::ALL),
+        _ => None,
+    }
+}
+
+/// Returns the kernel bits set in `mask` that are not allocated by algebra `version`.
+///
+/// A non-zero result means the mask carries rights this replica does not understand.
+/// For a `version` unknown to this crate every kernel bit is treated as unallocated.
+#[inline]
+pub fn unallocated_bits(mask: RightsMask, version: u16) -> RightsMask {
+    let allocated = allocated_core_bits(version).unwrap_or(0);
+    mask & !EXTENSION_MASK & !allocated
+}
+
+/// Checks that `mask` only uses kernel bits allocated by algebra `version`.
+/// Extension bits (16-31) are always accepted.
+#[inline]
+pub fn is_understood(mask: RightsMask, version: u16) -> bool {
+    unallocated_bits(mask, version) == 0
+}
+
 /// Canonicalizes a rights mask by adding any implied rights.
 ///
 /// For example, `WRITE` permission implies `READ` permission. This function ensures that
 /// if the `WRITE` bit is set, the `READ` bit is also set in the returned mask.
+/// `AUDIT` and `SEAL` likewise imply `READ`.
 ///
 /// # Arguments
 /// * `mask` - The `RightsMask` to canonicalize.
@@ -41,6 +92,12 @@ pub fn canonicalise(mask: RightsMask) -> RightsMask {
     if (m & core::WRITE) == core::WRITE { // Check if WRITE bit is set
         m |= core::READ; // If WRITE is set, ensure READ is also set
     }
+    if (m & core::AUDIT) == core::AUDIT {
+        m |= core::READ;
+    }
+    if (m & core::SEAL) == core::SEAL {
+        m |= core::READ;
+    }
     // Add other implication rules here if they are defined in the future.
     // e.g., if core::SUPER_WRITE implied core::WRITE, you'd add:
     // if (m & core::SUPER_WRITE) == core::SUPER_WRITE {
@@ -55,6 +112,9 @@ pub fn canonicalise(mask: RightsMask) -> RightsMask {
 /// It then checks if all bits set in the `need` mask are also set in the canonicalized `have` mask.
 /// This corresponds to the rule: `(canonicalise(have) & need) == need`.
 ///
+/// Masks carrying unallocated reserved bits (see `is_understood`) are never sufficient,
+/// whichever side they appear on.
+///
 /// # Arguments
 /// * `have` - The `RightsMask` representing the permissions currently possessed.
 /// * `need` - The `RightsMask` representing the permissions required for an operation.
@@ -63,12 +123,326 @@ pub fn canonicalise(mask: RightsMask) -> RightsMask {
 /// `true` if the `have` mask satisfies the `need` mask, `false` otherwise.
 #[inline]
 pub fn sufficient(have: RightsMask, need: RightsMask) -> bool {
+    if !is_understood(have, ALGEBRA_VERSION) || !is_understood(need, ALGEBRA_VERSION) {
+        return false;
+    }
     // First, ensure the 'have' mask includes all implied rights.
     let canonical_have = canonicalise(have);
     // Then, check if all bits required by 'need' are present in 'canonical_have'.
     (canonical_have & need) == need
 }
 
+// --- Configurable rights algebra ----------------------------------------------
+// Domain overlays (finance, logistics, ...) name bits 16-31 and declare their own
+// implication edges. The kernel consults a `RightsAlgebra` rather than the free
+// functions above so that overlay implications take part in `rights_sufficient`.
+
+/// Errors raised while configuring a rights algebra or parsing a rights mask.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+pub enum RightsError {
+    /// The requested algebra version is not known to this crate.
+    #[error("Unknown rights algebra version: {0}")]
+    UnknownVersion(u16),
+    /// An overlay namespace or right name is empty or contains characters other than `[A-Za-z0-9_]`.
+    #[error("Invalid rights name: {0:?}")]
+    InvalidName(String),
+    /// An overlay with the same namespace is already registered.
+    #[error("Rights overlay namespace already registered: {0}")]
+    DuplicateNamespace(String),
+    /// The same right name appears twice within an overlay.
+    #[error("Right {0} declared twice")]
+    DuplicateRight(String),
+    /// An overlay right is not a single bit within bits 16-31.
+    #[error("Right {0} must be a single bit within bits 16-31, got {1:#010x}")]
+    BitOutOfRange(String, RightsMask),
+    /// An overlay right claims a bit already named by another right.
+    #[error("Right {0} claims bit {1:#010x} which is already allocated")]
+    BitAlreadyAllocated(String, RightsMask),
+    /// A name in an implication edge or a parsed mask does not resolve to a right.
+    #[error("Unknown right: {0}")]
+    UnknownRight(String),
+    /// The implication edges of an overlay form a cycle through the named right.
+    #[error("Implication cycle through right {0}")]
+    ImplicationCycle(String),
+}
+
+/// A rights algebra: the kernel bits it allocates plus every implication between rights.
+///
+/// Implementations MUST be deterministic; every replica configured with the same
+/// algebra must reach the same verdict for the same masks.
+pub trait RightsAlgebra: Send + Sync + std::fmt::Debug + 'static {
+    /// Version of the kernel bit allocation enforced by this algebra.
+    fn version(&self) -> u16;
+
+    /// Adds every right implied by `mask`, transitively.
+    fn canonicalise(&self, mask: RightsMask) -> RightsMask;
+
+    /// Returns the kernel bits set in `mask` that this algebra does not allocate.
+    fn unallocated_bits(&self, mask: RightsMask) -> RightsMask {
+        unallocated_bits(mask, self.version())
+    }
+
+    /// Checks `have` against `need` after canonicalisation. Masks carrying
+    /// unallocated kernel bits are never sufficient.
+    fn sufficient(&self, have: RightsMask, need: RightsMask) -> bool {
+        if self.unallocated_bits(have) != 0 || self.unallocated_bits(need) != 0 {
+            return false;
+        }
+        (self.canonicalise(have) & need) == need
+    }
+
+    /// Parses a mask such as `READ|WRITE|finance:SETTLE`. Bits without a name
+    /// may be given as hexadecimal literals (`0x00100000`).
+    fn parse(&self, text: &str) -> Result<RightsMask, RightsError>;
+
+    /// Renders `mask` in the format accepted by `parse`.
+    fn format(&self, mask: RightsMask) -> String;
+}
+
+/// Core right names in bit order, paired with their masks.
+const CORE_NAMES: [(&str, RightsMask); 7] = [
+    ("READ", core::READ),
+    ("WRITE", core::WRITE),
+    ("DELEGATE", core::DELEGATE),
+    ("ISSUE", core::ISSUE),
+    ("REVOKE", core::REVOKE),
+    ("AUDIT", core::AUDIT),
+    ("SEAL", core::SEAL),
+];
+
+fn validate_name(name: &str) -> Result<(), RightsError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(RightsError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// A domain overlay: a namespace of named rights in bits 16-31 and the
+/// implications between them.
+///
+/// Implication targets may be rights of the same overlay or core rights
+/// (e.g. `SETTLE ⇒ WRITE`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RightsOverlay {
+    namespace: String,
+    rights: Vec<(String, RightsMask)>,
+    implications: Vec<(String, String)>,
+}
+
+impl RightsOverlay {
+    /// Starts an overlay named `namespace` (e.g. `"finance"`).
+    pub fn new(namespace: &str) -> Self {
+        RightsOverlay { namespace: namespace.to_string(), rights: Vec::new(), implications: Vec::new() }
+    }
+
+    /// Names `bit` as the right `name` within this overlay.
+    pub fn right(mut self, name: &str, bit: RightsMask) -> Self {
+        self.rights.push((name.to_string(), bit));
+        self
+    }
+
+    /// Declares that holding `from` implies holding `to`.
+    pub fn implies(mut self, from: &str, to: &str) -> Self {
+        self.implications.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// The overlay's namespace.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Resolves a right of this overlay, or a core right, by name.
+    fn resolve(&self, name: &str, version: u16) -> Option<RightsMask> {
+        self.rights
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, bit)| *bit)
+            .or_else(|| core_bit(name, version))
+    }
+}
+
+/// Resolves a core right by name, provided `version` allocates it.
+fn core_bit(name: &str, version: u16) -> Option<RightsMask> {
+    let allocated = allocated_core_bits(version).unwrap_or(0);
+    CORE_NAMES
+        .iter()
+        .find(|(n, bit)| *n == name && allocated & bit != 0)
+        .map(|(_, bit)| *bit)
+}
+
+/// The standard `RightsAlgebra`: the core algebra of a given version plus any
+/// number of registered domain overlays.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RightsRegistry {
+    version: u16,
+    overlays: Vec<RightsOverlay>,
+    /// `closure[i]` is every right transitively implied by bit `i`, including itself.
+    closure: [RightsMask; 32],
+}
+
+impl Default for RightsRegistry {
+    fn default() -> Self {
+        Self::with_version(ALGEBRA_VERSION).expect("current algebra version is always known")
+    }
+}
+
+impl RightsRegistry {
+    /// Creates a registry without overlays for the current algebra version.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry without overlays enforcing algebra `version`.
+    pub fn with_version(version: u16) -> Result<Self, RightsError> {
+        if allocated_core_bits(version).is_none() {
+            return Err(RightsError::UnknownVersion(version));
+        }
+        let mut closure = [0; 32];
+        for (i, implied) in closure.iter_mut().enumerate() {
+            *implied = canonicalise(1 << i);
+        }
+        Ok(RightsRegistry { version, overlays: Vec::new(), closure })
+    }
+
+    /// Registers `overlay` after checking that its names are valid and unique, its
+    /// bits lie within 16-31 and are not already allocated, and its implications
+    /// resolve and are acyclic. The registry is left unchanged on error.
+    pub fn register(&mut self, overlay: RightsOverlay) -> Result<(), RightsError> {
+        validate_name(&overlay.namespace)?;
+        if self.overlay(&overlay.namespace).is_some() {
+            return Err(RightsError::DuplicateNamespace(overlay.namespace));
+        }
+
+        let mut allocated: RightsMask = self.overlays.iter().flat_map(|o| o.rights.iter()).fold(0, |acc, (_, bit)| acc | bit);
+        for (i, (name, bit)) in overlay.rights.iter().enumerate() {
+            validate_name(name)?;
+            if overlay.rights[..i].iter().any(|(n, _)| n == name) {
+                return Err(RightsError::DuplicateRight(name.clone()));
+            }
+            if bit.count_ones() != 1 || bit & EXTENSION_MASK == 0 {
+                return Err(RightsError::BitOutOfRange(name.clone(), *bit));
+            }
+            if allocated & bit != 0 {
+                return Err(RightsError::BitAlreadyAllocated(name.clone(), *bit));
+            }
+            allocated |= bit;
+        }
+
+        // Resolve edges to bit indices, then extend the closure in topological order.
+        let mut edges: Vec<(usize, RightsMask)> = Vec::with_capacity(overlay.implications.len());
+        for (from, to) in &overlay.implications {
+            let from_bit = overlay
+                .rights
+                .iter()
+                .find(|(n, _)| n == from)
+                .map(|(_, bit)| *bit)
+                .ok_or_else(|| RightsError::UnknownRight(format!("{}:{}", overlay.namespace, from)))?;
+            let to_bit = overlay
+                .resolve(to, self.version)
+                .ok_or_else(|| RightsError::UnknownRight(format!("{}:{}", overlay.namespace, to)))?;
+            edges.push((from_bit.trailing_zeros() as usize, to_bit));
+        }
+
+        let mut closure = self.closure;
+        // Marks: 0 = unvisited, 1 = on stack, 2 = done.
+        let mut marks = [0u8; 32];
+        for (name, bit) in &overlay.rights {
+            Self::visit(bit.trailing_zeros() as usize, &edges, &mut closure, &mut marks)
+                .map_err(|_| RightsError::ImplicationCycle(format!("{}:{}", overlay.namespace, name)))?;
+        }
+
+        self.closure = closure;
+        self.overlays.push(overlay);
+        Ok(())
+    }
+
+    /// Depth-first closure computation; `Err(())` signals a back edge (cycle).
+    fn visit(bit: usize, edges: &[(usize, RightsMask)], closure: &mut [RightsMask; 32], marks: &mut [u8; 32]) -> Result<(), ()> {
+        match marks[bit] {
+            1 => return Err(()),
+            2 => return Ok(()),
+            _ => {}
+        }
+        marks[bit] = 1;
+        let mut implied = closure[bit];
+        for (_, to) in edges.iter().filter(|(from, _)| *from == bit) {
+            let to_index = to.trailing_zeros() as usize;
+            // Core targets already carry their full closure; only overlay bits need visiting.
+            if (to & EXTENSION_MASK) != 0 {
+                Self::visit(to_index, edges, closure, marks)?;
+            }
+            implied |= closure[to_index];
+        }
+        closure[bit] = implied;
+        marks[bit] = 2;
+        Ok(())
+    }
+
+    /// Returns the overlay registered under `namespace`, if any.
+    pub fn overlay(&self, namespace: &str) -> Option<&RightsOverlay> {
+        self.overlays.iter().find(|o| o.namespace == namespace)
+    }
+}
+
+impl RightsAlgebra for RightsRegistry {
+    fn version(&self) -> u16 {
+        self.version
+    }
+
+    fn canonicalise(&self, mask: RightsMask) -> RightsMask {
+        (0..32)
+            .filter(|i| mask & (1 << i) != 0)
+            .fold(mask, |acc, i| acc | self.closure[i])
+    }
+
+    fn parse(&self, text: &str) -> Result<RightsMask, RightsError> {
+        let mut mask = 0;
+        for token in text.split('|').map(str::trim) {
+            if let Some(hex) = token.strip_prefix("0x") {
+                mask |= RightsMask::from_str_radix(hex, 16)
+                    .map_err(|_| RightsError::UnknownRight(token.to_string()))?;
+                continue;
+            }
+            let bit = match token.split_once(':') {
+                Some((namespace, name)) => self
+                    .overlay(namespace)
+                    .and_then(|o| o.rights.iter().find(|(n, _)| n == name))
+                    .map(|(_, bit)| *bit),
+                None => core_bit(token, self.version),
+            };
+            mask |= bit.ok_or_else(|| RightsError::UnknownRight(token.to_string()))?;
+        }
+        Ok(mask)
+    }
+
+    fn format(&self, mask: RightsMask) -> String {
+        let mut parts = Vec::new();
+        let mut remaining = mask;
+        for (name, bit) in CORE_NAMES.iter().filter(|(name, _)| core_bit(name, self.version).is_some()) {
+            if mask & bit != 0 {
+                parts.push(name.to_string());
+                remaining &= !bit;
+            }
+        }
+        let mut named: Vec<(RightsMask, String)> = self
+            .overlays
+            .iter()
+            .flat_map(|o| o.rights.iter().map(move |(name, bit)| (*bit, format!("{}:{}", o.namespace, name))))
+            .filter(|(bit, _)| mask & bit != 0)
+            .collect();
+        named.sort();
+        for (bit, name) in named {
+            parts.push(name);
+            remaining &= !bit;
+        }
+        if remaining != 0 || parts.is_empty() {
+            parts.push(format!("{:#010x}", remaining));
+        }
+        parts.join("|")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,4 +497,130 @@ mod tests {
         assert!(sufficient(have_with_extension, core::READ | extension_bit_16));
         assert!(!sufficient(have_core, core::READ | extension_bit_16));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_audit_and_seal_imply_read() {
+        assert_eq!(canonicalise(core::AUDIT), core::AUDIT | core::READ);
+        assert_eq!(canonicalise(core::SEAL), core::SEAL | core::READ);
+        assert!(sufficient(core::AUDIT, core::READ));
+        assert!(!sufficient(core::AUDIT, core::WRITE));
+        assert!(!sufficient(core::SEAL, core::WRITE));
+    }
+
+    #[test]
+    fn test_reserved_bits_rejected() {
+        let reserved_bit_7 = 1 << 7;
+        assert_eq!(unallocated_bits(core::ALL | EXTENSION_MASK, ALGEBRA_VERSION), 0);
+        assert_eq!(unallocated_bits(core::READ | reserved_bit_7, ALGEBRA_VERSION), reserved_bit_7);
+        assert!(!sufficient(core::READ | reserved_bit_7, core::READ));
+        assert!(!sufficient(core::ALL, reserved_bit_7));
+    }
+
+    #[test]
+    fn test_older_algebra_refuses_newer_bits() {
+        assert!(is_understood(core::REVOKE, 1));
+        assert!(!is_understood(core::AUDIT, 1));
+        assert_eq!(unallocated_bits(core::SEAL | core::READ, 1), core::SEAL);
+        assert!(is_understood(core::AUDIT, 2));
+        // Unknown versions understand no kernel bits at all, only extensions.
+        assert!(!is_understood(core::READ, 99));
+        assert!(is_understood(1 << 16, 99));
+    }
+
+    fn finance_registry() -> RightsRegistry {
+        let mut registry = RightsRegistry::new();
+        registry
+            .register(
+                RightsOverlay::new("finance")
+                    .right("TRANSFER", 1 << 16)
+                    .right("SETTLE", 1 << 17)
+                    .right("CLOSE", 1 << 18)
+                    .implies("SETTLE", "TRANSFER")
+                    .implies("TRANSFER", "WRITE")
+                    .implies("CLOSE", "SETTLE"),
+            )
+            .expect("finance overlay is valid");
+        registry
+    }
+
+    #[test]
+    fn test_registry_overlay_implications() {
+        let registry = finance_registry();
+        let settle = 1 << 17;
+        let close = 1 << 18;
+        assert_eq!(registry.canonicalise(settle), settle | (1 << 16) | core::WRITE | core::READ);
+        assert_eq!(registry.canonicalise(close), close | settle | (1 << 16) | core::WRITE | core::READ);
+        assert!(registry.sufficient(close, 1 << 16));
+        assert!(!registry.sufficient(1 << 16, settle));
+        // Core implications still hold.
+        assert_eq!(registry.canonicalise(core::AUDIT), canonicalise(core::AUDIT));
+    }
+
+    #[test]
+    fn test_registry_rejects_invalid_overlays() {
+        let mut registry = finance_registry();
+        assert_eq!(
+            registry.register(RightsOverlay::new("finance").right("X", 1 << 20)),
+            Err(RightsError::DuplicateNamespace("finance".into()))
+        );
+        assert_eq!(
+            registry.register(RightsOverlay::new("logistics").right("SHIP", 1 << 10)),
+            Err(RightsError::BitOutOfRange("SHIP".into(), 1 << 10))
+        );
+        assert_eq!(
+            registry.register(RightsOverlay::new("logistics").right("SHIP", (1 << 20) | (1 << 21))),
+            Err(RightsError::BitOutOfRange("SHIP".into(), (1 << 20) | (1 << 21)))
+        );
+        assert_eq!(
+            registry.register(RightsOverlay::new("logistics").right("SHIP", 1 << 16)),
+            Err(RightsError::BitAlreadyAllocated("SHIP".into(), 1 << 16))
+        );
+        assert_eq!(
+            registry.register(RightsOverlay::new("logistics").right("SHIP", 1 << 20).implies("SHIP", "SETTLE")),
+            Err(RightsError::UnknownRight("logistics:SETTLE".into()))
+        );
+        assert_eq!(
+            registry.register(RightsOverlay::new("log-istics").right("SHIP", 1 << 20)),
+            Err(RightsError::InvalidName("log-istics".into()))
+        );
+        assert_eq!(
+            registry.register(
+                RightsOverlay::new("logistics")
+                    .right("SHIP", 1 << 20)
+                    .right("LOAD", 1 << 21)
+                    .implies("SHIP", "LOAD")
+                    .implies("LOAD", "SHIP")
+            ),
+            Err(RightsError::ImplicationCycle("logistics:SHIP".into()))
+        );
+        // Failed registrations leave the registry untouched.
+        assert!(registry.overlay("logistics").is_none());
+        assert_eq!(registry, finance_registry());
+    }
+
+    #[test]
+    fn test_registry_parse_and_format() {
+        let registry = finance_registry();
+        let mask = registry.parse("READ|WRITE|finance:SETTLE").unwrap();
+        assert_eq!(mask, core::READ | core::WRITE | (1 << 17));
+        assert_eq!(registry.format(mask), "READ|WRITE|finance:SETTLE");
+        assert_eq!(registry.format(core::REVOKE | (1 << 24)), "REVOKE|0x01000000");
+        assert_eq!(registry.parse(" REVOKE | 0x01000000 ").unwrap(), core::REVOKE | (1 << 24));
+        assert_eq!(registry.format(0), "0x00000000");
+        assert_eq!(registry.parse("finance:MINT"), Err(RightsError::UnknownRight("finance:MINT".into())));
+
+        // Names follow the configured version: AUDIT is unknown to a version 1 algebra.
+        let v1 = RightsRegistry::with_version(1).unwrap();
+        assert_eq!(v1.parse("AUDIT"), Err(RightsError::UnknownRight("AUDIT".into())));
+        assert_eq!(v1.format(core::AUDIT), "0x00000020");
+        assert_eq!(RightsRegistry::with_version(99), Err(RightsError::UnknownVersion(99)));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn property_format_parse_roundtrip(mask in proptest::prelude::any::<u32>()) {
+            let registry = finance_registry();
+            proptest::prop_assert_eq!(registry.parse(&registry.format(mask)).unwrap(), mask);
+        }
+    }
+}
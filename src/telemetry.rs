@@ -0,0 +1,26 @@
+//!
+//! Tracing support.
+//!
+//! The kernel emits `tracing` spans for its pipeline (`apply`,
+//! `validate_command`, `runtime_execute`, `append_delta`, `materialise_event`,
+//! `process_incoming_event`) carrying command ids, replicas, Lamport clocks and
+//! outcomes. Emitting spans is free when no subscriber is installed; services
+//! normally install their own. The `tracing-subscriber` feature provides a
+//! minimal formatter for binaries and tests that have none.
+
+/// Installs a global `fmt` subscriber logging spans and events up to `max_level`.
+///
+/// Fails if a global subscriber has already been installed.
+#[cfg(feature = "tracing-subscriber")]
+pub fn install_subscriber(
+    max_level: tracing::Level,
+) -> Result<(), tracing_subscriber::util::TryInitError> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    tracing_subscriber::fmt()
+        .with_max_level(max_level)
+        .with_span_events(FmtSpan::CLOSE)
+        .finish()
+        .try_init()
+}
@@ -46,7 +46,8 @@ impl TryFrom<u8> for AlgSuite {
 /// RightsMask, a 32-bit field, as defined in `kernel_spec.md` §6 and `SpecPlan` §2.
 /// The interpretation of its bits is:
 /// - Bits 0-4: Core kernel rights (READ, WRITE, DELEGATE, ISSUE, REVOKE) - Frozen.
-/// - Bits 5-15: Reserved for future kernel-level needs.
+/// - Bits 5-6: Kernel rights AUDIT and SEAL (rights algebra version 2).
+/// - Bits 7-15: Reserved for future kernel-level needs; must be zero.
 /// - Bits 16-31: Available for domain-specific overlays (e.g., finance, logistics).
 pub type RightsMask = u32;
 
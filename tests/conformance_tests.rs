@@ -2,8 +2,9 @@
 
 use amulet_core::kernel::{Kernel, Runtime};
 use amulet_core::error::KernelError;
-use amulet_core::primitives::{ReplicaIdBytes, ReplicaID, VClock, Command, CidBytes, SignatureBytes, Entity, Capability, PublicKeyBytes};
+use amulet_core::primitives::{ReplicaIdBytes, ReplicaID, VClock, Command, CidBytes, SignatureBytes, Capability, PublicKeyBytes};
 use amulet_core::types::AlgSuite;
+use amulet_core::rights;
 use amulet_core::command_traits::{EncodedCmd, CommandTraitError};
 use amulet_core::kernel::core::{StateDelta, SystemState};
 use amulet_core::crypto::PlaceholderCryptoProvider;
@@ -56,10 +57,12 @@ fn create_placeholder_capability() -> Capability {
         alg_suite: AlgSuite::CLASSIC as u8,
         holder: PublicKeyBytes([0u8; 32]), // Placeholder public key
         target_entity: CidBytes([0u8; 32]), // Placeholder target entity
-        rights: u32::MAX, // Grant all rights for simplicity in these tests
+        rights: rights::core::ALL | rights::EXTENSION_MASK, // Grant all allocated rights for simplicity in these tests
         nonce: 0,
         expiry_lc: None, // No expiry for simplicity
         kind: 0,
+        caveats: Vec::new(),
+        max_uses: None,
         signature: SignatureBytes([0u8; 64]), // Placeholder signature
     }
 }
@@ -77,13 +80,14 @@ impl<CP: amulet_core::crypto::CryptoProvider + Clone> Runtime<CP> for MockRuntim
         Ok(StateDelta {
             new_entities: Vec::new(),
             updated_entities: Vec::new(),
+            deleted_entities: Vec::new(),
         })
     }
 }
 
 // Helper function to create a Kernel instance with a specific replica ID
 fn create_kernel_for_test(replica_id: ReplicaID) -> Kernel<PlaceholderCryptoProvider, MockRuntime> {
-    let crypto_provider = PlaceholderCryptoProvider::default();
+    let crypto_provider = PlaceholderCryptoProvider;
     let runtime = MockRuntime;
     Kernel::new(replica_id, runtime, crypto_provider)
 }
@@ -100,7 +104,7 @@ fn create_test_command_for_conformance(
         alg_suite: AlgSuite::CLASSIC as u8,
         replica: replica_id,
         capability: CidBytes([1u8; 32]),
-        lclock: lclock,
+        lclock,
         vclock: vclock_opt,
         payload: MockCmdPayload(payload_bytes),
         signature: SignatureBytes([0u8; 64]),
@@ -111,15 +115,15 @@ fn create_test_command_for_conformance(
 fn test_vclock_increment_local_component() {
     let replica_id_bytes = [1u8; 16];
     let replica_id = ReplicaIdBytes(replica_id_bytes);
-    let mut kernel = create_kernel_for_test(replica_id.clone());
+    let mut kernel = create_kernel_for_test(replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
-    let initial_lc = kernel.local_vc.0.get(&replica_id).unwrap_or(&0).clone();
+    let initial_lc = *kernel.local_vc.0.get(&replica_id).unwrap_or(&0);
 
-    let command = create_test_command_for_conformance(vec![1, 2, 3], None, replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1, 2, 3], None, replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -137,16 +141,16 @@ fn test_vclock_increment_other_components_unchanged() {
     let other_replica_id = ReplicaIdBytes(other_replica_id_bytes);
     let other_replica_initial_lc = 5;
 
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
     // Initialize the kernel's VClock to include another replica's clock
-    kernel.local_vc.0.insert(other_replica_id.clone(), other_replica_initial_lc);
+    kernel.local_vc.0.insert(other_replica_id, other_replica_initial_lc);
 
-    let command = create_test_command_for_conformance(vec![4, 5, 6], None, local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![4, 5, 6], None, local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -161,6 +165,7 @@ fn test_vclock_increment_other_components_unchanged() {
 // More tests for VClock merge logic will be added here.
 
 // Helper function to create a VClock from a vector of (ReplicaID, u64) tuples
+#[allow(dead_code)]
 fn create_vclock_from_map(map: Vec<(ReplicaID, u64)>) -> VClock {
     VClock(map.into_iter().collect())
 }
@@ -169,21 +174,21 @@ fn create_vclock_from_map(map: Vec<(ReplicaID, u64)>) -> VClock {
 fn test_vclock_merge_command_vclock_none() {
     let replica_id_bytes = [1u8; 16];
     let replica_id = ReplicaIdBytes(replica_id_bytes);
-    let mut kernel = create_kernel_for_test(replica_id.clone());
+    let mut kernel = create_kernel_for_test(replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap.clone()); // clone since it's used twice for cmd1 & cmd2
 
     // Event 1: establish initial clock for replica_id
-    let cmd1 = create_test_command_for_conformance(vec![1], None, replica_id.clone(), 1);
+    let cmd1 = create_test_command_for_conformance(vec![1], None, replica_id, 1);
     let event1_result = kernel.apply(&cmd1);
     assert!(event1_result.is_ok(), "apply command failed: {:?}", event1_result.err());
     let event1 = event1_result.unwrap();
     assert_eq!(*event1.vclock.0.get(&replica_id).unwrap(), 1);
 
     // Event 2: command has no vclock, event vclock should be kernel's incremented vclock
-    let cmd2 = create_test_command_for_conformance(vec![2], None, replica_id.clone(), 2);
+    let cmd2 = create_test_command_for_conformance(vec![2], None, replica_id, 2);
     let event2_result = kernel.apply(&cmd2);
     assert!(event2_result.is_ok(), "apply command failed: {:?}", event2_result.err());
     let event2 = event2_result.unwrap();
@@ -195,7 +200,7 @@ fn test_vclock_merge_command_vclock_none() {
 fn test_vclock_merge_command_vclock_present_no_overlap() {
     let local_replica_id_bytes = [1u8; 16];
     let local_replica_id = ReplicaIdBytes(local_replica_id_bytes);
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
@@ -205,10 +210,10 @@ fn test_vclock_merge_command_vclock_present_no_overlap() {
     let cmd_replica_id = ReplicaIdBytes(cmd_replica_id_bytes);
 
     let mut cmd_vclock_map = HashMap::new();
-    cmd_vclock_map.insert(cmd_replica_id.clone(), 5);
+    cmd_vclock_map.insert(cmd_replica_id, 5);
     let cmd_vclock = VClock(cmd_vclock_map);
 
-    let command = create_test_command_for_conformance(vec![1,2,3], Some(cmd_vclock), local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1,2,3], Some(cmd_vclock), local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -227,19 +232,19 @@ fn test_vclock_merge_command_vclock_present_kernel_ahead() {
     let shared_replica_id_bytes = [3u8; 16];
     let shared_replica_id = ReplicaIdBytes(shared_replica_id_bytes);
 
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
-    kernel.local_vc.0.insert(shared_replica_id.clone(), 10);
+    kernel.local_vc.0.insert(shared_replica_id, 10);
 
     let mut cmd_vclock_map = HashMap::new();
-    cmd_vclock_map.insert(shared_replica_id.clone(), 5);
+    cmd_vclock_map.insert(shared_replica_id, 5);
     let cmd_vclock = VClock(cmd_vclock_map);
 
-    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -256,19 +261,19 @@ fn test_vclock_merge_command_vclock_present_command_ahead() {
     let shared_replica_id_bytes = [3u8; 16];
     let shared_replica_id = ReplicaIdBytes(shared_replica_id_bytes);
 
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
-    kernel.local_vc.0.insert(shared_replica_id.clone(), 5);
+    kernel.local_vc.0.insert(shared_replica_id, 5);
 
     let mut cmd_vclock_map = HashMap::new();
-    cmd_vclock_map.insert(shared_replica_id.clone(), 10);
+    cmd_vclock_map.insert(shared_replica_id, 10);
     let cmd_vclock = VClock(cmd_vclock_map);
 
-    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -287,21 +292,21 @@ fn test_vclock_merge_command_vclock_present_mixed_ahead() {
     let replica_b_bytes = [11u8; 16];
     let replica_b = ReplicaIdBytes(replica_b_bytes);
 
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
-    kernel.local_vc.0.insert(replica_a.clone(), 20);
-    kernel.local_vc.0.insert(replica_b.clone(), 20);
+    kernel.local_vc.0.insert(replica_a, 20);
+    kernel.local_vc.0.insert(replica_b, 20);
 
     let mut cmd_vclock_map = HashMap::new();
-    cmd_vclock_map.insert(replica_a.clone(), 15);
-    cmd_vclock_map.insert(replica_b.clone(), 25);
+    cmd_vclock_map.insert(replica_a, 15);
+    cmd_vclock_map.insert(replica_b, 25);
     let cmd_vclock = VClock(cmd_vclock_map);
 
-    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let event = event_result.unwrap();
@@ -319,18 +324,18 @@ fn test_vclock_merge_updates_kernel_local_vc() {
     let cmd_replica_id_bytes = [2u8; 16];
     let cmd_replica_id = ReplicaIdBytes(cmd_replica_id_bytes);
 
-    let mut kernel = create_kernel_for_test(local_replica_id.clone());
+    let mut kernel = create_kernel_for_test(local_replica_id);
 
     // Insert placeholder capability
     let placeholder_cap = create_placeholder_capability();
     kernel.state.capabilities.insert(placeholder_cap.id, placeholder_cap);
 
     let mut cmd_vclock_map = HashMap::new();
-    cmd_vclock_map.insert(cmd_replica_id.clone(), 5);
-    cmd_vclock_map.insert(local_replica_id.clone(), 0);
+    cmd_vclock_map.insert(cmd_replica_id, 5);
+    cmd_vclock_map.insert(local_replica_id, 0);
     let cmd_vclock = VClock(cmd_vclock_map);
 
-    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id.clone(), 1);
+    let command = create_test_command_for_conformance(vec![1], Some(cmd_vclock), local_replica_id, 1);
     let event_result = kernel.apply(&command);
     assert!(event_result.is_ok(), "apply command failed: {:?}", event_result.err());
     let _event = event_result.unwrap();
@@ -7,6 +7,7 @@ use amulet_core::primitives::{
     CID, ReplicaID,
 };
 use amulet_core::types::AlgSuite;
+use amulet_core::rights;
 use amulet_core::command_traits::{EncodedCmd, CommandTraitError};
 use amulet_core::crypto::ConfigurableCryptoProvider;
 use amulet_core::crypto::CryptoError;
@@ -66,11 +67,11 @@ impl EncodedCmd for MockValidationCmd {
 fn arb_capability() -> impl Strategy<Value = Capability> {
     (
         any::<[u8; 32]>()      // id_byte for CID
-            .prop_map(|b| CidBytes(b)),
+            .prop_map(CidBytes),
         Just(AlgSuite::CLASSIC as u8), // alg_suite_tag (fixed for simplicity)
         Just(PublicKeyBytes(TEST_HOLDER_PK_BYTES)), // holder (fixed)
         any::<[u8; 32]>()      // target_entity_byte for CID
-            .prop_map(|b| CidBytes(b)),
+            .prop_map(CidBytes),
         any::<u32>(),          // rights
         any::<u64>(),          // nonce
         prop_oneof![          // expiry_lc
@@ -79,12 +80,12 @@ fn arb_capability() -> impl Strategy<Value = Capability> {
         ],
         Just(0u16),            // kind (fixed)
         any::<[u8; 64]>()      // signature_bytes
-            .prop_map(|b| SignatureBytes(b)),
+            .prop_map(SignatureBytes),
     )
         .prop_map(
             |(id, alg_suite, holder, target_entity, rights, nonce, expiry_lc, kind, signature)| {
                 Capability {
-                    id, alg_suite, holder, target_entity, rights, nonce, expiry_lc, kind, signature,
+                    id, alg_suite, holder, target_entity, rights, nonce, expiry_lc, kind, caveats: Vec::new(), max_uses: None, signature,
                 }
             },
         )
@@ -94,7 +95,7 @@ fn arb_capability() -> impl Strategy<Value = Capability> {
 fn arb_command(cap_cid: CID) -> impl Strategy<Value = Command<MockValidationCmd>> {
     (
         any::<[u8; 32]>()      // id_byte for CID
-            .prop_map(|b| CidBytes(b)),
+            .prop_map(CidBytes),
         Just(AlgSuite::CLASSIC as u8), // alg_suite_tag (must match capability's for basic validation)
         Just(TEST_REPLICA_ID_CMD),     // replica (fixed)
         Just(cap_cid),         // capability CID (linked to the generated capability)
@@ -103,7 +104,7 @@ fn arb_command(cap_cid: CID) -> impl Strategy<Value = Command<MockValidationCmd>
         prop::collection::vec(any::<u8>(), 0..32), // payload_data for MockValidationCmd
         any::<u32>(),          // required_rights_value for MockValidationCmd
         any::<[u8; 64]>()      // signature_bytes
-            .prop_map(|b| SignatureBytes(b)),
+            .prop_map(SignatureBytes),
     )
         .prop_map(
             |(id, alg_suite, replica, capability, lclock, vclock, payload_data, required_rights_value, signature)| {
@@ -128,10 +129,12 @@ proptest! {
         cmd_lclock_offset in 0..100u64 // Kernel's current_lc will be 0, command lclock will be >= 0
     ) {
         let capability = cap_strategy_input;
-        let mut crypto_provider = ConfigurableCryptoProvider::default();
-        crypto_provider.verification_outcome = Err(CryptoError::InvalidSignature);
+        let crypto_provider = ConfigurableCryptoProvider {
+            verification_outcome: Err(CryptoError::InvalidSignature),
+            ..Default::default()
+        };
 
-        let mut kernel = Kernel::new(TEST_REPLICA_ID_CMD, DefaultRuntime::default(), crypto_provider);
+        let mut kernel = Kernel::new(TEST_REPLICA_ID_CMD, DefaultRuntime, crypto_provider);
         kernel.state.capabilities.insert(capability.id, capability.clone());
 
         // Generate a command linked to this capability using arb_command
@@ -169,13 +172,13 @@ proptest! {
         let capability = cap_strategy_input;
         let crypto_provider = ConfigurableCryptoProvider::default(); // Defaults to Ok(()) for verify
 
-        let mut kernel = Kernel::new(TEST_REPLICA_ID_CMD, DefaultRuntime::default(), crypto_provider);
+        let mut kernel = Kernel::new(TEST_REPLICA_ID_CMD, DefaultRuntime, crypto_provider);
         
         // Ensure capability is not expired for this test to focus on crypto success
         // and other basic validations pass.
         let mut mutable_cap = capability.clone();
         mutable_cap.expiry_lc = Some(kernel.local_lc + 200); // Far future expiry
-        mutable_cap.rights = u32::MAX; // Grant all rights to simplify
+        mutable_cap.rights = rights::core::ALL | rights::EXTENSION_MASK; // Grant all allocated rights to simplify
         kernel.state.capabilities.insert(mutable_cap.id, mutable_cap.clone());
 
         // command_strategy here is not used in test logic directly, but shows how it could be used if the test was refactored
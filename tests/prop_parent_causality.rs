@@ -0,0 +1,96 @@
+#![cfg(test)]
+
+//! Property tests for the parent causal bound (spec §2.1 invariant 3) and
+//! parent-chain acyclicity enforced by `Kernel::append_delta`.
+
+use std::sync::Arc;
+
+use proptest::prelude::*;
+use amulet_core::error::{Invariant, KernelError};
+use amulet_core::kernel::{Kernel, StateDelta};
+use amulet_core::primitives::{CidBytes, Entity, EntityHeader, ReplicaIdBytes, CID};
+
+fn cid(n: u8) -> CID {
+    CidBytes([n; 32])
+}
+
+fn entity(id: u8, lclock: u64, parent: Option<u8>) -> Entity<Vec<u8>> {
+    Entity {
+        header: EntityHeader { id: cid(id), version: 1, lclock, parent: parent.map(cid) },
+        body: vec![id],
+    }
+}
+
+/// Whether following `parents` from any node of the delta ever loops.
+fn has_cycle(parents: &[Option<usize>]) -> bool {
+    (0..parents.len()).any(|start| {
+        let mut current = parents[start];
+        for _ in 0..parents.len() {
+            match current {
+                Some(next) if next == start => return true,
+                Some(next) => current = parents[next],
+                None => return false,
+            }
+        }
+        false
+    })
+}
+
+proptest! {
+    /// Entities appended one event at a time, each under an already observed
+    /// parent, are always accepted and keep the bound for every entity in Σ.
+    #[test]
+    fn prop_growing_forest_respects_bound(parent_choices in prop::collection::vec(any::<prop::sample::Index>(), 1..24), roots in prop::collection::vec(any::<bool>(), 24)) {
+        let mut kernel = Kernel::new_with_default_crypto(ReplicaIdBytes([1; 16]));
+        for (i, choice) in parent_choices.iter().enumerate() {
+            let lclock = i as u64 + 1;
+            let parent = if i == 0 || roots[i] { None } else { Some(choice.index(i) as u8) };
+            let delta = StateDelta { new_entities: vec![entity(i as u8, lclock, parent)], ..Default::default() };
+            prop_assert!(kernel.append_delta(&delta, lclock).is_ok());
+        }
+        for ent in kernel.state.entities.values() {
+            if let Some(parent) = ent.header.parent {
+                prop_assert!(kernel.state.entities[&parent].header.lclock <= ent.header.lclock);
+            }
+        }
+    }
+
+    /// A child is accepted exactly when its lclock is not below its observed parent's.
+    #[test]
+    fn prop_bound_decides_acceptance(parent_lc in 0u64..1000, child_lc in 0u64..1000) {
+        let mut kernel = Kernel::new_with_default_crypto(ReplicaIdBytes([1; 16]));
+        kernel.state.entities.insert(cid(1), Arc::new(entity(1, parent_lc, None)));
+        let delta = StateDelta { new_entities: vec![entity(2, child_lc, Some(1))], ..Default::default() };
+        let result = kernel.append_delta(&delta, child_lc);
+        if parent_lc <= child_lc {
+            prop_assert!(result.is_ok());
+        } else {
+            prop_assert_eq!(result, Err(KernelError::InvariantViolation(Invariant::ParentCausalBound {
+                entity: cid(2),
+                parent: cid(1),
+                parent_lclock: parent_lc,
+                entity_lclock: child_lc,
+            })));
+        }
+    }
+
+    /// A delta whose parent links form a cycle is always refused, and an
+    /// acyclic one is always accepted; Σ is untouched on refusal.
+    #[test]
+    fn prop_cycles_rejected(links in prop::collection::vec(prop::option::of(0usize..8), 1..8)) {
+        let n = links.len();
+        let parents: Vec<Option<usize>> = links.iter().map(|link| link.map(|p| p % n)).collect();
+        let delta = StateDelta {
+            new_entities: parents.iter().enumerate().map(|(i, p)| entity(i as u8, 1, p.map(|p| p as u8))).collect(),
+            ..Default::default()
+        };
+        let mut kernel = Kernel::new_with_default_crypto(ReplicaIdBytes([1; 16]));
+        let result = kernel.append_delta(&delta, 1);
+        if has_cycle(&parents) {
+            prop_assert!(matches!(result, Err(KernelError::InvariantViolation(Invariant::ParentCycle(_)))));
+            prop_assert!(kernel.state.entities.is_empty());
+        } else {
+            prop_assert!(result.is_ok());
+        }
+    }
+}